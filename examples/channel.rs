@@ -0,0 +1,35 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+// Channel endpoints are Send but not Sync, so each thread gets its own.
+enum End {
+    Tx(Sender<usize>),
+    Rx(Receiver<usize>),
+}
+
+fn main() {
+    cobb::run_test_per_thread(cobb::PerThreadCfg::<End> {
+        cfg: cobb::TestCfg {
+            threads: 8,
+            iterations: 1000,
+            ..Default::default()
+        },
+        setup: |threads| {
+            let (tx, rx) = channel();
+            let mut ends = vec![End::Rx(rx)];
+            ends.extend((1..threads).map(|_| End::Tx(tx.clone())));
+            ends
+        },
+        test: |end, tctx| {
+            if let End::Tx(tx) = end {
+                tx.send(tctx.thread_index()).unwrap();
+            }
+        },
+        after_each: |ends, ictx| {
+            if let End::Rx(rx) = &*ends.get(0) {
+                let got = rx.try_iter().sum::<usize>();
+                assert_eq!(got, (1..ictx.thread_count()).sum::<usize>());
+            }
+        },
+        ..Default::default()
+    });
+}
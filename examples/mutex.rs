@@ -53,8 +53,8 @@ fn main() {
         before_each: |m| {
             *m.lock() = 0;
         },
-        after_each: |m| {
-            assert_eq!((0..16usize).sum::<usize>(), *m.lock());
+        after_each: |m, ictx| {
+            assert_eq!((0..ictx.thread_count()).sum::<usize>(), *m.lock());
         },
         ..Default::default()
     });
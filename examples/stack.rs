@@ -25,6 +25,11 @@ impl<T> BuggyStack<T> {
         }
     }
 }
+impl<T> Default for BuggyStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl<T> BuggyStack<T> {
     pub fn push(&self, data: T) {
         let n = Box::into_raw(Box::new(BuggyNode {
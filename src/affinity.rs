@@ -0,0 +1,398 @@
+//! Pinning runner threads to particular CPUs.
+use crate::Rng;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// When `TestCfg::repin` moves threads to new CPUs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repin {
+    EachIteration,
+    /// At each reprioritization point (see `TestCfg::reprioritize`).
+    OnReprioritize,
+}
+
+/// For `TestCfg::oversubscribe`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Oversubscribe {
+    /// How many threads to run per core.
+    pub factor: usize,
+    /// Confine the threads to this many cores, rather than all of them (or
+    /// all of the group's, with `TestCfg::partition_cores`).
+    pub cores: Option<usize>,
+}
+
+impl Default for Oversubscribe {
+    fn default() -> Self {
+        Self {
+            factor: 4,
+            cores: None,
+        }
+    }
+}
+
+/// Where `TestCfg::numa` puts things, on machines with several NUMA nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Numa {
+    /// Put each of the group's threads on a different node from the last
+    /// (going round them), rather than all on the same one.
+    pub spread: bool,
+    /// Allocate the test state on this node. This works by running `setup`
+    /// confined to the node, so it's only allocated there if `setup` writes
+    /// to it (with Linux's default first-touch policy), which it usually
+    /// does.
+    pub state_node: Option<usize>,
+}
+
+impl Default for Numa {
+    fn default() -> Self {
+        Self {
+            spread: true,
+            state_node: None,
+        }
+    }
+}
+
+/// What a thread's CPU is set to when it isn't pinned.
+pub(crate) const UNPINNED: usize = usize::MAX;
+
+/// The CPUs this process may run on, lowest first. Empty if that can't be
+/// found out here.
+pub(crate) fn allowed() -> Vec<usize> {
+    imp::allowed()
+}
+
+/// The CPUs out of `cpus` in each NUMA node that has any. Empty if that can't
+/// be found out here.
+pub(crate) fn numa_nodes(cpus: &[usize]) -> Vec<Vec<usize>> {
+    imp::numa_nodes()
+        .into_iter()
+        .map(|node| node.into_iter().filter(|c| cpus.contains(c)).collect())
+        .filter(|node: &Vec<usize>| !node.is_empty())
+        .collect()
+}
+
+/// Pins the current thread to `cpus`, warning (once) if that fails.
+pub(crate) fn pin_current(cpus: &[usize]) {
+    static WARNED: AtomicBool = AtomicBool::new(false);
+    if imp::SUPPORTED && !imp::pin_current(cpus) && !WARNED.swap(true, Ordering::Relaxed) {
+        eprintln!(
+            "cobb: failed to pin a thread to CPUs {:?}: {}",
+            cpus,
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+/// Puts the current thread's affinity back the way it was when it was made,
+/// when dropped. See `pin_current_scoped`.
+pub(crate) struct Restore(Option<imp::Saved>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        if let Some(saved) = self.0.take() {
+            imp::restore(saved);
+        }
+    }
+}
+
+/// Like `pin_current`, but only until the returned `Restore` is dropped.
+pub(crate) fn pin_current_scoped(cpus: &[usize]) -> Restore {
+    let saved = imp::current();
+    pin_current(cpus);
+    Restore(saved)
+}
+
+/// Splits `cpus` into `groups` parts that don't share any physical cores (as
+/// far as we can tell), and returns part `group`. If there are fewer cores
+/// than groups, some groups get the same core.
+pub(crate) fn partition(cpus: &[usize], groups: usize, group: usize) -> Vec<usize> {
+    // Hyperthreads of the same core go together.
+    let mut cores: Vec<(Option<String>, Vec<usize>)> = vec![];
+    for &cpu in cpus {
+        let core = imp::core_of(cpu);
+        match cores.iter_mut().find(|(c, _)| core.is_some() && *c == core) {
+            Some((_, v)) => v.push(cpu),
+            None => cores.push((core, vec![cpu])),
+        }
+    }
+    if cores.is_empty() {
+        return vec![];
+    }
+    if cores.len() <= groups {
+        return cores.swap_remove(group % cores.len()).1;
+    }
+    // The first `cores.len() % groups` groups get one extra core.
+    let (per, extra) = (cores.len() / groups, cores.len() % groups);
+    let start = group * per + group.min(extra);
+    let len = per + usize::from(group < extra);
+    cores[start..start + len]
+        .iter()
+        .flat_map(|(_, v)| v.iter().copied())
+        .collect()
+}
+
+/// Picks a random CPU out of `cpus` for each of `threads` threads. It picks a
+/// random number of distinct ones to use first, so that threads often end up
+/// sharing one, and just as often don't.
+pub(crate) fn random_assignment(cpus: &[usize], threads: usize, rng: &mut Rng) -> Vec<usize> {
+    let mut cpus = cpus.to_vec();
+    rng.shuffle(&mut cpus);
+    cpus.truncate(rng.between(1..threads.min(cpus.len()) + 1));
+    (0..threads).map(|_| cpus[rng.upto(cpus.len())]).collect()
+}
+
+#[cfg(all(target_os = "linux", not(miri)))]
+mod imp {
+    pub(super) const SUPPORTED: bool = true;
+
+    /// A `cpu_set_t`, which glibc and musl both make 1024 bits.
+    #[repr(C)]
+    pub(super) struct CpuSet([u64; 16]);
+
+    extern "C" {
+        fn sched_getaffinity(pid: i32, size: usize, set: *mut CpuSet) -> i32;
+        fn sched_setaffinity(pid: i32, size: usize, set: *const CpuSet) -> i32;
+    }
+
+    pub(super) fn allowed() -> Vec<usize> {
+        let mut set = CpuSet([0; 16]);
+        if unsafe { sched_getaffinity(0, core::mem::size_of::<CpuSet>(), &mut set) } != 0 {
+            return vec![];
+        }
+        (0..1024)
+            .filter(|&i| set.0[i / 64] & (1 << (i % 64)) != 0)
+            .collect()
+    }
+
+    /// The CPUs sharing a core with `cpu` (including it), which is as good a
+    /// name for the core as any.
+    pub(super) fn core_of(cpu: usize) -> Option<String> {
+        let path = format!(
+            "/sys/devices/system/cpu/cpu{}/topology/thread_siblings_list",
+            cpu
+        );
+        std::fs::read_to_string(path)
+            .ok()
+            .map(|s| s.trim().to_string())
+    }
+
+    pub(super) fn numa_nodes() -> Vec<Vec<usize>> {
+        let mut nodes = vec![];
+        for i in 0.. {
+            let path = format!("/sys/devices/system/node/node{}/cpulist", i);
+            match std::fs::read_to_string(path) {
+                Ok(list) => nodes.push(parse_list(list.trim())),
+                // Node numbers can have gaps, but it's rare enough not to
+                // bother with.
+                Err(_) => break,
+            }
+        }
+        nodes
+    }
+
+    /// Parses a list like "0-3,8,10-11".
+    fn parse_list(list: &str) -> Vec<usize> {
+        let mut cpus = vec![];
+        for part in list.split(',').filter(|p| !p.is_empty()) {
+            let mut ends = part.splitn(2, '-').map(|n| n.parse::<usize>());
+            match (ends.next(), ends.next()) {
+                (Some(Ok(a)), None) => cpus.push(a),
+                (Some(Ok(a)), Some(Ok(b))) => cpus.extend(a..=b),
+                _ => {}
+            }
+        }
+        cpus
+    }
+
+    pub(super) fn pin_current(cpus: &[usize]) -> bool {
+        let mut set = CpuSet([0; 16]);
+        for &cpu in cpus {
+            if cpu >= 1024 {
+                return false;
+            }
+            set.0[cpu / 64] |= 1 << (cpu % 64);
+        }
+        // pid 0 is the calling thread, not the whole process.
+        unsafe { sched_setaffinity(0, core::mem::size_of::<CpuSet>(), &set) == 0 }
+    }
+
+    pub(super) type Saved = CpuSet;
+
+    pub(super) fn current() -> Option<Saved> {
+        let mut set = CpuSet([0; 16]);
+        match unsafe { sched_getaffinity(0, core::mem::size_of::<CpuSet>(), &mut set) } {
+            0 => Some(set),
+            _ => None,
+        }
+    }
+
+    pub(super) fn restore(set: Saved) {
+        unsafe { sched_setaffinity(0, core::mem::size_of::<CpuSet>(), &set) };
+    }
+}
+
+/// On Windows, this only knows about the first processor group (so, the first
+/// 64 CPUs).
+#[cfg(all(windows, not(miri)))]
+mod imp {
+    pub(super) const SUPPORTED: bool = true;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentProcess() -> usize;
+        fn GetCurrentThread() -> usize;
+        fn GetProcessAffinityMask(process: usize, mask: *mut usize, system: *mut usize) -> i32;
+        fn SetThreadAffinityMask(thread: usize, mask: usize) -> usize;
+    }
+
+    pub(super) fn allowed() -> Vec<usize> {
+        let (mut mask, mut system) = (0, 0);
+        if unsafe { GetProcessAffinityMask(GetCurrentProcess(), &mut mask, &mut system) } == 0 {
+            return vec![];
+        }
+        (0..usize::BITS as usize)
+            .filter(|&i| mask & (1 << i) != 0)
+            .collect()
+    }
+
+    pub(super) fn core_of(_cpu: usize) -> Option<String> {
+        None
+    }
+
+    pub(super) fn numa_nodes() -> Vec<Vec<usize>> {
+        vec![]
+    }
+
+    pub(super) fn pin_current(cpus: &[usize]) -> bool {
+        let mut mask = 0usize;
+        for &cpu in cpus {
+            if cpu >= usize::BITS as usize {
+                return false;
+            }
+            mask |= 1 << cpu;
+        }
+        unsafe { SetThreadAffinityMask(GetCurrentThread(), mask) != 0 }
+    }
+
+    pub(super) type Saved = usize;
+
+    /// There's no getting a thread's mask without setting it, so this sets it
+    /// to the process's for a moment.
+    pub(super) fn current() -> Option<Saved> {
+        let (mut process, mut system) = (0, 0);
+        if unsafe { GetProcessAffinityMask(GetCurrentProcess(), &mut process, &mut system) } == 0 {
+            return None;
+        }
+        match unsafe { SetThreadAffinityMask(GetCurrentThread(), process) } {
+            0 => None,
+            mask => {
+                restore(mask);
+                Some(mask)
+            }
+        }
+    }
+
+    pub(super) fn restore(mask: Saved) {
+        unsafe { SetThreadAffinityMask(GetCurrentThread(), mask) };
+    }
+}
+
+/// macOS doesn't let threads be pinned, so this is a best effort: each CPU
+/// number becomes an affinity tag, and the kernel tries to run threads with
+/// the same tag on cores that share a cache (and ones with different tags on
+/// ones that don't). Apple silicon ignores these entirely.
+#[cfg(all(target_vendor = "apple", not(miri)))]
+mod imp {
+    pub(super) const SUPPORTED: bool = true;
+
+    const THREAD_AFFINITY_POLICY: u32 = 4;
+
+    extern "C" {
+        fn pthread_self() -> usize;
+        fn pthread_mach_thread_np(thread: usize) -> u32;
+        fn thread_policy_set(thread: u32, flavor: u32, info: *const i32, count: u32) -> i32;
+        fn thread_policy_get(
+            thread: u32,
+            flavor: u32,
+            info: *mut i32,
+            count: *mut u32,
+            get_default: *mut u32,
+        ) -> i32;
+    }
+
+    pub(super) fn allowed() -> Vec<usize> {
+        (0..std::thread::available_parallelism().map_or(1, |n| n.get())).collect()
+    }
+
+    pub(super) fn core_of(_cpu: usize) -> Option<String> {
+        None
+    }
+
+    pub(super) fn numa_nodes() -> Vec<Vec<usize>> {
+        vec![]
+    }
+
+    /// A set of CPUs gets the tag of the first one.
+    pub(super) fn pin_current(cpus: &[usize]) -> bool {
+        // Tag 0 means no affinity.
+        let tag = match cpus.first() {
+            Some(&cpu) => cpu as i32 + 1,
+            None => return false,
+        };
+        set_tag(tag)
+    }
+
+    fn set_tag(tag: i32) -> bool {
+        let port = unsafe { pthread_mach_thread_np(pthread_self()) };
+        unsafe { thread_policy_set(port, THREAD_AFFINITY_POLICY, &tag, 1) == 0 }
+    }
+
+    /// The thread's affinity tag.
+    pub(super) type Saved = i32;
+
+    pub(super) fn current() -> Option<Saved> {
+        let port = unsafe { pthread_mach_thread_np(pthread_self()) };
+        let (mut tag, mut count, mut get_default) = (0, 1, 0);
+        match unsafe {
+            thread_policy_get(
+                port,
+                THREAD_AFFINITY_POLICY,
+                &mut tag,
+                &mut count,
+                &mut get_default,
+            )
+        } {
+            0 => Some(tag),
+            _ => None,
+        }
+    }
+
+    pub(super) fn restore(tag: Saved) {
+        set_tag(tag);
+    }
+}
+
+#[cfg(not(all(any(target_os = "linux", windows, target_vendor = "apple"), not(miri))))]
+mod imp {
+    pub(super) const SUPPORTED: bool = false;
+
+    pub(super) fn allowed() -> Vec<usize> {
+        vec![]
+    }
+    pub(super) fn core_of(_cpu: usize) -> Option<String> {
+        None
+    }
+
+    pub(super) fn numa_nodes() -> Vec<Vec<usize>> {
+        vec![]
+    }
+    pub(super) fn pin_current(_cpus: &[usize]) -> bool {
+        false
+    }
+
+    pub(super) type Saved = ();
+
+    pub(super) fn current() -> Option<Saved> {
+        None
+    }
+    pub(super) fn restore(_saved: Saved) {}
+}
@@ -0,0 +1,107 @@
+//! Writing everything about a failed run to one directory, so there's a
+//! single thing to keep (or for CI to upload). See `TestCfg::artifact_dir`.
+use crate::{CobbError, TestCfg, Trace};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+/// Writes the reproducer for a run of the test `name` (with the settings
+/// `config`, from `describe`) with master seed `seed` that failed with
+/// `failures`, in a new directory under `dir`, and returns the new
+/// directory's path.
+pub(crate) fn write(
+    dir: &Path,
+    name: &str,
+    config: &str,
+    seed: u64,
+    failures: &[CobbError],
+    traces: &[Trace],
+) -> std::io::Result<PathBuf> {
+    let dir = dir.join(format!("{}-{:016x}", crate::trace::file_name(name), seed));
+    std::fs::create_dir_all(&dir)?;
+    let mut summary = String::new();
+    let _ = writeln!(summary, "test {}", name);
+    let _ = writeln!(summary, "seed {:#x}", seed);
+    let _ = writeln!(summary, "rerun with COBB_SEED={:#x}", seed);
+    let _ = writeln!(summary);
+    for t in traces {
+        let _ = writeln!(
+            summary,
+            "group {} failed in iteration {} (iteration seed {:#x}), its trace is in {}",
+            t.group,
+            t.iteration,
+            t.seed,
+            trace_file(t)
+        );
+    }
+    let _ = writeln!(summary);
+    for f in failures {
+        let _ = writeln!(summary, "{:#}", f);
+    }
+    std::fs::write(dir.join("failure.txt"), summary)?;
+    std::fs::write(dir.join("config.txt"), config)?;
+    for t in traces {
+        std::fs::write(dir.join(trace_file(t)), t.to_string())?;
+    }
+    Ok(dir)
+}
+
+fn trace_file(t: &Trace) -> String {
+    format!("group{}-iteration{}.trace", t.group, t.iteration)
+}
+
+/// The settings in `test`, one per line (leaving out the callbacks).
+pub(crate) fn describe<T>(test: &TestCfg<T>) -> String {
+    let mut out = String::new();
+    macro_rules! fields {
+        ($($field:ident),* $(,)?) => {
+            $(let _ = writeln!(out, "{} = {:?}", stringify!($field), test.$field);)*
+        };
+    }
+    fields!(
+        name,
+        threads,
+        thread_range,
+        iterations,
+        sub_iterations,
+        sub_iteration_range,
+        groups,
+        seed,
+        intensity,
+        schedule,
+        sp_weights,
+        sp_probability,
+        reprioritize,
+        reprioritize_cadence,
+        reprioritize_at_start,
+        priorities,
+        low_priority,
+        realtime,
+        inversion,
+        repin,
+        affinity,
+        partition_cores,
+        oversubscribe,
+        numa,
+        disturbers,
+        random_participation,
+        iteration_timeout,
+        max_total_time,
+        watchdog,
+        spurious_wakeups,
+        fence_chance,
+        suspend,
+        preempt,
+        freeze,
+        start_stagger,
+        start_mode,
+        start_order,
+        escalate,
+        calibrate,
+        minimize,
+        confidence,
+    );
+    let _ = writeln!(out, "phases = {}", test.phases.len());
+    let _ = writeln!(out, "owner = {}", test.owner.is_some());
+    let _ = writeln!(out, "custom scheduler = {}", test.scheduler.is_some());
+    out
+}
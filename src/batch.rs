@@ -0,0 +1,192 @@
+use crate::{extract_msg, run_test, PrioritizeMode, TestCfg};
+use std::time::{Duration, Instant};
+
+/// A type-erased cobb test, for running several of them with `run_tests`.
+pub struct TestSpec {
+    name: String,
+    run: Box<dyn Fn() + Send + Sync>,
+}
+
+impl TestSpec {
+    /// Wraps up a test config. Its name is taken from `TestCfg::name`.
+    pub fn new<T: Send + Sync + 'static>(cfg: TestCfg<T>) -> Self {
+        let name = cfg.name.unwrap_or("cobb").to_string();
+        Self::from_fn(name, move || run_test(cfg.clone()))
+    }
+    /// A test that runs an arbitrary function, which should panic on failure.
+    pub fn from_fn(name: impl Into<String>, run: impl Fn() + Send + Sync + 'static) -> Self {
+        Self {
+            name: name.into(),
+            run: Box::new(run),
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Runs the test, panicking if it fails.
+    pub fn run(&self) {
+        (self.run)()
+    }
+}
+
+impl core::fmt::Debug for TestSpec {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TestSpec")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+/// Runs each test in turn, continuing past failures, and then panics with a
+/// summary if any of them failed.
+///
+/// If the `COBB_FILTER` environment variable is set, only the tests whose
+/// names match it are run (see `run_tests_filtered`).
+pub fn run_tests(tests: &[TestSpec]) {
+    let filter = std::env::var("COBB_FILTER").ok();
+    run_tests_filtered(tests, filter.as_deref().filter(|f| !f.is_empty()));
+}
+
+/// Like `run_tests`, but only runs the tests whose names match `filter`
+/// (according to `filter_matches`), or all of them if it's `None`.
+pub fn run_tests_filtered(tests: &[TestSpec], filter: Option<&str>) {
+    let start = Instant::now();
+    let selected = tests
+        .iter()
+        .filter(|t| filter.is_none_or(|f| filter_matches(f, &t.name)))
+        .collect::<Vec<_>>();
+    let mut failed = vec![];
+    for test in &selected {
+        eprintln!("cobb: running {}", test.name);
+        let (res, time) = run_caught(|| test.run());
+        match res {
+            Ok(()) => eprintln!("cobb: {} ok ({:.2?})", test.name, time),
+            Err(msg) => {
+                eprintln!("cobb: {} FAILED ({:.2?}): {}", test.name, time, msg);
+                failed.push(test.name.as_str());
+            }
+        }
+    }
+    eprintln!(
+        "cobb: {} passed, {} failed, {} filtered out ({:.2?})",
+        selected.len() - failed.len(),
+        failed.len(),
+        tests.len() - selected.len(),
+        start.elapsed()
+    );
+    if !failed.is_empty() {
+        panic!(
+            "{} of {} cobb tests failed: {:?}",
+            failed.len(),
+            selected.len(),
+            failed
+        );
+    }
+}
+
+/// Whether a test named `name` is selected by `filter`, which is a comma
+/// separated list of patterns. Patterns containing `*` or `?` are globs that
+/// must match the whole name, others just need to be a substring of it.
+pub fn filter_matches(filter: &str, name: &str) -> bool {
+    filter.split(',').map(str::trim).any(|pat| {
+        if pat.contains(['*', '?']) {
+            glob_matches(pat.as_bytes(), name.as_bytes())
+        } else {
+            name.contains(pat)
+        }
+    })
+}
+
+fn glob_matches(pat: &[u8], s: &[u8]) -> bool {
+    // Backtracking matcher, where `*` may only backtrack to the most recent
+    // star, which is enough since it can match anything.
+    let (mut p, mut i) = (0, 0);
+    let mut star = None;
+    while i < s.len() {
+        match pat.get(p) {
+            Some(b'*') => {
+                star = Some((p, i));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == s[i] => {
+                p += 1;
+                i += 1;
+            }
+            _ => match star {
+                Some((sp, si)) => {
+                    p = sp + 1;
+                    i = si + 1;
+                    star = Some((sp, si + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pat[p..].iter().all(|&c| c == b'*')
+}
+
+/// The axes for `run_matrix`. An empty axis means "just use the value from
+/// the base config".
+#[derive(Debug, Clone, Default)]
+pub struct Matrix {
+    pub threads: Vec<usize>,
+    pub sub_iterations: Vec<usize>,
+    pub reprioritize: Vec<Option<PrioritizeMode>>,
+}
+
+/// Runs `base` once for every combination of the values in `axes`, reporting
+/// which ones passed, and then panics if any of them failed.
+pub fn run_matrix<T: Send + Sync + 'static>(base: TestCfg<T>, axes: Matrix) {
+    fn axis<V: Clone>(values: &[V], base: V) -> Vec<V> {
+        if values.is_empty() {
+            vec![base]
+        } else {
+            values.to_vec()
+        }
+    }
+    let name = base.name.unwrap_or("cobb");
+    let mut results = vec![];
+    for &threads in &axis(&axes.threads, base.threads) {
+        for &sub_iterations in &axis(&axes.sub_iterations, base.sub_iterations) {
+            for reprioritize in axis(&axes.reprioritize, base.reprioritize.clone()) {
+                let cell = format!(
+                    "threads={} sub_iterations={} reprioritize={:?}",
+                    threads, sub_iterations, reprioritize
+                );
+                eprintln!("{}: running {}", name, cell);
+                let cfg = TestCfg {
+                    threads,
+                    sub_iterations,
+                    reprioritize,
+                    ..base.clone()
+                };
+                let (res, time) = run_caught(|| run_test(cfg));
+                results.push((cell, res, time));
+            }
+        }
+    }
+    eprintln!("{}: matrix results:", name);
+    for (cell, res, time) in &results {
+        match res {
+            Ok(()) => eprintln!("  ok      {} ({:.2?})", cell, time),
+            Err(msg) => eprintln!("  FAILED  {} ({:.2?}): {}", cell, time, msg),
+        }
+    }
+    let failed = results.iter().filter(|r| r.1.is_err()).count();
+    if failed != 0 {
+        panic!(
+            "{}: {} of {} matrix cells failed",
+            name,
+            failed,
+            results.len()
+        );
+    }
+}
+
+/// Runs `f`, turning a panic into an error message.
+pub(crate) fn run_caught(f: impl FnOnce()) -> (Result<(), String>, Duration) {
+    let start = Instant::now();
+    let res =
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).map_err(|e| extract_msg(&*e));
+    (res, start.elapsed())
+}
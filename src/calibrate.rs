@@ -0,0 +1,57 @@
+//! Measuring how long the test body takes, so that delays can be sized to
+//! match. See `TestCfg::calibrate`.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// How many iterations the warmup lasts, at most.
+const WARMUP: usize = 16;
+
+#[derive(Default)]
+pub(crate) struct Calibration {
+    samples: Mutex<Vec<Duration>>,
+    /// The median body time in nanoseconds (at least 1), or 0 during warmup.
+    typical: AtomicU64,
+}
+
+impl Calibration {
+    /// The number of iterations to warm up for, out of `iterations`.
+    pub(crate) fn warmup(iterations: usize) -> usize {
+        (iterations / 10).clamp(1, WARMUP)
+    }
+
+    pub(crate) fn warming_up(&self) -> bool {
+        self.typical.load(Ordering::Relaxed) == 0
+    }
+
+    pub(crate) fn record(&self, d: Duration) {
+        self.samples
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(d);
+    }
+
+    /// Ends the warmup, returning the typical body time.
+    pub(crate) fn finish(&self) -> Duration {
+        let mut samples = self.samples.lock().unwrap_or_else(PoisonError::into_inner);
+        samples.sort_unstable();
+        let median = samples.get(samples.len() / 2).copied().unwrap_or_default();
+        let nanos = (median.as_nanos() as u64).max(1);
+        self.typical.store(nanos, Ordering::Relaxed);
+        Duration::from_nanos(nanos)
+    }
+
+    /// The typical body time, once the warmup's over.
+    pub(crate) fn typical(&self) -> Option<Duration> {
+        match self.typical.load(Ordering::Relaxed) {
+            0 => None,
+            n => Some(Duration::from_nanos(n)),
+        }
+    }
+}
+
+/// A random delay between half and twice `typical`, from the random `r`.
+pub(crate) fn relative_delay(typical: Duration, r: u64) -> Duration {
+    let frac = (r >> 40) as f64 / (1u64 << 24) as f64;
+    typical.mul_f64(0.5 + 1.5 * frac)
+}
@@ -0,0 +1,89 @@
+//! A tiny command line parser for binaries that run cobb tests, so that they
+//! can be tweaked without rebuilding or fiddling with environment variables.
+//! Flags take precedence over `COBB_CONFIG`, `COBB_SEED` and `COBB_VERBOSE`
+//! (see `TestCfg::overrides`).
+//!
+//! ```no_run
+//! let mut cfg = cobb::TestCfg::<()> {
+//!     setup: || (),
+//!     ..Default::default()
+//! };
+//! cobb::cli::parse().apply(&mut cfg);
+//! cobb::run_test(cfg);
+//! ```
+use crate::Overrides;
+
+const USAGE: &str = "\
+Options:
+    --iterations N       number of iterations to run
+    --threads N          number of threads per group
+    --sub-iterations N   number of sub-iterations per iteration
+    --groups N           number of groups to run in parallel
+    --intensity X        scale how hard threads are perturbed (default 1.0)
+    --seed S             the master seed, in hex (with 0x) or decimal
+    --verbose, -v        print more about what the driver is doing (repeat
+                         for more, up to 3)
+    --verbosity N        how much to print, from 0 to 3
+    --help               print this message";
+
+/// Parses the process's command line arguments, exiting with a usage message
+/// if they're invalid (or `--help` was passed).
+pub fn parse() -> Overrides {
+    parse_from(std::env::args().skip(1)).unwrap_or_else(|e| {
+        if e.is_empty() {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        eprintln!("error: {}\n{}", e, USAGE);
+        std::process::exit(2);
+    })
+}
+
+/// Parses `args` (which shouldn't include the program name). Returns an empty
+/// error message if `--help` was requested.
+pub fn parse_from<I>(args: I) -> Result<Overrides, String>
+where
+    I: IntoIterator,
+    I::Item: Into<String>,
+{
+    let mut out = Overrides::default();
+    let mut args = args.into_iter().map(Into::into);
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => {
+                (arg[..i].to_string(), Some(arg[i + 1..].to_string()))
+            }
+            _ => (arg, None),
+        };
+        let mut value = || -> Result<String, String> {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} needs a value", flag))
+        };
+        match flag.as_str() {
+            "--iterations" => out.iterations = Some(parse_value(&flag, value()?)?),
+            "--threads" => out.threads = Some(parse_value(&flag, value()?)?),
+            "--sub-iterations" => out.sub_iterations = Some(parse_value(&flag, value()?)?),
+            "--groups" => out.groups = Some(parse_value(&flag, value()?)?),
+            "--intensity" => out.intensity = Some(parse_value(&flag, value()?)?),
+            "--seed" => out.seed = Some(crate::config::parse_seed(&value()?)?),
+            "--verbose" | "-v" => {
+                out.verbosity = Some(out.verbosity.unwrap_or(0).saturating_add(1).min(3))
+            }
+            "--verbosity" => out.verbosity = Some(parse_value(&flag, value()?)?),
+            "--help" | "-h" => return Err(String::new()),
+            _ => return Err(format!("unknown argument {:?}", flag)),
+        }
+    }
+    Ok(out)
+}
+
+fn parse_value<V>(flag: &str, v: String) -> Result<V, String>
+where
+    V: core::str::FromStr,
+    V::Err: core::fmt::Display,
+{
+    v.parse()
+        .map_err(|e| format!("bad value {:?} for {}: {}", v, flag, e))
+}
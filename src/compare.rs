@@ -0,0 +1,100 @@
+//! Running two versions of a test the same way, to see where they differ.
+//! See `run_compare`.
+use crate::error::outcome_key;
+use crate::{derive_seed, Hooks, Replay, Rng, Stream, TestCfg};
+
+/// What `run_compare` found.
+#[derive(Debug, Clone, Default)]
+pub struct Comparison {
+    /// The master seed both sides were run with.
+    pub seed: u64,
+    pub iterations: usize,
+    /// The iterations where the two sides' outcomes differed.
+    pub divergences: Vec<Divergence>,
+}
+
+/// An iteration where the two sides of `run_compare` went differently.
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    pub iteration: usize,
+    /// The iteration's seed, for `run_single_iteration`.
+    pub seed: u64,
+    /// How each side did: `None` if it passed, and otherwise what went wrong.
+    pub a: Option<String>,
+    pub b: Option<String>,
+}
+
+/// Runs two versions of a test (say, a fix and the code it fixes, or a new
+/// lock-free structure and a reference one that uses a lock) side by side,
+/// with the same master seed, so each iteration gets the same seed, start
+/// order, participants and schedule point decisions on both sides, and
+/// reports the iterations that passed on one side and failed on the other,
+/// or failed differently on each. Failures count as the same if their
+/// messages are (give or take any numbers in them), wherever they happened,
+/// since the two sides are usually different code.
+///
+/// The seed and number of iterations come from `a`, and there's a single
+/// group. The two configs should otherwise agree on everything to do with
+/// scheduling (threads, schedule mode and so on), or there's not much point.
+/// Schedule points line up best with a cooperative `TestCfg::schedule`, since
+/// otherwise the OS still runs the threads however it likes. Each iteration is
+/// run on its own (as with `run_single_iteration`), so this is a lot slower
+/// per iteration than `run_test`.
+pub fn run_compare<A, B>(mut a: TestCfg<A>, mut b: TestCfg<B>) -> Comparison
+where
+    A: Send + Sync + 'static,
+    B: Send + Sync + 'static,
+{
+    let name = a.name.unwrap_or("cobb");
+    // For `COBB_SEED`. If the rest is broken, each run says so.
+    let _ = crate::config::apply_env_config(&mut a);
+    let seed = a.seed.unwrap_or_else(|| Rng::new().gen());
+    let iterations = a.iterations;
+    eprintln!("{}: comparing with seed {:#x}", name, seed);
+    let (hooks_a, hooks_b) = (Hooks::from_cfg(&a), Hooks::from_cfg(&b));
+    a.seed = Some(seed);
+    a.iterations = 1;
+    a.groups = 1;
+    b.seed = Some(seed);
+    b.iterations = 1;
+    b.groups = 1;
+    let mut out = Comparison {
+        seed,
+        iterations,
+        divergences: vec![],
+    };
+    for iteration in 0..iterations {
+        let iter_seed = derive_seed(seed, Stream::Iteration, 0, iteration);
+        let replay = || Replay {
+            seed: iter_seed,
+            quiet: true,
+            ..Default::default()
+        };
+        let ra = crate::try_run_with_hooks(a.clone(), hooks_a.clone(), Some(replay())).err();
+        let rb = crate::try_run_with_hooks(b.clone(), hooks_b.clone(), Some(replay())).err();
+        if ra.as_ref().map(outcome_key) != rb.as_ref().map(outcome_key) {
+            let (ra, rb) = (ra.map(|e| e.to_string()), rb.map(|e| e.to_string()));
+            eprintln!(
+                "{}: iteration {} (iteration seed {:#x}) went differently: {} vs {}",
+                name,
+                iteration,
+                iter_seed,
+                ra.as_deref().unwrap_or("passed"),
+                rb.as_deref().unwrap_or("passed")
+            );
+            out.divergences.push(Divergence {
+                iteration,
+                seed: iter_seed,
+                a: ra,
+                b: rb,
+            });
+        }
+    }
+    eprintln!(
+        "{}: the two sides went differently in {} of {} iterations",
+        name,
+        out.divergences.len(),
+        iterations
+    );
+    out
+}
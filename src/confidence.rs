@@ -0,0 +1,55 @@
+//! Running long enough to be fairly sure a test doesn't fail. See
+//! `TestCfg::confidence`.
+
+/// How sure `TestCfg::confidence` should be that the test doesn't fail.
+///
+/// If each iteration independently fails with probability `p`, then the
+/// chance of `n` iterations all passing is `(1 - p)^n`. So once that's below
+/// `1 - level` for `p = failure_rate`, we can say (with that confidence) that
+/// the real failure rate is lower. Iterations aren't really independent, and
+/// a bug that needs a rare timing might not show up on this machine at all,
+/// so this is guidance for how long to run, rather than proof.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Confidence {
+    /// The per-iteration failure rate to rule out.
+    pub failure_rate: f64,
+    /// How sure to be of that, between 0 and 1.
+    pub level: f64,
+}
+
+impl Default for Confidence {
+    fn default() -> Self {
+        Self {
+            failure_rate: 1e-4,
+            level: 0.99,
+        }
+    }
+}
+
+impl Confidence {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let open = |x: f64| x > 0.0 && x < 1.0;
+        if !open(self.failure_rate) || !open(self.level) {
+            return Err(format!(
+                "confidence needs a failure_rate and level strictly between 0 and 1, got {:?}",
+                self
+            ));
+        }
+        Ok(())
+    }
+
+    /// How many iterations have to pass in a row.
+    pub fn iterations(&self) -> usize {
+        let n = (1.0 - self.level).ln() / (1.0 - self.failure_rate).ln();
+        n.ceil().min(usize::MAX as f64) as usize
+    }
+
+    /// The lowest failure rate that `passed` iterations in a row rules out,
+    /// at this level.
+    pub fn bound(&self, passed: usize) -> f64 {
+        if passed == 0 {
+            return 1.0;
+        }
+        1.0 - (1.0 - self.level).powf(1.0 / passed as f64)
+    }
+}
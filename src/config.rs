@@ -0,0 +1,219 @@
+use crate::TestCfg;
+
+/// Run parameters that override the ones in a `TestCfg`, so that stress
+/// levels can be tuned without touching the test source.
+///
+/// With the `serde` feature, these are loaded from the TOML or JSON file named
+/// by the `COBB_CONFIG` environment variable (if set) whenever a test is run.
+/// Besides the top-level keys, the file may have a `tests` table with
+/// per-test overrides, keyed by `TestCfg::name`:
+///
+/// ```toml
+/// iterations = 100000
+/// groups = 4
+///
+/// [tests.stack]
+/// threads = 32
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Overrides {
+    pub iterations: Option<usize>,
+    pub threads: Option<usize>,
+    pub sub_iterations: Option<usize>,
+    pub groups: Option<usize>,
+    pub verbosity: Option<u8>,
+    pub intensity: Option<f32>,
+    /// In a file, either an integer or a string in the form `parse_seed`
+    /// takes. Half of all seeds are too big for a TOML integer, so write it
+    /// the way cobb prints it, as a string like `"0xc200d4a2695c5326"`.
+    #[cfg_attr(feature = "serde", serde(with = "seed_serde"))]
+    pub seed: Option<u64>,
+}
+
+impl Overrides {
+    /// Replaces the values in `cfg` with any that are set here, and keeps
+    /// them in `TestCfg::overrides`, so that they also take precedence over
+    /// `COBB_CONFIG` and the environment variables when the test runs.
+    pub fn apply<T>(&self, cfg: &mut TestCfg<T>) {
+        self.set(cfg);
+        cfg.overrides = cfg.overrides.merge(self);
+    }
+
+    fn set<T>(&self, cfg: &mut TestCfg<T>) {
+        if let Some(n) = self.iterations {
+            cfg.iterations = n;
+        }
+        if let Some(n) = self.threads {
+            cfg.threads = n;
+            cfg.thread_range = None;
+        }
+        if let Some(n) = self.sub_iterations {
+            cfg.sub_iterations = n;
+            cfg.sub_iteration_range = None;
+        }
+        if let Some(n) = self.groups {
+            cfg.groups = n;
+        }
+        if let Some(v) = self.verbosity {
+            cfg.verbosity = v;
+        }
+        if let Some(v) = self.intensity {
+            cfg.intensity = v;
+        }
+        if let Some(s) = self.seed {
+            cfg.seed = Some(s);
+        }
+    }
+
+    /// Values set in `other` take precedence over the ones in `self`.
+    pub fn merge(&self, other: &Overrides) -> Overrides {
+        Overrides {
+            iterations: other.iterations.or(self.iterations),
+            threads: other.threads.or(self.threads),
+            sub_iterations: other.sub_iterations.or(self.sub_iterations),
+            groups: other.groups.or(self.groups),
+            verbosity: other.verbosity.or(self.verbosity),
+            intensity: other.intensity.or(self.intensity),
+            seed: other.seed.or(self.seed),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+struct ConfigFile {
+    #[serde(flatten)]
+    all: Overrides,
+    tests: std::collections::BTreeMap<String, Overrides>,
+}
+
+#[cfg(feature = "serde")]
+impl Overrides {
+    /// Loads the overrides for the test named `name` from a TOML or JSON file
+    /// (picked by the extension, defaulting to TOML).
+    pub fn from_file(
+        path: impl AsRef<std::path::Path>,
+        name: Option<&str>,
+    ) -> std::io::Result<Overrides> {
+        use std::io::{Error, ErrorKind};
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let file: ConfigFile = if path.extension() == Some("json".as_ref()) {
+            serde_json::from_str(&text).map_err(|e| Error::new(ErrorKind::InvalidData, e))?
+        } else {
+            toml::from_str(&text).map_err(|e| Error::new(ErrorKind::InvalidData, e))?
+        };
+        let test = name.and_then(|n| file.tests.get(n)).cloned();
+        Ok(file.all.merge(&test.unwrap_or_default()))
+    }
+}
+
+/// Writes seeds as hex strings, and reads them from strings or integers.
+#[cfg(feature = "serde")]
+mod seed_serde {
+    use serde::de::{Deserializer, Error, Unexpected, Visitor};
+    use serde::Serializer;
+
+    pub(super) fn serialize<S: Serializer>(seed: &Option<u64>, s: S) -> Result<S::Ok, S::Error> {
+        match seed {
+            Some(seed) => s.serialize_some(&format!("{:#x}", seed)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u64>, D::Error> {
+        struct SeedVisitor;
+        impl<'de> Visitor<'de> for SeedVisitor {
+            type Value = Option<u64>;
+            fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                f.write_str("a seed, as an integer or a string like \"0x2a\"")
+            }
+            fn visit_u64<E: Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(Some(v))
+            }
+            fn visit_i64<E: Error>(self, v: i64) -> Result<Self::Value, E> {
+                match v {
+                    0.. => Ok(Some(v as u64)),
+                    _ => Err(E::invalid_value(Unexpected::Signed(v), &self)),
+                }
+            }
+            fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
+                super::parse_seed(v).map(Some).map_err(E::custom)
+            }
+            fn visit_unit<E: Error>(self) -> Result<Self::Value, E> {
+                Ok(None)
+            }
+            fn visit_none<E: Error>(self) -> Result<Self::Value, E> {
+                Ok(None)
+            }
+            fn visit_some<D: Deserializer<'de>>(self, d: D) -> Result<Self::Value, D::Error> {
+                d.deserialize_any(self)
+            }
+        }
+        d.deserialize_any(SeedVisitor)
+    }
+}
+
+/// Parses a seed, in hex (with `0x`) or decimal.
+pub(crate) fn parse_seed(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("invalid seed {:?}: {}", s, e))
+}
+
+/// Applies the file named by `COBB_CONFIG`, the seed in `COBB_SEED`, the
+/// level in `COBB_VERBOSE`, the output in `COBB_OUTPUT`, and the directories
+/// in `COBB_TRACE_DIR`, `COBB_CORPUS_DIR`, `COBB_ARTIFACT_DIR`,
+/// `COBB_JUNIT_DIR` and `COBB_HTML_DIR`, if any. `TestCfg::overrides` are
+/// put back on top afterwards.
+pub(crate) fn apply_env_config<T>(cfg: &mut TestCfg<T>) -> Result<(), String> {
+    #[cfg(feature = "serde")]
+    if let Some(path) = std::env::var_os("COBB_CONFIG").filter(|p| !p.is_empty()) {
+        Overrides::from_file(&path, cfg.name)
+            .map_err(|e| format!("failed to load COBB_CONFIG {:?}: {}", path, e))?
+            .set(cfg);
+    }
+    if let Some(seed) = std::env::var("COBB_SEED").ok().filter(|s| !s.is_empty()) {
+        cfg.seed = Some(parse_seed(&seed).map_err(|e| format!("COBB_SEED: {}", e))?);
+    }
+    if let Some(v) = std::env::var("COBB_VERBOSE").ok().filter(|s| !s.is_empty()) {
+        cfg.verbosity = v
+            .trim()
+            .parse()
+            .map_err(|e| format!("COBB_VERBOSE: invalid level {:?}: {}", v, e))?;
+    }
+    if let (None, Some(out)) = (
+        &cfg.events,
+        std::env::var("COBB_OUTPUT").ok().filter(|s| !s.is_empty()),
+    ) {
+        cfg.events = crate::EventSink::parse(&out).map_err(|e| format!("COBB_OUTPUT: {}", e))?;
+    }
+    let dir = |var| {
+        std::env::var_os(var)
+            .filter(|d| !d.is_empty())
+            .map(Into::into)
+    };
+    if cfg.trace_dir.is_none() {
+        cfg.trace_dir = dir("COBB_TRACE_DIR");
+    }
+    if cfg.corpus_dir.is_none() {
+        cfg.corpus_dir = dir("COBB_CORPUS_DIR");
+    }
+    if cfg.artifact_dir.is_none() {
+        cfg.artifact_dir = dir("COBB_ARTIFACT_DIR");
+    }
+    if cfg.junit_dir.is_none() {
+        cfg.junit_dir = dir("COBB_JUNIT_DIR");
+    }
+    if cfg.html_dir.is_none() {
+        cfg.html_dir = dir("COBB_HTML_DIR");
+    }
+    cfg.overrides.clone().set(cfg);
+    Ok(())
+}
@@ -0,0 +1,411 @@
+//! The cooperative scheduler, which only lets one runner thread run at a
+//! time, and picks which one runs next at each schedule point, either using an
+//! RNG seeded for the iteration, systematically, guided by which
+//! interleavings have been seen before, or as a fuzzer's input says. As long
+//! as the test's threads only block on each other at schedule points and
+//! cobb's barriers, the interleaving (and so the outcome) of an iteration is
+//! determined entirely by those choices.
+use crate::{Rng, Scheduler, SpInfo};
+use std::collections::HashSet;
+use std::panic::Location;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+
+struct State {
+    /// The thread allowed to run, if any.
+    current: Option<usize>,
+    /// Participating threads that haven't finished the iteration, and aren't
+    /// blocked in cobb.
+    runnable: Vec<bool>,
+    /// Threads blocked in cobb (at a barrier).
+    blocked: Vec<bool>,
+    decider: Decider,
+}
+
+enum Decider {
+    Random(Rng),
+    Systematic(Explorer),
+    Guided(Guide),
+    Fuzzed(Arc<crate::fuzz::Input>),
+}
+
+impl State {
+    fn pick_next(&mut self) {
+        let runnable = (0..self.runnable.len())
+            .filter(|&t| self.runnable[t])
+            .collect::<Vec<_>>();
+        let prev = self.current.filter(|&t| self.runnable[t]);
+        self.decide(prev, runnable);
+    }
+
+    /// Picks a thread other than `me`, which can't go on yet. Switching away
+    /// from it doesn't count as a preemption. If there's nobody else, `me`
+    /// keeps going.
+    fn pick_other(&mut self, me: usize) {
+        let runnable = (0..self.runnable.len())
+            .filter(|&t| t != me && self.runnable[t])
+            .collect::<Vec<_>>();
+        if runnable.is_empty() {
+            self.current = Some(me);
+        } else {
+            self.decide(None, runnable);
+        }
+    }
+
+    fn decide(&mut self, prev: Option<usize>, runnable: Vec<usize>) {
+        self.current = match &mut self.decider {
+            Decider::Random(rng) => rng.choose(&runnable).copied(),
+            Decider::Systematic(e) => e.choose(prev, runnable),
+            Decider::Guided(g) => g.choose(&runnable),
+            Decider::Fuzzed(input) => {
+                (!runnable.is_empty()).then(|| runnable[input.choose(runnable.len())])
+            }
+        };
+    }
+}
+
+/// Coverage guided scheduling. Each iteration's sequence of schedule points
+/// (which thread hit which `sp` call) is hashed, and the choices that led to
+/// ones we haven't seen before are kept. Most iterations then replay a prefix
+/// of one of those and choose randomly from there, which finds its way to
+/// rare interleavings much faster than choosing randomly from the start.
+pub(crate) struct Guide {
+    rng: Rng,
+    seen: HashSet<u64>,
+    /// Choices that led to new interleavings.
+    corpus: Vec<Vec<usize>>,
+    /// The choices to replay this iteration, and the ones actually made.
+    plan: Vec<usize>,
+    choices: Vec<usize>,
+    hash: u64,
+    iterations: usize,
+}
+
+/// Kept choice sequences beyond this replace old ones at random.
+const MAX_CORPUS: usize = 1000;
+
+impl Guide {
+    fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::with_seed(seed),
+            seen: HashSet::new(),
+            corpus: vec![],
+            plan: vec![],
+            choices: vec![],
+            hash: 0,
+            iterations: 0,
+        }
+    }
+
+    fn choose(&mut self, options: &[usize]) -> Option<usize> {
+        if options.is_empty() {
+            return None;
+        }
+        let i = match self.plan.get(self.choices.len()) {
+            Some(&i) => i % options.len(),
+            None => self.rng.upto(options.len()),
+        };
+        self.choices.push(i);
+        Some(options[i])
+    }
+
+    fn observe(&mut self, thread: usize, site: &'static Location<'static>) {
+        let site = site as *const Location<'_> as usize as u64;
+        self.hash = (self.hash.rotate_left(5) ^ site ^ (thread as u64) << 48)
+            .wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+
+    fn next_iteration(&mut self) {
+        if self.iterations != 0 && self.seen.insert(self.hash) {
+            let choices = core::mem::take(&mut self.choices);
+            if self.corpus.len() < MAX_CORPUS {
+                self.corpus.push(choices);
+            } else {
+                let i = self.rng.upto(MAX_CORPUS);
+                self.corpus[i] = choices;
+            }
+        }
+        self.iterations += 1;
+        self.choices.clear();
+        self.hash = 0;
+        self.plan.clear();
+        // Sometimes start from scratch, so we're not stuck with what we found
+        // early on.
+        if !self.corpus.is_empty() && !self.rng.chance(0.2) {
+            let base = &self.corpus[self.rng.upto(self.corpus.len())];
+            let keep = self.rng.upto(base.len() + 1);
+            self.plan.extend_from_slice(&base[..keep]);
+        }
+    }
+
+    fn report(&self) -> String {
+        format!(
+            "saw {} distinct interleavings in {} iterations",
+            self.seen.len(),
+            self.iterations
+        )
+    }
+}
+
+/// Enumerates schedules depth first, with iterative context bounding: first
+/// all the schedules without preemptions (switching away from a thread that
+/// could have kept going), then all of them with up to one, and so on.
+pub(crate) struct Explorer {
+    max_preemptions: usize,
+    bound: usize,
+    /// The choices made in the current schedule so far, and the ones to replay
+    /// from the last one.
+    stack: Vec<Choice>,
+    pos: usize,
+    preemptions: usize,
+    started: bool,
+    schedules: usize,
+    /// The highest bound we've explored every schedule for.
+    complete: Option<usize>,
+}
+
+struct Choice {
+    /// The threads that could go next, with the one that doesn't preempt
+    /// anything (if any) first.
+    options: Vec<usize>,
+    taken: usize,
+}
+
+impl Explorer {
+    fn new(max_preemptions: usize) -> Self {
+        Self {
+            max_preemptions,
+            bound: 0,
+            stack: vec![],
+            pos: 0,
+            preemptions: 0,
+            started: false,
+            schedules: 0,
+            complete: None,
+        }
+    }
+
+    fn choose(&mut self, prev: Option<usize>, mut options: Vec<usize>) -> Option<usize> {
+        if options.is_empty() {
+            return None;
+        }
+        if let Some(p) = prev {
+            options.retain(|&t| t != p);
+            options.insert(0, p);
+            if self.preemptions >= self.bound {
+                options.truncate(1);
+            }
+        }
+        // If the test didn't behave the same as last time, we can't replay the
+        // rest, so start exploring from here.
+        if self
+            .stack
+            .get(self.pos)
+            .is_some_and(|c| c.options != options)
+        {
+            self.stack.truncate(self.pos);
+        }
+        if self.pos == self.stack.len() {
+            self.stack.push(Choice { options, taken: 0 });
+        }
+        let c = &self.stack[self.pos];
+        self.pos += 1;
+        let t = c.options[c.taken];
+        if prev.is_some_and(|p| p != t) {
+            self.preemptions += 1;
+        }
+        Some(t)
+    }
+
+    /// Sets up the next schedule to run, returning false once they've all been
+    /// run.
+    fn next_schedule(&mut self) -> bool {
+        if self.started {
+            self.stack.truncate(self.pos);
+            while let Some(top) = self.stack.last_mut() {
+                if top.taken + 1 < top.options.len() {
+                    top.taken += 1;
+                    break;
+                }
+                self.stack.pop();
+            }
+            if self.stack.is_empty() {
+                self.complete = Some(self.bound);
+                if self.bound >= self.max_preemptions {
+                    return false;
+                }
+                self.bound += 1;
+            }
+        }
+        self.started = true;
+        self.schedules += 1;
+        self.pos = 0;
+        self.preemptions = 0;
+        true
+    }
+
+    fn report(&self) -> String {
+        let mut s = format!("ran {} schedules", self.schedules);
+        if let Some(b) = self.complete {
+            s += &format!(", all of the ones with up to {} preemptions", b);
+        }
+        if self.complete != Some(self.bound) {
+            // Estimate how far through the tree we got, assuming it's balanced.
+            let (mut done, mut scale) = (0.0, 1.0);
+            for c in &self.stack {
+                done += scale * c.taken as f64 / c.options.len() as f64;
+                scale /= c.options.len() as f64;
+            }
+            s += &format!(
+                ", and about {:.1}% of the ones with {} preemptions",
+                done * 100.0,
+                self.bound
+            );
+        }
+        s
+    }
+}
+
+pub(crate) struct CoopShared {
+    state: Mutex<State>,
+    cv: Condvar,
+}
+
+impl CoopShared {
+    pub(crate) fn new(threads: usize, mode: crate::ScheduleMode, seed: u64) -> Self {
+        Self {
+            state: Mutex::new(State {
+                current: None,
+                runnable: vec![false; threads],
+                blocked: vec![false; threads],
+                decider: match mode {
+                    crate::ScheduleMode::Systematic { max_preemptions } => {
+                        Decider::Systematic(Explorer::new(max_preemptions))
+                    }
+                    crate::ScheduleMode::CoverageGuided => Decider::Guided(Guide::new(seed)),
+                    _ => Decider::Random(Rng::with_seed(seed)),
+                },
+            }),
+            cv: Condvar::new(),
+        }
+    }
+
+    /// A scheduler whose every choice comes from `input`, for `fuzz::run`.
+    pub(crate) fn fuzzed(threads: usize, input: Arc<crate::fuzz::Input>) -> Self {
+        Self {
+            state: Mutex::new(State {
+                current: None,
+                runnable: vec![false; threads],
+                blocked: vec![false; threads],
+                decider: Decider::Fuzzed(input),
+            }),
+            cv: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Only call between iterations. Returns false if there's nothing left to
+    /// explore.
+    pub(crate) fn new_iteration(&self, seed: u64, participating: impl Fn(usize) -> bool) -> bool {
+        let mut s = self.lock();
+        match &mut s.decider {
+            Decider::Random(rng) => *rng = Rng::with_seed(seed),
+            Decider::Systematic(e) => {
+                if !e.next_schedule() {
+                    return false;
+                }
+            }
+            Decider::Guided(g) => g.next_iteration(),
+            Decider::Fuzzed(_) => {}
+        }
+        for t in 0..s.runnable.len() {
+            s.runnable[t] = participating(t);
+            s.blocked[t] = false;
+        }
+        s.current = None;
+        s.pick_next();
+        true
+    }
+
+    /// How much of the schedule space has been explored, if that's what we're
+    /// doing.
+    pub(crate) fn report(&self) -> Option<String> {
+        match &self.lock().decider {
+            Decider::Systematic(e) => Some(e.report()),
+            Decider::Guided(g) => Some(g.report()),
+            Decider::Random(_) | Decider::Fuzzed(_) => None,
+        }
+    }
+
+    /// Lets whoever's turn it is know, and waits for it to be ours.
+    fn wait_turn(&self, s: MutexGuard<'_, State>, me: usize) {
+        self.cv.notify_all();
+        let _s = self
+            .cv
+            .wait_while(s, |s| s.current != Some(me))
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+}
+
+pub(crate) struct CoopScheduler {
+    shared: Arc<CoopShared>,
+    thread: usize,
+}
+
+impl CoopScheduler {
+    pub(crate) fn new(shared: Arc<CoopShared>, thread: usize) -> Self {
+        Self { shared, thread }
+    }
+}
+
+impl Scheduler for CoopScheduler {
+    fn begin_iteration(&mut self, _iteration: usize) {
+        let s = self.shared.lock();
+        self.shared.wait_turn(s, self.thread);
+    }
+    fn end_iteration(&mut self) {
+        let mut s = self.shared.lock();
+        s.runnable[self.thread] = false;
+        if s.current == Some(self.thread) {
+            s.pick_next();
+            self.shared.cv.notify_all();
+        }
+    }
+    fn perturb(&mut self, sp: &SpInfo<'_>) {
+        let mut s = self.shared.lock();
+        if let Decider::Guided(g) = &mut s.decider {
+            g.observe(self.thread, sp.location());
+        }
+        s.pick_next();
+        if s.current != Some(self.thread) {
+            sp.note_yield();
+        }
+        self.shared.wait_turn(s, self.thread);
+    }
+    fn before_block(&mut self) {
+        let mut s = self.shared.lock();
+        s.runnable[self.thread] = false;
+        s.blocked[self.thread] = true;
+        s.pick_next();
+        self.shared.cv.notify_all();
+    }
+    fn contended(&mut self) -> bool {
+        let mut s = self.shared.lock();
+        s.pick_other(self.thread);
+        self.shared.wait_turn(s, self.thread);
+        true
+    }
+    fn after_block(&mut self) {
+        let mut s = self.shared.lock();
+        s.runnable[self.thread] = true;
+        s.blocked[self.thread] = false;
+        // Wait until everyone released with us is back, so that who goes next
+        // doesn't depend on the order they woke up in.
+        if s.current.is_none() && !s.blocked.contains(&true) {
+            s.pick_next();
+        }
+        self.shared.wait_turn(s, self.thread);
+    }
+}
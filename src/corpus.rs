@@ -0,0 +1,43 @@
+//! Keeping the traces of past failures around, to replay before each run.
+//! See `TestCfg::corpus_dir`.
+use crate::Trace;
+use std::path::{Path, PathBuf};
+
+/// The directory a test's entries go in.
+fn test_dir(dir: &Path, test: &str) -> PathBuf {
+    dir.join(crate::trace::file_name(test))
+}
+
+/// Adds `trace` to the corpus in `dir`, returning the path of the new entry.
+pub(crate) fn save(dir: &Path, trace: &Trace) -> std::io::Result<PathBuf> {
+    let dir = test_dir(dir, &trace.test);
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{:016x}.trace", trace.seed));
+    std::fs::write(&path, trace.to_string())?;
+    Ok(path)
+}
+
+/// The entries for `test` in the corpus in `dir`, oldest first. Ones that
+/// can't be read are skipped, with a warning.
+pub(crate) fn entries(dir: &Path, test: &str) -> Vec<(PathBuf, Trace)> {
+    let Ok(read) = std::fs::read_dir(test_dir(dir, test)) else {
+        return vec![];
+    };
+    let mut found = read
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| p.extension() == Some("trace".as_ref()))
+        .map(|p| (std::fs::metadata(&p).and_then(|m| m.modified()).ok(), p))
+        .collect::<Vec<_>>();
+    found.sort();
+    found
+        .into_iter()
+        .filter_map(|(_, p)| match Trace::load(&p) {
+            Ok(t) => Some((p, t)),
+            Err(e) => {
+                eprintln!("cobb: skipping corpus entry {}: {}", p.display(), e);
+                None
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,64 @@
+//! Counts of how often each `TestCtx::sp` call site was hit, and how often the
+//! scheduler actually got out of the way there, for `TestCfg::sp_report`.
+use crate::SpHint;
+use std::collections::HashMap;
+use std::panic::Location;
+use std::sync::{Mutex, PoisonError};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SiteStats {
+    pub(crate) hits: u64,
+    /// How many of the hits yielded, slept, or waited for another thread.
+    pub(crate) yields: u64,
+    pub(crate) hint: SpHint,
+}
+
+pub(crate) type SiteCounts = HashMap<&'static Location<'static>, SiteStats>;
+
+/// The counts for the whole run. Runner threads count locally, and add theirs
+/// in here when they exit.
+#[derive(Default)]
+pub(crate) struct Sites {
+    counts: Mutex<SiteCounts>,
+}
+
+impl Sites {
+    pub(crate) fn merge(&self, local: &SiteCounts) {
+        let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        for (&loc, s) in local {
+            let c = counts.entry(loc).or_default();
+            c.hits += s.hits;
+            c.yields += s.yields;
+            c.hint = s.hint;
+        }
+    }
+
+    /// The counts for each site, in source order.
+    pub(crate) fn sorted(&self) -> Vec<(&'static Location<'static>, SiteStats)> {
+        let counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut sites = counts.iter().map(|(&l, &s)| (l, s)).collect::<Vec<_>>();
+        sites.sort_by_key(|(loc, _)| (loc.file(), loc.line(), loc.column()));
+        sites
+    }
+
+    pub(crate) fn report(&self) -> String {
+        let sites = self.sorted();
+        if sites.is_empty() {
+            return "no schedule points were hit".to_string();
+        }
+        let mut s = "schedule points:".to_string();
+        for (loc, c) in sites {
+            s += &format!(
+                "\n  {}: hit {} times, yielded {} ({:.1}%)",
+                loc,
+                c.hits,
+                c.yields,
+                c.yields as f64 * 100.0 / c.hits as f64
+            );
+            if c.hint != SpHint::None {
+                s += &format!(" [{:?}]", c.hint);
+            }
+        }
+        s
+    }
+}
@@ -0,0 +1,89 @@
+//! How much CPU time the current thread has used, for the per-thread numbers
+//! in `RunReport`.
+use std::time::Duration;
+
+/// The CPU time the current thread has used so far, or `None` where we
+/// can't tell.
+pub(crate) fn current() -> Option<Duration> {
+    imp::current()
+}
+
+#[cfg(all(any(target_os = "linux", target_vendor = "apple"), not(miri)))]
+mod imp {
+    use std::os::raw::c_long;
+    use std::time::Duration;
+
+    #[cfg(target_os = "linux")]
+    const CLOCK_THREAD_CPUTIME_ID: i32 = 3;
+    #[cfg(target_vendor = "apple")]
+    const CLOCK_THREAD_CPUTIME_ID: i32 = 16;
+
+    #[repr(C)]
+    struct Timespec {
+        sec: c_long,
+        nsec: c_long,
+    }
+
+    extern "C" {
+        fn clock_gettime(clock: i32, tp: *mut Timespec) -> i32;
+    }
+
+    pub(super) fn current() -> Option<Duration> {
+        let mut ts = Timespec { sec: 0, nsec: 0 };
+        match unsafe { clock_gettime(CLOCK_THREAD_CPUTIME_ID, &mut ts) } {
+            0 => Some(Duration::new(ts.sec as u64, ts.nsec as u32)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(all(windows, not(miri)))]
+mod imp {
+    use std::time::Duration;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct FileTime {
+        low: u32,
+        high: u32,
+    }
+
+    impl FileTime {
+        /// In 100ns units.
+        fn ticks(&self) -> u64 {
+            (u64::from(self.high) << 32) | u64::from(self.low)
+        }
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentThread() -> usize;
+        fn GetThreadTimes(
+            thread: usize,
+            creation: *mut FileTime,
+            exit: *mut FileTime,
+            kernel: *mut FileTime,
+            user: *mut FileTime,
+        ) -> i32;
+    }
+
+    /// This only moves on at each clock tick (around 15ms by default), so
+    /// it's rough for short runs.
+    pub(super) fn current() -> Option<Duration> {
+        let mut times: [FileTime; 4] = Default::default();
+        let [creation, exit, kernel, user] = &mut times;
+        match unsafe { GetThreadTimes(GetCurrentThread(), creation, exit, kernel, user) } {
+            0 => None,
+            _ => Some(Duration::from_nanos((kernel.ticks() + user.ticks()) * 100)),
+        }
+    }
+}
+
+#[cfg(not(all(any(target_os = "linux", windows, target_vendor = "apple"), not(miri))))]
+mod imp {
+    use std::time::Duration;
+
+    pub(super) fn current() -> Option<Duration> {
+        None
+    }
+}
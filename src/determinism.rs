@@ -0,0 +1,133 @@
+//! Checking that cobb makes the same decisions every time it's given the same
+//! seed. See `check_determinism`.
+use crate::{derive_seed, Hooks, Replay, Rng, Stream, TestCfg, Trace, TraceEvent};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Runs each of `test`'s iterations twice, with the same seed, and checks
+/// that cobb made the same decisions both times: the start order,
+/// priorities, benched threads, and what happened at each schedule point.
+/// Seeds, `run_single_iteration` and `run_test_replay` are only as good as
+/// that, so this is for catching cobb (or a custom `TestCfg::scheduler`)
+/// bringing in randomness from somewhere else. Panics, listing what differed,
+/// if anything did.
+///
+/// Only the decisions are compared, not the test's results. When a thread
+/// goes a different way through the test the second time (which the OS's
+/// scheduling can easily cause), its schedule points after that can't be
+/// compared, and this says how often that happened. There's a single group,
+/// and each iteration is run on its own, so use a handful of iterations
+/// rather than the usual thousands.
+pub fn check_determinism<T: Send + Sync + 'static>(mut test: TestCfg<T>) {
+    let name = test.name.unwrap_or("cobb");
+    // For `COBB_SEED`. If the rest is broken, each run says so.
+    let _ = crate::config::apply_env_config(&mut test);
+    let seed = test.seed.unwrap_or_else(|| Rng::new().gen());
+    eprintln!("{}: checking determinism with seed {:#x}", name, seed);
+    let hooks = Hooks::from_cfg(&test);
+    let iterations = test.iterations;
+    test.seed = Some(seed);
+    test.iterations = 1;
+    test.groups = 1;
+    let mut problems = vec![];
+    let mut diverged = 0;
+    for iteration in 0..iterations {
+        let iter_seed = derive_seed(seed, Stream::Iteration, 0, iteration);
+        let run = || {
+            let capture = Arc::new(Mutex::new(vec![]));
+            let replay = Replay {
+                seed: iter_seed,
+                quiet: true,
+                capture: Some(Arc::clone(&capture)),
+                ..Default::default()
+            };
+            let _ = crate::try_run_with_hooks(test.clone(), hooks.clone(), Some(replay));
+            let mut traces = capture.lock().unwrap_or_else(PoisonError::into_inner);
+            traces.pop()
+        };
+        let (Some(a), Some(b)) = (run(), run()) else {
+            eprintln!(
+                "{}: iteration {} didn't get far enough to compare",
+                name, iteration
+            );
+            continue;
+        };
+        let (mut found, paths) = compare(&a, &b);
+        diverged += paths;
+        for p in &mut found {
+            *p = format!(
+                "iteration {} (iteration seed {:#x}): {}",
+                iteration, iter_seed, p
+            );
+        }
+        problems.extend(found);
+    }
+    if diverged != 0 {
+        eprintln!(
+            "{}: threads went a different way through the test {} times, so not every schedule point could be compared",
+            name, diverged
+        );
+    }
+    if problems.is_empty() {
+        eprintln!(
+            "{}: cobb made the same decisions both times in all {} iterations",
+            name, iterations
+        );
+        return;
+    }
+    let msg = format!(
+        "cobb made different decisions with the same seed:\n  {}",
+        problems.join("\n  ")
+    );
+    eprintln!("{}: {}", name, msg);
+    std::panic::resume_unwind(Box::new(msg));
+}
+
+/// The decisions that differ between `a` and `b`, and how many threads took
+/// a different path.
+fn compare(a: &Trace, b: &Trace) -> (Vec<String>, usize) {
+    let mut found = vec![];
+    if a.order != b.order {
+        found.push(format!("start order {:?} vs {:?}", a.order, b.order));
+    }
+    if a.priorities != b.priorities {
+        found.push(format!(
+            "priorities {:?} vs {:?}",
+            a.priorities, b.priorities
+        ));
+    }
+    if a.benched != b.benched {
+        found.push(format!(
+            "benched threads {:?} vs {:?}",
+            a.benched, b.benched
+        ));
+    }
+    let mut diverged = 0;
+    for (thread, (ea, eb)) in a.threads.iter().zip(&b.threads).enumerate() {
+        let sps = |events: &'_ [TraceEvent]| {
+            events
+                .iter()
+                .filter_map(|e| match e {
+                    TraceEvent::Sp {
+                        location, action, ..
+                    } => Some((location.clone(), *action)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let (sa, sb) = (sps(ea), sps(eb));
+        for (n, ((la, aa), (lb, ab))) in sa.iter().zip(&sb).enumerate() {
+            if la != lb {
+                diverged += 1;
+                break;
+            }
+            if aa != ab {
+                found.push(format!(
+                    "thread {}'s schedule point {} (at {}) did {} vs {}",
+                    thread, n, la, aa, ab
+                ));
+                break;
+            }
+        }
+    }
+    (found, diverged)
+}
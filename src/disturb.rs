@@ -0,0 +1,134 @@
+//! Extra threads that keep the rest of the machine busy while the test runs,
+//! which changes its timing in ways an idle machine never does. See
+//! `TestCfg::disturbers`.
+use crate::Rng;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A kind of disturber thread. See `TestCfg::disturbers`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disturber {
+    /// Stream through a buffer of this many bytes over and over, evicting the
+    /// test's data from the caches. It should be bigger than the last level
+    /// cache.
+    Cache(usize),
+    /// Allocate and free blocks of assorted sizes, contending on the
+    /// allocator's locks (and churning its caches).
+    Allocator,
+    /// Make cheap syscalls (zero-length sleeps, yields and `getpid`) in a
+    /// tight loop, so the scheduler is always busy around the runner threads.
+    Syscalls,
+}
+
+#[derive(Default)]
+struct Flags {
+    /// Whether an iteration's running. They idle in between.
+    active: AtomicBool,
+    done: AtomicBool,
+}
+
+impl Flags {
+    /// Waits until there's an iteration running, returning false once it's
+    /// time to stop.
+    fn wait_active(&self) -> bool {
+        while !self.active.load(Ordering::Relaxed) {
+            if self.done.load(Ordering::Relaxed) {
+                return false;
+            }
+            thread::sleep(Duration::from_micros(50));
+        }
+        !self.done.load(Ordering::Relaxed)
+    }
+}
+
+/// The disturber threads for a group, which stop when this is dropped.
+pub(crate) struct Disturbers {
+    flags: Arc<Flags>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Disturbers {
+    pub(crate) fn spawn(label: &str, kinds: &[Disturber], seed: u64) -> Self {
+        let flags = Arc::new(Flags::default());
+        let threads = kinds
+            .iter()
+            .enumerate()
+            .map(|(i, &kind)| {
+                let flags = Arc::clone(&flags);
+                let rng = Rng::with_seed(crate::split_seed(seed, i as u64));
+                thread::Builder::new()
+                    .name(format!("{} disturber {}", label, i))
+                    .spawn(move || match kind {
+                        Disturber::Cache(bytes) => thrash_cache(&flags, bytes),
+                        Disturber::Allocator => churn_allocator(&flags, rng),
+                        Disturber::Syscalls => make_syscalls(&flags, rng),
+                    })
+                    .unwrap_or_else(|e| panic!("Cobb: failed to launch disturber: {:?}", e))
+            })
+            .collect();
+        Self { flags, threads }
+    }
+
+    pub(crate) fn set_active(&self, active: bool) {
+        self.flags.active.store(active, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Disturbers {
+    fn drop(&mut self) {
+        self.flags.done.store(true, Ordering::Relaxed);
+        for t in self.threads.drain(..) {
+            let _ = t.join();
+        }
+    }
+}
+
+fn thrash_cache(flags: &Flags, bytes: usize) {
+    const LINE: usize = 64;
+    let mut buf = vec![0u8; bytes.max(LINE)];
+    while flags.wait_active() {
+        for chunk in buf.chunks_mut(1 << 20) {
+            for line in chunk.iter_mut().step_by(LINE) {
+                *line = line.wrapping_add(1);
+            }
+            core::hint::black_box(&mut *chunk);
+            // Check in every so often, rather than once per (possibly huge)
+            // pass.
+            if !flags.active.load(Ordering::Relaxed) {
+                break;
+            }
+        }
+    }
+}
+
+fn churn_allocator(flags: &Flags, mut rng: Rng) {
+    let mut blocks: Vec<Vec<u8>> = (0..64).map(|_| vec![]).collect();
+    while flags.wait_active() {
+        for _ in 0..64 {
+            let i = rng.upto(blocks.len());
+            // Mostly small, with the odd big one.
+            let size = match rng.upto(16) {
+                0 => 16 << 10 << rng.upto(4),
+                _ => 16 << rng.upto(8),
+            };
+            blocks[i] = vec![1; size];
+        }
+        core::hint::black_box(&blocks);
+    }
+}
+
+fn make_syscalls(flags: &Flags, mut rng: Rng) {
+    while flags.wait_active() {
+        for _ in 0..64 {
+            match rng.upto(3) {
+                0 => thread::sleep(Duration::from_nanos(0)),
+                1 => thread::yield_now(),
+                _ => {
+                    core::hint::black_box(std::process::id());
+                }
+            }
+        }
+    }
+}
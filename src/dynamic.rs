@@ -0,0 +1,67 @@
+use crate::{run_with_hooks, Hooks, IterCtx, TestCfg, TestCtx};
+use std::any::Any;
+use std::sync::Arc;
+
+/// The type-erased test state used by `run_test_dyn`.
+pub type DynState = Box<dyn Any + Send + Sync>;
+
+type DynTestFn = Box<dyn Fn(&DynState, &TestCtx) + Send + Sync>;
+type DynAfterEachFn = Box<dyn Fn(&DynState, &IterCtx<'_>) + Send + Sync>;
+type DynOwnerFn = Box<dyn Fn(&mut DynState, &TestCtx) + Send + Sync>;
+
+/// A test whose callbacks are boxed closures, rather than `fn`s, and whose
+/// state is type-erased. This is for building tests at runtime (say, from a
+/// spec file) without knowing the state's type at compile time.
+pub struct DynTestCfg {
+    /// Everything other than the callbacks. The callbacks in here are ignored
+    /// in favor of the ones below.
+    pub cfg: TestCfg<DynState>,
+    pub setup: Box<dyn Fn() -> DynState + Send + Sync>,
+    pub teardown: Box<dyn Fn(&mut DynState) + Send + Sync>,
+    /// Like `TestCfg::test`. Ignored if `phases` isn't empty.
+    pub test: DynTestFn,
+    pub phases: Vec<DynTestFn>,
+    /// Like `TestCfg::owner`.
+    pub owner: Option<DynOwnerFn>,
+    pub before_each: Box<dyn Fn(&DynState) + Send + Sync>,
+    pub after_each: DynAfterEachFn,
+}
+
+impl DynTestCfg {
+    /// A test with the default config, and no-op callbacks other than `setup`
+    /// and `test`.
+    pub fn new(
+        setup: impl Fn() -> DynState + Send + Sync + 'static,
+        test: impl Fn(&DynState, &TestCtx) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            cfg: TestCfg::default(),
+            setup: Box::new(setup),
+            teardown: Box::new(|_| {}),
+            test: Box::new(test),
+            phases: vec![],
+            owner: None,
+            before_each: Box::new(|_| {}),
+            after_each: Box::new(|_, _| {}),
+        }
+    }
+}
+
+/// Like `run_test`, for a `DynTestCfg`.
+pub fn run_test_dyn(test: DynTestCfg) {
+    let phases = if test.phases.is_empty() {
+        vec![test.test]
+    } else {
+        test.phases
+    };
+    let setup = test.setup;
+    let hooks = Hooks {
+        setup: Arc::new(move |_| setup()),
+        teardown: Arc::from(test.teardown),
+        phases: phases.into_iter().map(Arc::from).collect(),
+        owner: test.owner.map(Arc::from),
+        before_each: Arc::from(test.before_each),
+        after_each: Arc::from(test.after_each),
+    };
+    run_with_hooks(test.cfg, hooks);
+}
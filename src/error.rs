@@ -0,0 +1,335 @@
+use crate::{PanicDetails, Violation};
+use core::fmt;
+use std::backtrace::Backtrace;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Why a test failed. `try_run_test` returns these, and `run_test` panics
+/// with one as the payload (after printing it), so harnesses catching the
+/// panic can get at it too.
+///
+/// The ones for panics keep where the panic happened, the thread it happened
+/// on, a backtrace, and the value it panicked with, in their `details` (see
+/// `panic_details`). Formatting with `{:#}` includes the thread and
+/// backtrace.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum CobbError {
+    /// `setup` panicked.
+    SetupPanicked {
+        group: usize,
+        message: String,
+        details: Option<Arc<PanicDetails>>,
+    },
+    /// A runner thread panicked, almost always in the test itself. `regions`
+    /// has the `TestCtx::region`s it was inside, innermost last, and `seed` is
+    /// the iteration's seed, for `run_single_iteration`.
+    ThreadPanicked {
+        group: usize,
+        thread: usize,
+        iteration: Option<usize>,
+        seed: Option<u64>,
+        message: String,
+        regions: Vec<&'static str>,
+        details: Option<Arc<PanicDetails>>,
+    },
+    /// One of the other callbacks run by a group's driver panicked. `hook` is
+    /// its name, like `"after_each"`, or `"driver"` for cobb itself.
+    HookPanicked {
+        group: usize,
+        hook: &'static str,
+        iteration: Option<usize>,
+        message: String,
+        details: Option<Arc<PanicDetails>>,
+    },
+    /// An iteration took longer than `TestCfg::iteration_timeout`. `stuck` has
+    /// the names of the threads that hadn't finished it.
+    Timeout {
+        group: usize,
+        iteration: usize,
+        seed: u64,
+        timeout: Duration,
+        stuck: Vec<String>,
+    },
+    /// Invariant violations were reported (see `soft_assert!`). Only the
+    /// first several are kept, `count` is the total. `distinct` has the first
+    /// of each different violation (going by where it was reported and its
+    /// message, ignoring any numbers in it), with how many times it was
+    /// reported, most common first.
+    Violations {
+        count: usize,
+        violations: Vec<Violation>,
+        distinct: Vec<(usize, Violation)>,
+    },
+    /// The `TestCfg` (or `COBB_CONFIG`) didn't make sense.
+    ConfigInvalid(String),
+    /// More than one thing went wrong.
+    Multiple(Vec<CobbError>),
+}
+
+impl fmt::Display for CobbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CobbError::SetupPanicked { group, message, .. } => {
+                write!(f, "group {} setup panicked", group)?;
+                write_panic(f, self.panic_details(), message)
+            }
+            CobbError::ThreadPanicked {
+                group,
+                thread,
+                iteration,
+                seed,
+                message,
+                regions,
+                ..
+            } => {
+                write!(f, "group {} thread {}", group, thread)?;
+                if let Some(i) = iteration {
+                    write!(f, " iteration {}", i)?;
+                }
+                if let Some(s) = seed {
+                    write!(f, " (seed {:#x})", s)?;
+                }
+                if !regions.is_empty() {
+                    write!(f, " inside {}", crate::describe_regions(regions))?;
+                }
+                write!(f, " panicked")?;
+                write_panic(f, self.panic_details(), message)
+            }
+            CobbError::HookPanicked {
+                group,
+                hook,
+                iteration,
+                message,
+                ..
+            } => {
+                write!(f, "group {} {}", group, hook)?;
+                if let Some(i) = iteration {
+                    write!(f, " iteration {}", i)?;
+                }
+                write!(f, " panicked")?;
+                write_panic(f, self.panic_details(), message)
+            }
+            CobbError::Timeout {
+                group,
+                iteration,
+                seed,
+                timeout,
+                stuck,
+            } => write!(
+                f,
+                "group {} iteration {} (seed {:#x}) timed out after {:?}, {} threads didn't finish: {:?}",
+                group,
+                iteration,
+                seed,
+                timeout,
+                stuck.len(),
+                stuck
+            ),
+            CobbError::Violations {
+                count, distinct, ..
+            } => {
+                write!(f, "{} violations", count)?;
+                if distinct.len() < *count {
+                    write!(f, ", {} different", distinct.len())?;
+                }
+                for (n, v) in distinct.iter().take(20) {
+                    write!(f, "\n  ")?;
+                    if *n > 1 {
+                        write!(f, "{}x ", n)?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                let shown = distinct.iter().take(20).map(|d| d.0).sum::<usize>();
+                if *count > shown {
+                    write!(f, "\n  ... and {} more", count - shown)?;
+                }
+                Ok(())
+            }
+            CobbError::ConfigInvalid(msg) => write!(f, "invalid config: {}", msg),
+            CobbError::Multiple(errors) => {
+                let distinct = dedup(errors);
+                write!(f, "{} failures", errors.len())?;
+                if distinct.len() < errors.len() {
+                    write!(f, ", {} different", distinct.len())?;
+                }
+                for (n, e) in distinct {
+                    write!(f, "\n  ")?;
+                    if n > 1 {
+                        write!(f, "{}x ", n)?;
+                    }
+                    match f.alternate() {
+                        true => write!(f, "{:#}", e)?,
+                        false => write!(f, "{}", e)?,
+                    }
+                }
+                Ok(())
+            }
+        }?;
+        match self.panic_details() {
+            Some(d) if f.alternate() => {
+                if let Some(thread) = &d.thread {
+                    write!(f, "\non thread '{}'", thread)?;
+                }
+                match &d.backtrace {
+                    Some(bt) => write!(f, "\nbacktrace:\n{}", bt),
+                    None => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// `s` with runs of digits (and hex numbers) replaced, so that messages that
+/// only differ in the values they mention compare equal.
+pub(crate) fn normalize(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if !c.is_ascii_digit() {
+            out.push(c);
+            continue;
+        }
+        if c == '0' && chars.peek() == Some(&'x') {
+            chars.next();
+        }
+        while chars.peek().is_some_and(char::is_ascii_hexdigit) {
+            chars.next();
+        }
+        out.push('#');
+    }
+    out
+}
+
+/// What two errors need to have in common to count as the same failure: the
+/// kind of thing that failed, where (for panics), and the message, ignoring
+/// which group, thread and iteration it was and any numbers (seeds
+/// included).
+pub(crate) fn dedup_key(e: &CobbError) -> String {
+    key(e, true)
+}
+
+/// `dedup_key` for each of the different failures (and violations) in `e`,
+/// sorted.
+pub(crate) fn dedup_keys(e: &CobbError) -> Vec<String> {
+    keys(e, true)
+}
+
+/// Like `dedup_key`, but leaving out where things happened, for comparing
+/// failures of different code.
+pub(crate) fn outcome_key(e: &CobbError) -> String {
+    key(e, false)
+}
+
+fn key(e: &CobbError, with_location: bool) -> String {
+    let (what, message) = match e {
+        CobbError::SetupPanicked { message, .. } => ("setup".to_string(), message),
+        CobbError::ThreadPanicked {
+            message, regions, ..
+        } => (format!("thread {:?}", regions), message),
+        CobbError::HookPanicked { hook, message, .. } => (hook.to_string(), message),
+        CobbError::Multiple(_) | CobbError::Violations { .. } => {
+            return keys(e, with_location).join("\n")
+        }
+        // This names the stuck threads, after the test.
+        CobbError::Timeout { .. } if !with_location => return "timeout".to_string(),
+        _ => return normalize(&e.to_string()),
+    };
+    match e.panic_details().and_then(|d| d.location.as_deref()) {
+        Some(location) if with_location => format!("{} {} {}", what, location, normalize(message)),
+        _ => format!("{} {}", what, normalize(message)),
+    }
+}
+
+fn keys(e: &CobbError, with_location: bool) -> Vec<String> {
+    let mut keys = match e {
+        CobbError::Multiple(errors) => errors.iter().flat_map(|e| keys(e, with_location)).collect(),
+        CobbError::Violations { distinct, .. } => distinct
+            .iter()
+            .map(|(_, v)| match with_location {
+                true => format!("violation {} {}", v.location, normalize(&v.message)),
+                false => format!("violation {}", normalize(&v.message)),
+            })
+            .collect(),
+        _ => vec![key(e, with_location)],
+    };
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+/// The first of each different failure in `errors`, with how many there
+/// were, most common first.
+fn dedup(errors: &[CobbError]) -> Vec<(usize, &CobbError)> {
+    let mut distinct: Vec<(usize, &CobbError)> = vec![];
+    let mut index = std::collections::HashMap::<String, usize>::new();
+    for e in errors {
+        match index.entry(dedup_key(e)) {
+            std::collections::hash_map::Entry::Occupied(i) => distinct[*i.get()].0 += 1,
+            std::collections::hash_map::Entry::Vacant(v) => {
+                v.insert(distinct.len());
+                distinct.push((1, e));
+            }
+        }
+    }
+    distinct.sort_by_key(|d| std::cmp::Reverse(d.0));
+    distinct
+}
+
+/// The end of a panic's line: where it happened, if that's known, and the
+/// message.
+fn write_panic(
+    f: &mut fmt::Formatter<'_>,
+    details: Option<&PanicDetails>,
+    message: &str,
+) -> fmt::Result {
+    if let Some(loc) = details.and_then(|d| d.location.as_ref()) {
+        write!(f, " at {}", loc)?;
+    }
+    write!(f, ": {}", message)
+}
+
+impl std::error::Error for CobbError {}
+
+impl CobbError {
+    /// The details of the panic, if it's a panic.
+    pub fn panic_details(&self) -> Option<&PanicDetails> {
+        match self {
+            CobbError::SetupPanicked { details, .. }
+            | CobbError::ThreadPanicked { details, .. }
+            | CobbError::HookPanicked { details, .. } => details.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The backtrace from where it panicked, if it's a panic and one could
+    /// be taken.
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.panic_details()?.backtrace.as_ref()
+    }
+
+    /// The group, iteration and iteration seed it happened in, as far as
+    /// they're known.
+    pub(crate) fn location(&self) -> (Option<usize>, Option<usize>, Option<u64>) {
+        match self {
+            CobbError::SetupPanicked { group, .. } => (Some(*group), None, None),
+            CobbError::ThreadPanicked {
+                group,
+                iteration,
+                seed,
+                ..
+            } => (Some(*group), *iteration, *seed),
+            CobbError::HookPanicked {
+                group, iteration, ..
+            } => (Some(*group), *iteration, None),
+            CobbError::Timeout {
+                group,
+                iteration,
+                seed,
+                ..
+            } => (Some(*group), Some(*iteration), Some(*seed)),
+            _ => (None, None, None),
+        }
+    }
+}
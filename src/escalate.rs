@@ -0,0 +1,94 @@
+//! Ramping the perturbation up while a test keeps passing. See
+//! `TestCfg::escalate`.
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How `TestCfg::escalate` ramps up the perturbation.
+///
+/// The run starts with everything scaled by `start` (on top of
+/// `TestCfg::intensity`), and each time `every` iterations go by without a
+/// failure, the scale is multiplied by `factor`, up to `max`. Once something
+/// fails, it stays where it got to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Escalation {
+    pub start: f64,
+    pub max: f64,
+    pub factor: f64,
+    /// The number of iterations between steps.
+    pub every: usize,
+}
+
+impl Default for Escalation {
+    fn default() -> Self {
+        Self {
+            start: 0.25,
+            max: 8.0,
+            factor: 2.0,
+            every: 100,
+        }
+    }
+}
+
+impl Escalation {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if !(self.start > 0.0 && self.start <= self.max && self.max.is_finite()) {
+            return Err(format!(
+                "escalation must have 0 < start <= max, got {}..={}",
+                self.start, self.max
+            ));
+        }
+        if !(self.factor >= 1.0 && self.factor.is_finite()) || self.every == 0 {
+            return Err(format!(
+                "escalation needs a factor of at least 1 and a nonzero step, got {:?}",
+                self
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The current scale for the whole run, which is always 1.0 without
+/// escalation.
+pub(crate) struct Level(AtomicU64);
+
+impl Level {
+    pub(crate) fn new(cfg: Option<&Escalation>) -> Self {
+        Self(AtomicU64::new(cfg.map_or(1.0, |e| e.start).to_bits()))
+    }
+
+    pub(crate) fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Decides when to step up. Only the first group's driver has one, and the
+/// rest just follow along.
+pub(crate) struct Escalator {
+    cfg: Escalation,
+    since: usize,
+    failed: bool,
+}
+
+impl Escalator {
+    pub(crate) fn new(cfg: Escalation) -> Self {
+        Self {
+            cfg,
+            since: 0,
+            failed: false,
+        }
+    }
+
+    /// Called after each iteration, with whether anything has failed in the
+    /// run so far. Returns the new level if it stepped up.
+    pub(crate) fn finished(&mut self, level: &Level, failed: bool) -> Option<f64> {
+        self.failed |= failed;
+        self.since += 1;
+        if self.failed || self.since < self.cfg.every {
+            return None;
+        }
+        self.since = 0;
+        let old = level.get();
+        let new = (old * self.cfg.factor).min(self.cfg.max);
+        level.0.store(new.to_bits(), Ordering::Relaxed);
+        (new != old).then_some(new)
+    }
+}
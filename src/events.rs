@@ -0,0 +1,169 @@
+//! The machine readable output. See `TestCfg::events`.
+use crate::CobbError;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Where `TestCfg::events` writes the JSON events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventSink {
+    Stderr,
+    /// Appended to, so that several tests can share one.
+    File(PathBuf),
+}
+
+impl EventSink {
+    /// Parses the value of `COBB_OUTPUT`: `json` for stderr, `json:<path>`
+    /// for a file, or `text` for nothing.
+    pub(crate) fn parse(s: &str) -> Result<Option<EventSink>, String> {
+        match s.trim() {
+            "text" => Ok(None),
+            "json" => Ok(Some(EventSink::Stderr)),
+            s => match s.strip_prefix("json:") {
+                Some(path) if !path.is_empty() => Ok(Some(EventSink::File(path.into()))),
+                _ => Err(format!(
+                    "expected `text`, `json` or `json:<path>`, got {:?}",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// Writes events for one run, a line each.
+pub(crate) struct Events {
+    out: Mutex<Box<dyn Write + Send>>,
+    test: String,
+}
+
+impl Events {
+    pub(crate) fn open(sink: &EventSink, test: &str) -> std::io::Result<Events> {
+        let out: Box<dyn Write + Send> = match sink {
+            EventSink::Stderr => Box::new(std::io::stderr()),
+            EventSink::File(path) => Box::new(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?,
+            ),
+        };
+        Ok(Events {
+            out: Mutex::new(out),
+            test: test.to_string(),
+        })
+    }
+
+    /// Starts an event of kind `kind`, to be finished with `emit`.
+    pub(crate) fn event(&self, kind: &str) -> Event {
+        Event(String::from("{"))
+            .str("event", kind)
+            .str("test", &self.test)
+    }
+
+    pub(crate) fn emit(&self, event: Event) {
+        let mut line = event.0;
+        line.push_str("}\n");
+        // One write per line, so lines from different groups (or tests
+        // sharing the file) don't get mixed up.
+        let mut out = self
+            .out
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let _ = out.write_all(line.as_bytes());
+        let _ = out.flush();
+    }
+
+    pub(crate) fn failure(&self, e: &CobbError) {
+        let (group, iteration, seed) = e.location();
+        let event = self
+            .event("iteration_failed")
+            .opt_num("group", group.map(|g| g as u64))
+            .opt_num("iteration", iteration.map(|i| i as u64))
+            .opt_seed("seed", seed)
+            .str("message", &e.to_string());
+        self.emit(event);
+    }
+}
+
+/// A JSON object being built.
+pub(crate) struct Event(String);
+
+impl Event {
+    fn key(mut self, key: &str) -> Self {
+        if self.0.len() > 1 {
+            self.0.push(',');
+        }
+        escape(&mut self.0, key);
+        self.0.push(':');
+        self
+    }
+
+    pub(crate) fn str(mut self, key: &str, value: &str) -> Self {
+        self = self.key(key);
+        escape(&mut self.0, value);
+        self
+    }
+
+    pub(crate) fn num(mut self, key: &str, value: u64) -> Self {
+        self = self.key(key);
+        self.0.push_str(&value.to_string());
+        self
+    }
+
+    pub(crate) fn opt_num(self, key: &str, value: Option<u64>) -> Self {
+        match value {
+            Some(v) => self.num(key, v),
+            None => self.null(key),
+        }
+    }
+
+    pub(crate) fn float(mut self, key: &str, value: f64) -> Self {
+        if !value.is_finite() {
+            return self.null(key);
+        }
+        self = self.key(key);
+        self.0.push_str(&value.to_string());
+        self
+    }
+
+    pub(crate) fn bool(mut self, key: &str, value: bool) -> Self {
+        self = self.key(key);
+        self.0.push_str(if value { "true" } else { "false" });
+        self
+    }
+
+    /// Seeds are written as hex strings, the way they're printed (and taken
+    /// by `COBB_SEED`), since plenty of JSON parsers would round them.
+    pub(crate) fn seed(self, key: &str, seed: u64) -> Self {
+        self.str(key, &format!("{:#x}", seed))
+    }
+
+    pub(crate) fn opt_seed(self, key: &str, seed: Option<u64>) -> Self {
+        match seed {
+            Some(s) => self.seed(key, s),
+            None => self.null(key),
+        }
+    }
+
+    fn null(mut self, key: &str) -> Self {
+        self = self.key(key);
+        self.0.push_str("null");
+        self
+    }
+}
+
+fn escape(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
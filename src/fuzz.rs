@@ -0,0 +1,179 @@
+//! Running a test with its scheduling decided by a fuzzer's input, so that
+//! coverage-guided fuzzers can search the space of schedules.
+use crate::{CobbError, Hooks, Priority, ScheduleMode, TestCfg};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Runs a single iteration of `test` with every scheduling decision taken
+/// from `data`: the threads' priorities, the order they start in, and, since
+/// the test is run with `ScheduleMode::Cooperative`, which thread goes next at
+/// each schedule point. Once `data` runs out, every remaining decision is the
+/// same as if the rest had been zeroes. Panics if the test fails, like
+/// `run_test`.
+///
+/// The seed (for `TestCtx::rng` and the like) comes from `data` as well, so
+/// the same input always runs the same way, which is what a fuzzer needs.
+/// Everything that would bring in randomness from elsewhere (suspension,
+/// preemption, disturbers, a varying number of threads or participants) is
+/// turned off, and there's only ever one group. The same caveats as for
+/// `ScheduleMode::Cooperative` apply: the threads may only wait on each other
+/// at schedule points, `TestCtx::barrier`, or with the types in `cobb::sync`.
+///
+/// With cargo-fuzz, this goes in a fuzz target:
+///
+/// ```ignore
+/// libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+///     cobb::fuzz::run(stack_test(), data);
+/// });
+/// ```
+pub fn run<T: Send + Sync + 'static>(test: TestCfg<T>, data: &[u8]) {
+    let name = test.name.unwrap_or("cobb");
+    if let Err(e) = try_run(test, data) {
+        eprintln!("{}: {:#}", name, e);
+        std::panic::resume_unwind(Box::new(e));
+    }
+}
+
+/// Like `run`, but returns why the test failed instead of panicking.
+pub fn try_run<T: Send + Sync + 'static>(
+    mut test: TestCfg<T>,
+    data: &[u8],
+) -> Result<(), CobbError> {
+    let hooks = Hooks::from_cfg(&test);
+    let input = Arc::new(Input::new(data));
+    test.iterations = 1;
+    test.groups = 1;
+    test.thread_range = None;
+    test.sub_iteration_range = None;
+    test.oversubscribe = None;
+    test.random_participation = false;
+    test.schedule = ScheduleMode::Cooperative;
+    test.reprioritize = None;
+    test.on_reprioritize = None;
+    test.inversion = None;
+    test.suspend = None;
+    test.preempt = None;
+    test.disturbers.clear();
+    test.escalate = None;
+    test.minimize = None;
+    test.corpus_dir = None;
+    test.priorities = (0..test.threads)
+        .map(|_| match input.choose(2) {
+            0 => Priority::Hi,
+            _ => Priority::Lo,
+        })
+        .collect();
+    let seed = input.seed();
+    test.seed = Some(seed);
+    let replay = crate::Replay {
+        seed,
+        input: Some(input),
+        // Fuzzers run a lot of these.
+        quiet: true,
+        ..Default::default()
+    };
+    crate::try_run_with_hooks(test, hooks, Some(replay)).map(drop)
+}
+
+/// Runs `make()`'s test under afl.rs, as `fuzz::run` does, for each input
+/// afl-fuzz hands over, in persistent mode. Call this from `main` in place of
+/// `afl::fuzz!`.
+///
+/// Failures are deduplicated by their message: only the first input to fail a
+/// particular way (in this process) is reported to afl-fuzz as a crash, and
+/// the rest just print the message, so one bug doesn't bury the others.
+#[cfg(feature = "afl")]
+pub fn afl<T: Send + Sync + 'static>(make: fn() -> TestCfg<T>) {
+    // afl's hook would abort at the first panic in a test thread, before cobb
+    // gets to look at it.
+    afl::fuzz(false, |data| {
+        if let Err(e) = try_run(make(), data) {
+            crash_if_new(&e);
+        }
+    });
+}
+
+/// Like `afl`, but for honggfuzz: runs `make()`'s test for each input
+/// honggfuzz hands over, forever, crashing only for failures that haven't
+/// been seen before (in this process). Build with `cargo hfuzz`.
+#[cfg(feature = "honggfuzz")]
+pub fn honggfuzz<T: Send + Sync + 'static>(make: fn() -> TestCfg<T>) -> ! {
+    static RESET_HOOK: std::sync::Once = std::sync::Once::new();
+    loop {
+        honggfuzz::fuzz(|data| {
+            // honggfuzz installs a hook that aborts at any panic, which would
+            // include the test threads' ones that cobb catches.
+            RESET_HOOK.call_once(|| drop(std::panic::take_hook()));
+            if let Err(e) = try_run(make(), data) {
+                crash_if_new(&e);
+            }
+        });
+    }
+}
+
+/// Aborts, so the fuzzer records the input as a crash, unless the same
+/// failure (going by where it panicked and its message, with any numbers in
+/// it ignored) has already been reported. Every input runs with its own seed,
+/// so anything that mentions the seed or iteration can't tell them apart.
+#[cfg(any(feature = "afl", feature = "honggfuzz"))]
+fn crash_if_new(e: &CobbError) {
+    use std::collections::HashSet;
+    use std::sync::{Mutex, PoisonError};
+    static SEEN: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+    let message = e.to_string();
+    let key = crate::error::dedup_key(e);
+    let new = SEEN
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get_or_insert_with(HashSet::new)
+        .insert(key);
+    if new {
+        eprintln!("cobb: {}", message);
+        std::process::abort();
+    }
+    eprintln!("cobb: failed again (not reporting it): {}", message);
+}
+
+/// The fuzzer's input, as it's used up by the decisions being made.
+pub(crate) struct Input {
+    data: Box<[u8]>,
+    pos: AtomicUsize,
+}
+
+impl Input {
+    fn new(data: &[u8]) -> Self {
+        Self {
+            data: data.into(),
+            pos: AtomicUsize::new(0),
+        }
+    }
+
+    fn byte(&self) -> u8 {
+        let i = self.pos.fetch_add(1, Ordering::Relaxed);
+        self.data.get(i).copied().unwrap_or(0)
+    }
+
+    /// A number in `0..n`, from as many bytes as that takes.
+    pub(crate) fn choose(&self, n: usize) -> usize {
+        let (mut x, mut range) = (0usize, 1usize);
+        while range < n {
+            x = (x << 8) | usize::from(self.byte());
+            range = range.saturating_mul(256);
+        }
+        x % n.max(1)
+    }
+
+    /// Shuffles `v` as the input says.
+    pub(crate) fn shuffle<T>(&self, v: &mut [T]) {
+        for i in 0..v.len().saturating_sub(1) {
+            v.swap(i, i + self.choose(v.len() - i));
+        }
+    }
+
+    /// A seed made from the whole input (FNV-1a), without using any of it up.
+    fn seed(&self) -> u64 {
+        self.data.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+            (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+}
@@ -0,0 +1,158 @@
+//! A `cargo test` compatible harness for cobb tests, for use in test targets
+//! with `harness = false`:
+//!
+//! ```toml
+//! [[test]]
+//! name = "concurrency"
+//! harness = false
+//! ```
+//!
+//! Supports the commonly used libtest flags (`--list`, `--exact`, `--skip`,
+//! `--test-threads`, and name filters), and accepts but ignores the others.
+use crate::batch::run_caught;
+use crate::TestSpec;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Runs all tests registered with `register_test!` as individual test cases,
+/// according to the process's command line, and exits.
+#[cfg(feature = "registry")]
+pub fn harness() -> ! {
+    run_harness(crate::registered_tests())
+}
+
+/// Like `harness`, but for an explicit list of tests.
+pub fn run_harness(tests: Vec<TestSpec>) -> ! {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(101);
+        }
+    };
+    let total = tests.len();
+    let selected = tests
+        .into_iter()
+        .filter(|t| args.selects(t.name()))
+        .collect::<Vec<_>>();
+    if args.list {
+        for t in &selected {
+            println!("{}: test", t.name());
+        }
+        if !args.quiet {
+            println!("\n{} tests, 0 benchmarks", selected.len());
+        }
+        std::process::exit(0);
+    }
+
+    let start = Instant::now();
+    println!("\nrunning {} tests", selected.len());
+    let next = AtomicUsize::new(0);
+    let failures = Mutex::new(vec![]);
+    let workers = args
+        .test_threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    std::thread::scope(|s| {
+        for _ in 0..workers.clamp(1, selected.len().max(1)) {
+            s.spawn(|| {
+                while let Some(test) = selected.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let (res, _) = run_caught(|| test.run());
+                    match res {
+                        Ok(()) => println!("test {} ... ok", test.name()),
+                        Err(msg) => {
+                            println!("test {} ... FAILED", test.name());
+                            failures.lock().unwrap().push((test.name(), msg));
+                        }
+                    }
+                }
+            });
+        }
+    });
+    let mut failures = failures.into_inner().unwrap();
+    failures.sort();
+    if !failures.is_empty() {
+        println!("\nfailures:\n");
+        for (name, msg) in &failures {
+            println!("---- {} ----\n{}\n", name, msg);
+        }
+        println!("failures:");
+        for (name, _) in &failures {
+            println!("    {}", name);
+        }
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed; 0 ignored; 0 measured; {} filtered out; finished in {:.2}s\n",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        selected.len() - failures.len(),
+        failures.len(),
+        total - selected.len(),
+        start.elapsed().as_secs_f64()
+    );
+    std::process::exit(if failures.is_empty() { 0 } else { 101 });
+}
+
+#[derive(Default)]
+struct Args {
+    list: bool,
+    exact: bool,
+    quiet: bool,
+    ignored: bool,
+    test_threads: Option<usize>,
+    filters: Vec<String>,
+    skip: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Args, String> {
+        let mut out = Args::default();
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            let (flag, mut inline) = match arg.split_once('=') {
+                Some((f, v)) if f.starts_with("--") => (f.to_string(), Some(v.to_string())),
+                _ => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .take()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{} needs a value", flag))
+            };
+            match flag.as_str() {
+                "--list" => out.list = true,
+                "--exact" => out.exact = true,
+                "-q" | "--quiet" => out.quiet = true,
+                // cobb has no ignored tests, so `--ignored` runs nothing.
+                "--ignored" => out.ignored = true,
+                "--skip" => out.skip.push(value()?),
+                "--test-threads" => {
+                    let v = value()?;
+                    let n = v
+                        .parse::<usize>()
+                        .map_err(|e| format!("bad --test-threads {:?}: {}", v, e))?;
+                    out.test_threads = Some(n);
+                }
+                "--include-ignored" | "--nocapture" | "--show-output" | "--bench" | "--test" => {}
+                "--color" | "--format" | "-Z" | "--logfile" => {
+                    value()?;
+                }
+                f if f.starts_with('-') => return Err(format!("unknown option {:?}", f)),
+                _ => out.filters.push(flag),
+            }
+        }
+        Ok(out)
+    }
+
+    fn selects(&self, name: &str) -> bool {
+        let matches = |pat: &String| {
+            if self.exact {
+                name == pat
+            } else {
+                name.contains(pat.as_str())
+            }
+        };
+        !self.ignored
+            && (self.filters.is_empty() || self.filters.iter().any(matches))
+            && !self.skip.iter().any(matches)
+    }
+}
@@ -0,0 +1,253 @@
+//! A report of a run to look at in a browser. See `TestCfg::html_dir`.
+use crate::coverage::Sites;
+use crate::interleaving::Interleavings;
+use crate::junit::escape;
+use crate::{CobbError, Priority, RunReport, Trace, TraceEvent};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+/// Everything that goes in the report.
+pub(crate) struct Page<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) seed: u64,
+    /// From `artifact::describe`.
+    pub(crate) config: &'a str,
+    pub(crate) failures: &'a [CobbError],
+    pub(crate) report: &'a RunReport,
+    pub(crate) sites: Option<&'a Sites>,
+    pub(crate) interleavings: Option<&'a Interleavings>,
+    /// The failing iterations'.
+    pub(crate) traces: &'a [Trace],
+}
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1 .failed { color: #b00; } h1 .passed { color: #070; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+td, th { border: 1px solid #ccc; padding: 0.2em 0.5em; text-align: left; vertical-align: top; }
+td.n { text-align: right; }
+pre { background: #f6f6f6; padding: 0.5em; overflow-x: auto; }
+.timeline td { font-family: monospace; font-size: 90%; white-space: nowrap; }
+.timeline .wait { background: #fde8c8; } .timeline .region { color: #555; }
+.timeline .lo { background: #eee; }
+";
+
+impl Page<'_> {
+    /// Writes the report to `<dir>/<name>.html`, replacing the last run's,
+    /// and returns its path.
+    pub(crate) fn write(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.html", crate::trace::file_name(self.name)));
+        std::fs::write(&path, self.render())?;
+        Ok(path)
+    }
+
+    fn render(&self) -> String {
+        let mut h = String::new();
+        let name = escape(self.name);
+        let _ = writeln!(h, "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">");
+        let _ = writeln!(h, "<title>cobb: {}</title>\n<style>{}</style>", name, STYLE);
+        let _ = writeln!(h, "</head><body>");
+        let (class, verdict) = match self.failures.is_empty() {
+            true => ("passed", "passed"),
+            false => ("failed", "failed"),
+        };
+        let _ = writeln!(
+            h,
+            "<h1>{} <span class=\"{}\">{}</span></h1>",
+            name, class, verdict
+        );
+        let _ = writeln!(
+            h,
+            "<p>Seed <code>{:#x}</code> (rerun with <code>COBB_SEED={:#x}</code>), \
+             {} iterations in {:.3}s.</p>",
+            self.seed,
+            self.seed,
+            self.report.iterations,
+            self.report.wall_time.as_secs_f64()
+        );
+        self.failures_section(&mut h);
+        self.groups_section(&mut h);
+        self.interleavings_section(&mut h);
+        self.sites_section(&mut h);
+        self.timelines_section(&mut h);
+        let _ = writeln!(h, "<h2>Config</h2>\n<pre>{}</pre>", escape(self.config));
+        let _ = writeln!(h, "</body></html>");
+        h
+    }
+
+    fn failures_section(&self, h: &mut String) {
+        if self.failures.is_empty() {
+            return;
+        }
+        let _ = writeln!(h, "<h2>Failures</h2>");
+        for f in self.failures {
+            let _ = writeln!(h, "<pre>{}</pre>", escape(&format!("{:#}", f)));
+        }
+    }
+
+    fn groups_section(&self, h: &mut String) {
+        if self.report.groups.is_empty() {
+            return;
+        }
+        let _ = writeln!(h, "<h2>Groups</h2>\n<table>");
+        let _ = writeln!(
+            h,
+            "<tr><th>group</th><th>threads</th><th>iterations</th><th>wall</th>\
+             <th>overlap</th><th>mean</th><th>median</th><th>p99</th><th>max</th></tr>"
+        );
+        for g in &self.report.groups {
+            let _ = writeln!(
+                h,
+                "<tr><td class=\"n\">{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td>\
+                 <td class=\"n\">{:.2?}</td><td class=\"n\">{:.2}</td><td class=\"n\">{:.2?}</td>\
+                 <td class=\"n\">{:.2?}</td><td class=\"n\">{:.2?}</td><td class=\"n\">{:.2?}</td></tr>",
+                g.group,
+                g.threads,
+                g.iterations,
+                g.wall_time,
+                g.overlap,
+                g.latency.mean,
+                g.latency.median,
+                g.latency.p99,
+                g.latency.max
+            );
+        }
+        let _ = writeln!(h, "</table>");
+    }
+
+    /// A chart of how many distinct interleavings had turned up as the run
+    /// went. One that flattens out early means the test stopped finding new
+    /// ones.
+    fn interleavings_section(&self, h: &mut String) {
+        let Some(i) = self.interleavings else {
+            return;
+        };
+        let (firsts, total) = i.curve();
+        let _ = writeln!(
+            h,
+            "<h2>Interleavings</h2>\n<p>{} distinct in {} iterations.</p>",
+            firsts.len(),
+            total
+        );
+        if total == 0 {
+            return;
+        }
+        let (width, height) = (600.0, 200.0);
+        let x = |n: usize| n as f64 / total as f64 * width;
+        let y = |n: usize| height - n as f64 / firsts.len().max(1) as f64 * height;
+        let mut points = format!("0,{}", height);
+        for (k, &at) in firsts.iter().enumerate() {
+            let _ = write!(
+                points,
+                " {:.1},{:.1} {:.1},{:.1}",
+                x(at),
+                y(k),
+                x(at),
+                y(k + 1)
+            );
+        }
+        let _ = write!(points, " {:.1},{:.1}", width, y(firsts.len()));
+        let _ = writeln!(
+            h,
+            "<svg width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" \
+             style=\"border: 1px solid #ccc\"><polyline fill=\"none\" stroke=\"#36c\" \
+             stroke-width=\"2\" points=\"{p}\"/></svg>\
+             <p>Distinct interleavings (up) against iterations (across).</p>",
+            w = width,
+            h = height,
+            p = points
+        );
+    }
+
+    fn sites_section(&self, h: &mut String) {
+        let Some(sites) = self.sites else {
+            return;
+        };
+        let sites = sites.sorted();
+        let _ = writeln!(h, "<h2>Schedule points</h2>");
+        if sites.is_empty() {
+            let _ = writeln!(h, "<p>None were hit.</p>");
+            return;
+        }
+        let _ = writeln!(
+            h,
+            "<table>\n<tr><th>location</th><th>hint</th><th>hits</th><th>yielded</th><th>%</th></tr>"
+        );
+        for (loc, s) in sites {
+            let _ = writeln!(
+                h,
+                "<tr><td>{}</td><td>{:?}</td><td class=\"n\">{}</td><td class=\"n\">{}</td>\
+                 <td class=\"n\">{:.1}</td></tr>",
+                escape(&loc.to_string()),
+                s.hint,
+                s.hits,
+                s.yields,
+                s.yields as f64 * 100.0 / s.hits.max(1) as f64
+            );
+        }
+        let _ = writeln!(h, "</table>");
+    }
+
+    /// What each thread did in the failing iterations, a column per thread.
+    fn timelines_section(&self, h: &mut String) {
+        if self.traces.is_empty() {
+            return;
+        }
+        let _ = writeln!(h, "<h2>Failing iterations</h2>");
+        for t in self.traces {
+            let _ = writeln!(
+                h,
+                "<h3>Group {} iteration {}</h3>\n<p>Iteration seed <code>{:#x}</code>, \
+                 started in the order {:?}.</p>",
+                t.group, t.iteration, t.seed, t.order
+            );
+            let _ = writeln!(h, "<table class=\"timeline\">\n<tr><th></th>");
+            for i in 0..t.threads.len() {
+                let lo = t.priorities.get(i) == Some(&Priority::Lo);
+                let _ = write!(
+                    h,
+                    "<th{}>thread {}{}{}</th>",
+                    if lo { " class=\"lo\"" } else { "" },
+                    i,
+                    if lo { " (lo)" } else { "" },
+                    if t.benched.contains(&i) {
+                        " (benched)"
+                    } else {
+                        ""
+                    }
+                );
+            }
+            let _ = writeln!(h, "</tr>");
+            let steps = t.threads.iter().map(Vec::len).max().unwrap_or(0);
+            for step in 0..steps {
+                let _ = write!(h, "<tr><td class=\"n\">{}</td>", step);
+                for events in &t.threads {
+                    match events.get(step) {
+                        None => h.push_str("<td></td>"),
+                        Some(TraceEvent::Sp {
+                            location, action, ..
+                        }) => {
+                            let waited = !matches!(action, crate::SpAction::Nothing);
+                            let _ = write!(
+                                h,
+                                "<td{}>{} {}</td>",
+                                if waited { " class=\"wait\"" } else { "" },
+                                escape(location),
+                                action
+                            );
+                        }
+                        Some(TraceEvent::Enter(r)) => {
+                            let _ = write!(h, "<td class=\"region\">&gt; {}</td>", escape(r));
+                        }
+                        Some(TraceEvent::Leave(r)) => {
+                            let _ = write!(h, "<td class=\"region\">&lt; {}</td>", escape(r));
+                        }
+                    }
+                }
+                let _ = writeln!(h, "</tr>");
+            }
+            let _ = writeln!(h, "</table>");
+        }
+    }
+}
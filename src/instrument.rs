@@ -0,0 +1,118 @@
+//! Spans and events for the `tracing` crate, with the `tracing` feature, so
+//! cobb's activity shows up on the same timeline as that of code that's
+//! already instrumented with it. Without the feature, these do nothing.
+//!
+//! Spans cross threads (a group's runner threads are inside its span, say),
+//! so parents are always given explicitly, rather than going by whichever
+//! span the thread is in.
+use crate::{CobbError, Priority, SpAction, SpHint};
+use std::panic::Location;
+
+/// Whether there's anything to do, to skip working out what to pass here
+/// otherwise.
+pub(crate) const ENABLED: bool = cfg!(feature = "tracing");
+
+pub(crate) use imp::Span;
+
+#[cfg(feature = "tracing")]
+mod imp {
+    use super::*;
+
+    #[derive(Clone)]
+    pub(crate) struct Span(tracing::Span);
+
+    pub(crate) type Entered<'a> = tracing::span::Entered<'a>;
+
+    impl Span {
+        pub(crate) fn enter(&self) -> Entered<'_> {
+            self.0.enter()
+        }
+
+        /// The whole run, on the thread that started it.
+        pub(crate) fn run(test: &str, seed: u64) -> Self {
+            let seed = format_args!("{:#x}", seed);
+            Self(tracing::info_span!("cobb run", test, %seed))
+        }
+
+        /// A group, on its driver.
+        pub(crate) fn group(&self, group: usize, threads: usize) -> Self {
+            Self(tracing::info_span!(parent: &self.0, "cobb group", group, threads))
+        }
+
+        /// One of a group's runner threads, for as long as it runs.
+        pub(crate) fn thread(&self, group: usize, thread: usize) -> Self {
+            Self(tracing::info_span!(parent: &self.0, "cobb thread", group, thread))
+        }
+
+        /// An iteration, in a group's span on its driver, or in a thread's
+        /// span on that thread.
+        pub(crate) fn iteration(&self, iteration: usize, seed: u64) -> Self {
+            let seed = format_args!("{:#x}", seed);
+            Self(tracing::debug_span!(parent: &self.0, "cobb iteration", iteration, %seed))
+        }
+    }
+
+    pub(crate) fn reprioritized(group: usize, iteration: usize, priorities: &[Priority]) {
+        tracing::debug!(group, iteration, ?priorities, "cobb reprioritized");
+    }
+
+    pub(crate) fn schedule_point(
+        location: &'static Location<'static>,
+        hint: SpHint,
+        action: SpAction,
+    ) {
+        let location = format_args!("{}", location);
+        tracing::trace!(%location, ?hint, ?action, "cobb schedule point");
+    }
+
+    pub(crate) fn failure(e: &CobbError) {
+        let (group, iteration, seed) = e.location();
+        let seed = seed.map(|s| format!("{:#x}", s));
+        tracing::error!(group, iteration, seed, error = %e, "cobb failure");
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod imp {
+    use super::*;
+
+    #[derive(Clone)]
+    pub(crate) struct Span;
+
+    pub(crate) type Entered<'a> = &'a Span;
+
+    impl Span {
+        pub(crate) fn enter(&self) -> Entered<'_> {
+            self
+        }
+
+        pub(crate) fn run(_test: &str, _seed: u64) -> Self {
+            Self
+        }
+
+        pub(crate) fn group(&self, _group: usize, _threads: usize) -> Self {
+            Self
+        }
+
+        pub(crate) fn thread(&self, _group: usize, _thread: usize) -> Self {
+            Self
+        }
+
+        pub(crate) fn iteration(&self, _iteration: usize, _seed: u64) -> Self {
+            Self
+        }
+    }
+
+    pub(crate) fn reprioritized(_group: usize, _iteration: usize, _priorities: &[Priority]) {}
+
+    pub(crate) fn schedule_point(
+        _location: &'static Location<'static>,
+        _hint: SpHint,
+        _action: SpAction,
+    ) {
+    }
+
+    pub(crate) fn failure(_e: &CobbError) {}
+}
+
+pub(crate) use imp::{failure, reprioritized, schedule_point};
@@ -0,0 +1,89 @@
+//! Counting how many different interleavings the iterations went through, for
+//! `TestCfg::interleaving_report`. Each group hashes what its threads do, in
+//! the order they're seen doing it, which is close enough to tell
+//! interleavings apart.
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+pub(crate) struct Interleavings {
+    /// The hash so far of each group's current iteration.
+    hashes: Vec<crate::CachePad<AtomicU64>>,
+    seen: Mutex<Seen>,
+    iterations: AtomicUsize,
+}
+
+#[derive(Default)]
+struct Seen {
+    hashes: HashSet<u64>,
+    /// How many iterations had finished when each one was first seen, for the
+    /// `TestCfg::html_dir` chart.
+    firsts: Vec<usize>,
+}
+
+fn mix(h: u64, thread: usize, what: u64) -> u64 {
+    (h.rotate_left(5) ^ what ^ (thread as u64) << 48).wrapping_mul(0x517c_c1b7_2722_0a95)
+}
+
+impl Interleavings {
+    pub(crate) fn new(groups: usize) -> Self {
+        Self {
+            hashes: (0..groups)
+                .map(|_| crate::CachePad::new(AtomicU64::new(0)))
+                .collect(),
+            seen: Mutex::default(),
+            iterations: AtomicUsize::new(0),
+        }
+    }
+
+    /// Starts hashing a new iteration of `group`, whose threads start in
+    /// `order`.
+    pub(crate) fn start(&self, group: usize, order: &[usize]) {
+        let h = order.iter().fold(0, |h, &t| mix(h, t, 0));
+        self.hashes[group].store(h, Ordering::Relaxed);
+    }
+
+    /// Notes that `thread` in `group` did `what`.
+    pub(crate) fn observe(&self, group: usize, thread: usize, what: u64) {
+        let _ = self.hashes[group].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |h| {
+            Some(mix(h, thread, what))
+        });
+    }
+
+    /// Done with `group`'s current iteration.
+    pub(crate) fn finish(&self, group: usize) {
+        let h = self.hashes[group].load(Ordering::Relaxed);
+        let mut seen = self.seen.lock().unwrap_or_else(PoisonError::into_inner);
+        let done = self.iterations.fetch_add(1, Ordering::Relaxed);
+        if seen.hashes.insert(h) {
+            seen.firsts.push(done);
+        }
+    }
+
+    pub(crate) fn report(&self) -> String {
+        let distinct = self
+            .seen
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .hashes
+            .len();
+        let iterations = self.iterations.load(Ordering::Relaxed);
+        let mut s = format!(
+            "saw {} distinct interleavings in {} iterations",
+            distinct, iterations
+        );
+        // Not much variety: the test probably runs straight through most of
+        // the time.
+        if iterations >= 100 && distinct * 100 < iterations {
+            s += " (more schedule points might shake things up more)";
+        }
+        s
+    }
+
+    /// The number of iterations before each new interleaving turned up, and
+    /// the total.
+    pub(crate) fn curve(&self) -> (Vec<usize>, usize) {
+        let seen = self.seen.lock().unwrap_or_else(PoisonError::into_inner);
+        (seen.firsts.clone(), self.iterations.load(Ordering::Relaxed))
+    }
+}
@@ -0,0 +1,117 @@
+//! JUnit XML reports, for CI dashboards. See `TestCfg::junit_dir`.
+use crate::{CobbError, RunReport};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Writes the report for a run of the test `name` to `dir`, as a test suite
+/// with a test case per group (and one for the run as a whole, if something
+/// failed that wasn't in any particular group), and returns the file's path.
+/// Reruns of the same test replace it.
+pub(crate) fn write(
+    dir: &Path,
+    name: &str,
+    seed: u64,
+    report: &RunReport,
+    failures: &[CobbError],
+) -> std::io::Result<PathBuf> {
+    let mut flat = vec![];
+    flatten(failures, &mut flat);
+    let mut cases = report
+        .groups
+        .iter()
+        .map(|g| (format!("group {}", g.group), Some(g.group), g.wall_time))
+        .collect::<Vec<_>>();
+    // A group that failed before it got going won't have timings.
+    for e in &flat {
+        if let (Some(g), _, _) = e.location() {
+            if !cases.iter().any(|c| c.1 == Some(g)) {
+                cases.push((format!("group {}", g), Some(g), Duration::ZERO));
+            }
+        }
+    }
+    cases.sort_by_key(|c| c.1);
+    if flat.iter().any(|e| e.location().0.is_none()) {
+        cases.push(("run".to_string(), None, report.wall_time));
+    }
+    let failed = cases
+        .iter()
+        .filter(|c| flat.iter().any(|e| e.location().0 == c.1))
+        .count();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" time=\"{:.3}\">",
+        escape(name),
+        cases.len(),
+        failed,
+        report.wall_time.as_secs_f64()
+    );
+    let _ = writeln!(xml, "  <properties>");
+    let _ = writeln!(xml, "    <property name=\"seed\" value=\"{:#x}\"/>", seed);
+    let _ = writeln!(xml, "  </properties>");
+    for (case, group, time) in &cases {
+        let _ = write!(
+            xml,
+            "  <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+            escape(name),
+            case,
+            time.as_secs_f64()
+        );
+        let errors = flat
+            .iter()
+            .filter(|e| e.location().0 == *group)
+            .collect::<Vec<_>>();
+        if errors.is_empty() {
+            let _ = writeln!(xml, "/>");
+            continue;
+        }
+        let _ = writeln!(xml, ">");
+        for e in errors {
+            let message = format!("{:#}", e);
+            let first = message.lines().next().unwrap_or("");
+            let _ = writeln!(
+                xml,
+                "    <failure message=\"{}\">{}\n\nrerun with COBB_SEED={:#x}</failure>",
+                escape(first),
+                escape(&message),
+                seed
+            );
+        }
+        let _ = writeln!(xml, "  </testcase>");
+    }
+    let _ = writeln!(xml, "</testsuite>");
+
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{}.xml", crate::trace::file_name(name)));
+    std::fs::write(&path, xml)?;
+    Ok(path)
+}
+
+fn flatten<'a>(failures: &'a [CobbError], out: &mut Vec<&'a CobbError>) {
+    for e in failures {
+        match e {
+            CobbError::Multiple(inner) => flatten(inner, out),
+            e => out.push(e),
+        }
+    }
+}
+
+/// Escapes `s` for XML text and attributes, which does for HTML too.
+pub(crate) fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // Not allowed in XML at all.
+            c if (c as u32) < 0x20 && !matches!(c, '\n' | '\r' | '\t') => out.push('?'),
+            c => out.push(c),
+        }
+    }
+    out
+}
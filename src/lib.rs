@@ -1,11 +1,98 @@
+use std::panic::Location;
 use std::sync::{Arc, RwLock};
 use std::{
     mem::MaybeUninit,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
     thread,
+    time::{Duration, Instant},
 };
 use thread::JoinHandle;
 
+mod affinity;
+mod artifact;
+mod batch;
+mod calibrate;
+pub mod cli;
+mod compare;
+mod confidence;
+mod config;
+mod coop;
+mod corpus;
+mod coverage;
+mod cputime;
+mod determinism;
+mod disturb;
+mod dynamic;
+mod error;
+mod escalate;
+mod events;
+pub mod fuzz;
+pub mod harness;
+mod html;
+mod instrument;
+mod interleaving;
+mod junit;
+mod messages;
+mod minimize;
+mod observe;
+mod outcome;
+mod output;
+mod panics;
+mod pct;
+mod per_thread;
+mod preempt;
+mod priority;
+mod schedule;
+mod start;
+mod stats;
+mod suspend;
+pub mod sync;
+mod trace;
+mod violation;
+mod watchdog;
+pub use affinity::{Numa, Oversubscribe, Repin};
+pub use batch::{filter_matches, run_matrix, run_tests, run_tests_filtered, Matrix, TestSpec};
+pub use compare::{run_compare, Comparison, Divergence};
+pub use confidence::Confidence;
+pub use config::Overrides;
+pub use determinism::check_determinism;
+pub use disturb::Disturber;
+pub use dynamic::{run_test_dyn, DynState, DynTestCfg};
+pub use error::CobbError;
+pub use escalate::Escalation;
+pub use events::EventSink;
+pub use minimize::Minimize;
+pub use observe::Observer;
+pub use output::ThreadOutput;
+pub use panics::PanicDetails;
+pub use per_thread::{run_test_per_thread, PerThread, PerThreadCfg};
+pub use preempt::PreemptCfg;
+pub use priority::{LowPriority, Realtime};
+pub use schedule::{
+    MakeScheduler, RandomScheduler, ScheduleMode, Scheduler, SpAction, SpHint, SpInfo, SpWeights,
+};
+pub use start::{Stagger, StartMode, StartOrder};
+pub use stats::{GroupReport, Latency, RunReport, ThreadReport};
+pub use suspend::{FreezeCfg, SuspendCfg};
+pub use sync::maybe_fence;
+pub use trace::{Trace, TraceEvent};
+pub use violation::Violation;
+#[cfg(feature = "registry")]
+mod registry;
+#[cfg(feature = "registry")]
+pub use harness::harness;
+#[cfg(feature = "registry")]
+pub use registry::{registered_tests, run_registered};
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::messages::trace;
+    #[cfg(feature = "registry")]
+    pub use crate::registry::Registered;
+    #[cfg(feature = "registry")]
+    pub use inventory;
+}
+
 #[repr(C, align(64))]
 #[derive(Clone, Copy)]
 struct CachePad<T> {
@@ -45,12 +132,331 @@ pub struct TestCfg<T> {
     pub setup: fn() -> T,
     pub teardown: fn(&mut T),
     pub test: fn(&T, &TestCtx),
+    /// If non-empty, each iteration runs these in order instead of `test`.
+    /// Each phase runs for all of the sub-iterations, and then all the
+    /// participating threads wait for each other before moving on to the next
+    /// one. Useful for things like "fill then drain", or "write then verify".
+    pub phases: Vec<fn(&T, &TestCtx)>,
+    /// If set, thread 0 is the owner: at the start of each iteration it gets
+    /// to run this with `&mut T`, while the other threads wait, and then
+    /// everybody runs the test with `&T` as usual. This is for tests where one
+    /// side needs to mutate things that the others only ever read, like the
+    /// writer of a seqlock, without resorting to `UnsafeCell`.
+    ///
+    /// Thread 0 always participates when this is set, even with
+    /// `random_participation`.
+    pub owner: Option<fn(&mut T, &TestCtx)>,
     pub before_each: fn(&T),
-    pub after_each: fn(&T),
+    pub after_each: fn(&T, &IterCtx<'_>),
     pub name: Option<&'static str>,
     pub reprioritize: Option<PrioritizeMode>,
-    // TODO: flag for mucking with thread suspend/resume
-    // so that the os reorders too.
+    /// How often to reprioritize (and call `on_reprioritize`, and re-pin with
+    /// `Repin::OnReprioritize`). Every 200 iterations by default.
+    pub reprioritize_cadence: Cadence,
+    /// Reprioritize before the first iteration too, rather than having it run
+    /// with the initial `priorities`.
+    pub reprioritize_at_start: bool,
+    /// The priority each thread starts out with, by thread index. Threads past
+    /// the end of this start out high priority.
+    pub priorities: Vec<Priority>,
+    /// How the OS is told a thread is low priority. High priority threads
+    /// just get the default. Supported on Linux, Windows, and Apple
+    /// platforms. Ignored elsewhere.
+    pub low_priority: LowPriority,
+    /// Make high priority threads realtime, so they run ahead of everything
+    /// else on their core. Mixing realtime and ordinary threads starves the
+    /// ordinary ones far harder than any ordinary priorities can. This needs
+    /// privileges: if they're missing, cobb warns, and the threads are just
+    /// high priority. Supported on Linux. Ignored elsewhere.
+    ///
+    /// A realtime thread that spins never lets ordinary threads on its core
+    /// run, except for the little time the kernel sets aside for them
+    /// (`/proc/sys/kernel/sched_rt_runtime_us`).
+    pub realtime: Option<Realtime>,
+    /// Called at each reprioritization point (after `reprioritize` has been
+    /// applied, if set) with the iteration number and each thread's priority,
+    /// which it can change. This is for encoding specific priority patterns,
+    /// like "the writer is always low priority".
+    pub on_reprioritize: Option<fn(usize, &mut [Priority])>,
+    /// Gets told about groups starting, iterations finishing,
+    /// reprioritization and failures as the run goes, and can stop it early.
+    pub observer: Option<Arc<dyn Observer>>,
+    /// Run the priority inversion scenario: see `Inversion`. This overrides
+    /// `priorities`, `reprioritize` and `on_reprioritize`.
+    pub inversion: Option<Inversion>,
+    /// Move the runner threads to randomly picked CPUs every so often. Some
+    /// of them often end up on the same core and some not, which changes
+    /// which races can show up: threads on the same core only ever
+    /// interleave, while threads on different ones really run at once, and
+    /// see each other's writes late. Supported where `affinity` is, and
+    /// ignored with `Inversion::single_core`.
+    pub repin: Option<Repin>,
+    /// The CPU to pin each thread to, by thread index, so the test can pick
+    /// which of the contending threads share a core (or a cache). Threads
+    /// past the end of this aren't pinned. `repin` and
+    /// `Inversion::single_core` take precedence over this.
+    ///
+    /// Supported on Linux and Windows (for the first 64 CPUs). On macOS, it
+    /// sets affinity tags instead, which only hint which threads should share
+    /// a cache. Ignored elsewhere.
+    pub affinity: Vec<usize>,
+    /// With several `groups`, split the CPUs between them, keeping
+    /// hyperthreads of the same core together, so that each group's threads
+    /// contend with each other rather than with other groups'. Otherwise the
+    /// failure rates of the groups don't mean much on big machines. `repin`
+    /// and `Inversion::single_core` stick to the group's CPUs, and `affinity`
+    /// overrides this. Supported where `affinity` is.
+    pub partition_cores: bool,
+    /// Run several times as many threads as there are cores, which overrides
+    /// `threads` and `thread_range`. With that many threads, the OS has to
+    /// preempt them constantly, and for long stretches, which exposes bugs
+    /// that never show up with a core per thread. Confining the threads to a
+    /// few cores is supported where `affinity` is.
+    pub oversubscribe: Option<Oversubscribe>,
+    /// Extra threads to run alongside each group's, while iterations are
+    /// running, to put the memory system or the OS scheduler under pressure.
+    /// Races that never show up on an idle machine often do once the caches
+    /// keep getting evicted, the allocator's contended, or the run queues
+    /// are busy.
+    pub disturbers: Vec<Disturber>,
+    /// If set, record each iteration's start order, priorities, and what
+    /// every thread did at its schedule points (see `Trace`), and when an
+    /// iteration fails, write that to a file in this directory. Defaults to
+    /// the `COBB_TRACE_DIR` environment variable, if that's set when the test
+    /// runs.
+    pub trace_dir: Option<std::path::PathBuf>,
+    /// If set, the trace of each failing iteration is kept in this directory
+    /// (in a subdirectory named after the test), and every run replays the
+    /// ones there (as with `run_test_replay`) before doing anything else,
+    /// failing straight away if one of them still fails. That way, each
+    /// failure found turns into a regression test. Defaults to the
+    /// `COBB_CORPUS_DIR` environment variable, if that's set when the test
+    /// runs.
+    pub corpus_dir: Option<std::path::PathBuf>,
+    /// If set, a failing run writes a directory in here named after the test
+    /// and its seed, with everything needed to look into the failure: the
+    /// effective config, the seed, what failed (and the panic messages), and
+    /// the trace of each failing iteration, which has what every thread did.
+    /// The directory's path is printed with the failure. Defaults to the
+    /// `COBB_ARTIFACT_DIR` environment variable, if that's set when the test
+    /// runs.
+    pub artifact_dir: Option<std::path::PathBuf>,
+    /// If set, write what happens during the run as JSON, an object per line,
+    /// for CI tooling to read instead of the usual messages (which still get
+    /// printed). Each has an `"event"` of `"run_started"`, `"group_started"`,
+    /// `"iteration_failed"` (with null `iteration` and `seed` for failures
+    /// outside of one) or `"run_finished"`, and the test's `"test"` name.
+    /// Seeds are hex strings. Defaults to the `COBB_OUTPUT` environment
+    /// variable, if that's set to `json` (for stderr) or `json:<path>` when
+    /// the test runs.
+    pub events: Option<EventSink>,
+    /// If set, write a JUnit XML report to `<name>.xml` in here at the end of
+    /// the run, with a test case per group, for CI systems to show. Failures
+    /// that weren't in a particular group go in an extra `run` test case.
+    /// Defaults to the `COBB_JUNIT_DIR` environment variable, if that's set
+    /// when the test runs.
+    pub junit_dir: Option<std::path::PathBuf>,
+    /// If set, write an HTML report to `<name>.html` in here at the end of
+    /// the run, to look at in a browser: the seed and config, what failed,
+    /// each group's timings, a chart of how many distinct interleavings
+    /// turned up as the run went, the schedule point counts (as with
+    /// `sp_report`), and what each thread did in the failing iterations.
+    /// This keeps track of everything the other reports do, so it slows
+    /// things down a little. Defaults to the `COBB_HTML_DIR` environment
+    /// variable, if that's set when the test runs.
+    pub html_dir: Option<std::path::PathBuf>,
+    /// How many of its latest `trace!` messages each thread keeps, to print
+    /// if the iteration fails. 0 turns `trace!` off.
+    pub trace_messages: usize,
+    /// Keep what each thread writes with `TestCtx::stdout` and
+    /// `TestCtx::stderr` during an iteration, and if it fails, print it all,
+    /// marked with when it was written and by which thread, rather than
+    /// letting it go straight out mixed up together. (`println!` and friends
+    /// can't be captured per thread, so use `writeln!(ctx.stdout(), ...)`
+    /// instead.)
+    pub capture_output: bool,
+    /// The outcomes (see `TestCtx::outcome`) that ought to turn up. Any that
+    /// never do are pointed out at the end of the run, which often means a
+    /// race the test was meant to exercise can't actually happen.
+    pub expected_outcomes: Vec<&'static str>,
+    /// On machines with several NUMA nodes, confine the threads to them, and
+    /// say where the test state's memory goes. Races look very different
+    /// when some threads have to reach across to another node's memory.
+    /// This takes precedence over `partition_cores` and `oversubscribe`, but
+    /// not `affinity`. Only supported on Linux.
+    pub numa: Option<Numa>,
+    /// If set, each iteration benches a random subset of the threads (they
+    /// skip the test function for that iteration), so that contention levels
+    /// between 2 and `threads` get explored, rather than always the maximum.
+    pub random_participation: bool,
+    /// If set, each group runs with a random number of threads picked from
+    /// this range, instead of `threads`. Many races only show up at specific
+    /// concurrency levels, so this sweeps them without editing the config.
+    ///
+    /// When combined with `random_participation`, the number of threads
+    /// running each iteration is also kept within this range.
+    pub thread_range: Option<core::ops::RangeInclusive<usize>>,
+    /// If set, each thread runs a random number of sub-iterations from this
+    /// range every iteration (instead of `sub_iterations`), so that threads
+    /// aren't all doing their k-th operation at around the same time.
+    ///
+    /// Be careful combining this with `TestCtx::barrier`, since threads will
+    /// reach it a different number of times.
+    pub sub_iteration_range: Option<core::ops::RangeInclusive<usize>>,
+    /// If set, the test fails (rather than hanging forever) when an iteration
+    /// takes longer than this, reporting the threads that hadn't finished it.
+    /// Handy when testing things that can deadlock.
+    ///
+    /// The stuck threads are leaked, since there's no way to stop them.
+    pub iteration_timeout: Option<Duration>,
+    /// If set, the test stops early (after finishing the iteration it's on)
+    /// once it's been running this long, and reports how many iterations it
+    /// got through. This puts an upper bound on how long CI can take, no
+    /// matter what `COBB_ITERATIONS` is.
+    pub max_total_time: Option<Duration>,
+    /// If set, a watchdog thread aborts the process when no iteration has
+    /// finished for this long, after printing what each runner thread was
+    /// last doing: how many iterations it finished, whether it's in the test,
+    /// and the last `TestCtx::sp` it reached. Without this, a deadlock in the
+    /// code being tested is just a silent hang.
+    pub watchdog: Option<Duration>,
+    /// Makes the scheduler used for `TestCtx::sp`, instead of the default
+    /// `RandomScheduler`. It's called on each runner thread, with its index and
+    /// the number of threads in the group.
+    pub scheduler: Option<MakeScheduler>,
+    /// Which of cobb's schedulers to use. Ignored if `scheduler` is set.
+    pub schedule: ScheduleMode,
+    /// The master seed, which everything random about the run (start orders,
+    /// priorities, schedule point decisions, `TestCtx::rng`, ...) comes from.
+    /// If it's not set, a random one is used. Either way, it's printed at the
+    /// start of the run and when it fails, and the `COBB_SEED` environment
+    /// variable (or `--seed`, with `cli`) overrides it, so a failing run can
+    /// be repeated.
+    ///
+    /// Only the cooperative schedulers make that exact: otherwise, the OS
+    /// still decides when threads actually run.
+    pub seed: Option<u64>,
+    /// The overrides applied with `Overrides::apply` (from `cli::parse`,
+    /// say). These win over `COBB_CONFIG` and the environment variables,
+    /// which are only looked at when the test runs, so flags on the command
+    /// line beat a stale `COBB_CONFIG` or an exported `COBB_SEED`.
+    pub overrides: Overrides,
+    /// What the default scheduler does at schedule points. Ignored if
+    /// `scheduler` is set.
+    pub sp_weights: SpWeights,
+    /// The chance that each schedule point does anything at all, on top of
+    /// the one passed to `TestCtx::sp_with`. As with that, only the default
+    /// scheduler pays attention to it.
+    pub sp_probability: f64,
+    /// How much to print about what the driver is up to: at 1, progress every
+    /// tenth of the run or every few seconds, whichever comes first; at 2,
+    /// also things like reprioritization and escalation; and at 3, everything
+    /// it does each iteration. Defaults to 0, or the `COBB_VERBOSE`
+    /// environment variable if that's set when the test runs.
+    pub verbosity: u8,
+    /// At the end of the run, print how many times each `TestCtx::sp` call
+    /// was hit, and how often the scheduler actually yielded or slept there,
+    /// to check that your schedule points are doing something. Defaults to
+    /// whether `COBB_SP_REPORT` was set when building.
+    pub sp_report: bool,
+    /// At the end of the run, print how many distinct interleavings the
+    /// iterations went through, going by the order the threads started in and
+    /// hit schedule points (and did the things passed to `TestCtx::observe`).
+    /// A test that only ever goes a handful of ways probably needs more
+    /// schedule points. Keeping track has threads touch shared state at every
+    /// schedule point, which changes the timing a little. Defaults to whether
+    /// `COBB_INTERLEAVING_REPORT` was set when building.
+    pub interleaving_report: bool,
+    /// At the end of a successful run, print a table of how long things
+    /// took: each group's wall time, time in `setup` and `teardown`, time its
+    /// threads spent waiting to be started versus running the test, how much
+    /// they overlapped, and how long iterations took, then each thread's
+    /// running and waiting times, and how much of the CPU it got at each
+    /// priority (flagging threads that low priority starved). The same
+    /// numbers are in the `RunReport` that `try_run_test` returns either way.
+    /// Defaults to whether `COBB_TIMING_REPORT` was set when building.
+    pub timing_report: bool,
+    /// The chance that waiting on a `cobb::sync::Condvar` returns right away,
+    /// as a spurious wakeup, to check that the code under test handles them.
+    pub spurious_wakeups: f64,
+    /// The chance that `cobb::maybe_fence` emits a fence (half the time just
+    /// a compiler fence, half the time a real `SeqCst` one).
+    pub fence_chance: f64,
+    /// Randomly suspend runner threads from outside, wherever they happen to
+    /// be in the test, and resume them a little later, so that the OS
+    /// reorders things too, with much longer preemption windows than
+    /// schedule points can make.
+    ///
+    /// Supported on Linux, where it uses `SIGURG` (replacing any handler for
+    /// it), Windows, and Apple platforms. Ignored elsewhere.
+    pub suspend: Option<SuspendCfg>,
+    /// Interrupt the process with a timer signal every so often, so the
+    /// kernel gets to preempt runner threads far more often than it would on
+    /// its own. It's cheaper than `suspend`, but the threads only get
+    /// rescheduled when there's someone else waiting to run.
+    ///
+    /// Supported on Linux, Android, Apple platforms and the BSDs, where it uses
+    /// `setitimer` and `SIGALRM` (replacing any handler for it while the test
+    /// runs). Ignored elsewhere.
+    ///
+    /// Interrupted syscalls are restarted, except for the ones that wait with
+    /// a timeout (`nanosleep`, `poll`, `select`, `epoll_wait`, `futex` with a
+    /// timeout, and so on), which fail with `EINTR` instead, so the code under
+    /// test has to cope with that. `std::thread::sleep` does.
+    pub preempt: Option<PreemptCfg>,
+    /// How often `TestCtx::freeze_candidate` freezes the thread that calls
+    /// it, and for how long.
+    pub freeze: FreezeCfg,
+    /// How spread out the thread starts are in each iteration. By default
+    /// they're started back to back (in a random order), which already
+    /// staggers them a little. Ignored with `StartMode::SpinGate`.
+    pub start_stagger: Stagger,
+    /// How threads are started each iteration.
+    pub start_mode: StartMode,
+    /// What order threads are started in each iteration. `random_participation`
+    /// benches the threads at the end of this order, and the `Random`,
+    /// `MostlyHi`, `MostlyLo` and `Count` modes of `reprioritize` give the
+    /// ones at the start of the previous iteration's order high priority. So
+    /// with an order that isn't random, they don't pick random threads either.
+    pub start_order: StartOrder,
+    /// Scales how hard threads get perturbed: sleep durations, spin counts,
+    /// and the chances of sleeping, freezing, being suspended or preempted,
+    /// fences and spurious wakeups. Below 1.0 makes for a quicker smoke test,
+    /// and above it for a harsher soak. Defaults to `COBB_INTENSITY` if it was
+    /// set when building, and 1.0 otherwise.
+    pub intensity: f32,
+    /// Start with mild perturbation and ramp it up while the test keeps
+    /// passing, to get through the cheap interleavings quickly and still end
+    /// up somewhere harsh. This scales the schedule points, `freeze`, and how
+    /// often `reprioritize` happens. Off by default.
+    pub escalate: Option<Escalation>,
+    /// Time the test fn during a short warmup (the first tenth of the
+    /// iterations, up to 16), and then size sleeps at schedule points and in
+    /// `freeze_candidate` relative to it, at half to twice as long as a
+    /// typical call. That way the same config shakes things up about as much
+    /// whether the test takes 50ns or 50µs. Schedule points don't do
+    /// anything during the warmup. Defaults to whether `COBB_CALIBRATE` was
+    /// set when building.
+    pub calibrate: bool,
+    /// When the test fails, go looking for a smaller config that still fails
+    /// (with fewer threads, fewer sub-iterations, and a lower
+    /// `sp_probability`), by running shrunk versions of the test, and print
+    /// the smallest one found. Only versions that fail the same way (the same
+    /// panic location and message, give or take any numbers in it) count. A
+    /// failure that needs 2 threads and 1 sub-iteration is a lot easier to
+    /// understand than one that needs 16 and 100. With `trace_dir`, it then
+    /// takes the trace of a failing iteration and drops as many of the
+    /// schedule points' perturbations as it can while it still fails, and
+    /// writes that there too, to replay with `run_test_replay`. Defaults to
+    /// `Minimize::default()` if `COBB_MINIMIZE` was set when building.
+    pub minimize: Option<Minimize>,
+    /// Instead of running `iterations` iterations, run as many as it takes to
+    /// rule out a failure rate per iteration of at least
+    /// `Confidence::failure_rate`, with `Confidence::level` confidence (split
+    /// between the groups), and then print the bound that was achieved, which
+    /// is lower if the run was stopped early. With the defaults, that's about
+    /// 46000 iterations.
+    pub confidence: Option<Confidence>,
 }
 
 impl<T> Clone for TestCfg<T> {
@@ -62,21 +468,164 @@ impl<T> Clone for TestCfg<T> {
             groups: self.groups,
             teardown: self.teardown,
             test: self.test,
+            phases: self.phases.clone(),
+            owner: self.owner,
             setup: self.setup,
             name: self.name,
             before_each: self.before_each,
             after_each: self.after_each,
-            reprioritize: self.reprioritize,
+            reprioritize: self.reprioritize.clone(),
+            reprioritize_cadence: self.reprioritize_cadence.clone(),
+            reprioritize_at_start: self.reprioritize_at_start,
+            priorities: self.priorities.clone(),
+            low_priority: self.low_priority,
+            realtime: self.realtime,
+            on_reprioritize: self.on_reprioritize,
+            observer: self.observer.clone(),
+            inversion: self.inversion,
+            repin: self.repin,
+            affinity: self.affinity.clone(),
+            partition_cores: self.partition_cores,
+            oversubscribe: self.oversubscribe,
+            disturbers: self.disturbers.clone(),
+            trace_dir: self.trace_dir.clone(),
+            corpus_dir: self.corpus_dir.clone(),
+            artifact_dir: self.artifact_dir.clone(),
+            events: self.events.clone(),
+            junit_dir: self.junit_dir.clone(),
+            html_dir: self.html_dir.clone(),
+            trace_messages: self.trace_messages,
+            capture_output: self.capture_output,
+            expected_outcomes: self.expected_outcomes.clone(),
+            numa: self.numa,
+            random_participation: self.random_participation,
+            thread_range: self.thread_range.clone(),
+            sub_iteration_range: self.sub_iteration_range.clone(),
+            iteration_timeout: self.iteration_timeout,
+            max_total_time: self.max_total_time,
+            watchdog: self.watchdog,
+            scheduler: self.scheduler,
+            schedule: self.schedule,
+            seed: self.seed,
+            overrides: self.overrides.clone(),
+            sp_weights: self.sp_weights,
+            sp_probability: self.sp_probability,
+            verbosity: self.verbosity,
+            sp_report: self.sp_report,
+            interleaving_report: self.interleaving_report,
+            timing_report: self.timing_report,
+            spurious_wakeups: self.spurious_wakeups,
+            fence_chance: self.fence_chance,
+            suspend: self.suspend,
+            preempt: self.preempt,
+            freeze: self.freeze,
+            start_stagger: self.start_stagger.clone(),
+            start_mode: self.start_mode,
+            start_order: self.start_order,
+            intensity: self.intensity,
+            escalate: self.escalate,
+            calibrate: self.calibrate,
+            minimize: self.minimize,
+            confidence: self.confidence,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, PartialOrd, Copy)]
+/// The priority of a test thread. See `TestCfg::priorities`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Priority {
+    Hi,
+    Lo,
+}
+
+/// The classic priority inversion setup, for `TestCfg::inversion`: one low
+/// priority thread (the `holder`) takes the resource the test is about, while
+/// all the others are high priority and spin or contend on it. If the holder
+/// never gets to run, because the high priority threads are hogging the CPU,
+/// they livelock.
+///
+/// It's up to the test to have the holder (`TestCtx::thread_index() ==
+/// holder`) take the resource, and the others contend on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Inversion {
+    pub holder: usize,
+    /// Pin all the group's threads to the same core, so that the holder has
+    /// to compete with the others for it. Supported where `TestCfg::affinity`
+    /// is.
+    pub single_core: bool,
+}
+
+impl Default for Inversion {
+    fn default() -> Self {
+        Self {
+            holder: 0,
+            single_core: true,
+        }
+    }
+}
+
+/// How often something happens, in iterations. See
+/// `TestCfg::reprioritize_cadence`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cadence {
+    Every(usize),
+    /// After a random number of iterations in this range, picked anew each
+    /// time.
+    Between(core::ops::RangeInclusive<usize>),
+    /// At each iteration, with this chance.
+    Chance(f64),
+}
+
+impl Default for Cadence {
+    fn default() -> Self {
+        Cadence::Every(200)
+    }
+}
+
+impl Cadence {
+    /// How many iterations to wait for next time, `level` times sooner than
+    /// usual (for `TestCfg::escalate`). Always 1 for `Chance`.
+    fn gap(&self, level: f64, rng: &mut Rng) -> usize {
+        let n = match self {
+            Cadence::Every(n) => *n,
+            Cadence::Between(r) => rng.between(*r.start()..*r.end() + 1),
+            Cadence::Chance(_) => 1,
+        };
+        ((n as f64 / level.max(1.0)) as usize).max(1)
+    }
+
+    /// Whether it's time, given it's been `since` iterations since last time.
+    fn due(&self, since: usize, gap: usize, level: f64, rng: &mut Rng) -> bool {
+        match self {
+            Cadence::Chance(p) => rng.chance((p * level.max(1.0)).min(1.0)),
+            _ => since >= gap,
+        }
+    }
+}
+
+// Comparing `Custom`s compares function addresses, which is fine for telling
+// whether a config changed.
+#[allow(unknown_lints, unpredictable_function_pointer_comparisons)]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum PrioritizeMode {
     Random,
     MostlyLo,
     MostlyHi,
     Count(usize),
+    /// Flip every thread's priority.
+    Alternating,
+    /// Shift the priorities along by one thread, so the last thread gets the
+    /// first one's. If they're all the same, just the first thread is made
+    /// high priority, so there's something to shift.
+    RoundRobin,
+    /// Give each thread the priority in here (true being high), by thread
+    /// index. Threads past the end are high priority. This is for patterns
+    /// like "the single writer is always low priority".
+    PerThreadPattern(Vec<bool>),
+    /// Call this with the iteration number and each thread's priority (true
+    /// being high), which it can change. Unlike `TestCfg::on_reprioritize`,
+    /// this replaces the built-in modes rather than going after them.
+    Custom(fn(usize, &mut [bool])),
 }
 
 impl<T> Default for TestCfg<T> {
@@ -103,8 +652,10 @@ impl<T> Default for TestCfg<T> {
             setup: || panic!("please provide setup"),
             teardown: |_| {},
             before_each: |_| {},
-            after_each: |_| {},
+            after_each: |_, _| {},
             test: |_, _| {},
+            phases: vec![],
+            owner: None,
             name: None,
             reprioritize: match option_env!("COBB_REPRIORITIZE") {
                 None | Some("") | Some("0") => None,
@@ -119,21 +670,603 @@ impl<T> Default for TestCfg<T> {
                     s
                 ),
             },
+            reprioritize_cadence: Cadence::default(),
+            reprioritize_at_start: false,
+            priorities: vec![],
+            low_priority: LowPriority::default(),
+            realtime: None,
+            on_reprioritize: None,
+            observer: None,
+            inversion: None,
+            repin: None,
+            affinity: vec![],
+            partition_cores: false,
+            oversubscribe: None,
+            disturbers: vec![],
+            trace_dir: None,
+            corpus_dir: None,
+            artifact_dir: None,
+            events: None,
+            junit_dir: None,
+            html_dir: None,
+            trace_messages: 64,
+            capture_output: false,
+            expected_outcomes: vec![],
+            numa: None,
+            random_participation: false,
+            thread_range: None,
+            sub_iteration_range: None,
+            iteration_timeout: None,
+            max_total_time: None,
+            watchdog: None,
+            scheduler: None,
+            schedule: ScheduleMode::default(),
+            seed: None,
+            overrides: Overrides::default(),
+            sp_weights: SpWeights::default(),
+            sp_probability: 1.0,
+            verbosity: 0,
+            sp_report: matches!(option_env!("COBB_SP_REPORT"), Some(s) if !s.is_empty() && s != "0"),
+            interleaving_report: matches!(
+                option_env!("COBB_INTERLEAVING_REPORT"),
+                Some(s) if !s.is_empty() && s != "0"
+            ),
+            timing_report: matches!(option_env!("COBB_TIMING_REPORT"), Some(s) if !s.is_empty() && s != "0"),
+            spurious_wakeups: 0.0,
+            fence_chance: 0.5,
+            suspend: None,
+            preempt: None,
+            freeze: FreezeCfg::default(),
+            start_stagger: Stagger::None,
+            start_mode: StartMode::default(),
+            start_order: StartOrder::default(),
+            intensity: option_env!("COBB_INTENSITY")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(1.0),
+            escalate: None,
+            calibrate: matches!(option_env!("COBB_CALIBRATE"), Some(s) if !s.is_empty() && s != "0"),
+            minimize: matches!(option_env!("COBB_MINIMIZE"), Some(s) if !s.is_empty() && s != "0")
+                .then(Minimize::default),
+            confidence: None,
         }
     }
 }
 
 pub fn run_test<T: Send + Sync + 'static>(test: TestCfg<T>) {
+    let hooks = Hooks::from_cfg(&test);
+    run_with_hooks(test, hooks);
+}
+
+/// Like `run_test`, but returns why the test failed instead of panicking, or
+/// how it went if it didn't.
+pub fn try_run_test<T: Send + Sync + 'static>(test: TestCfg<T>) -> Result<RunReport, CobbError> {
+    let hooks = Hooks::from_cfg(&test);
+    try_run_with_hooks(test, hooks, None)
+}
+
+/// Runs a single iteration of `test` (in one group), with the iteration seed
+/// from a failure message, so it goes the same way as the one that failed as
+/// far as cobb's concerned: the same start order, participants, CPU
+/// assignments and schedule point decisions. That makes it handy for stepping
+/// through in a debugger.
+///
+/// Pass the same `TestCfg` as the run that failed. With
+/// `TestCfg::thread_range`, set `TestCfg::seed` to its master seed too, since
+/// that picks the number of threads. The threads' priorities aren't
+/// reproduced when they were picked in an earlier iteration, and of course
+/// the OS still schedules the threads however it likes (unless a cooperative
+/// `TestCfg::schedule` is used).
+pub fn run_single_iteration<T: Send + Sync + 'static>(mut test: TestCfg<T>, seed: u64) {
+    let hooks = Hooks::from_cfg(&test);
+    test.iterations = 1;
+    test.groups = 1;
+    let name = test.name.unwrap_or("cobb");
+    let replay = Replay {
+        seed,
+        ..Default::default()
+    };
+    if let Err(e) = try_run_with_hooks(test, hooks, Some(replay)) {
+        eprintln!("{}: {:#}", name, e);
+        std::panic::resume_unwind(Box::new(e));
+    }
+}
+
+/// Runs `test` for a single iteration the way a `Trace` says one went, with
+/// the same start order, priorities, benched threads, iteration seed and
+/// actions at each schedule point, so a failure recorded with
+/// `TestCfg::trace_dir` (say, in CI) can be brought back on another machine.
+/// Load the trace with `Trace::load`.
+///
+/// This sets the number of threads (and all the settings that would change
+/// the priorities or which threads take part) to match the trace. If the
+/// threads stop hitting the same schedule points as the trace says they did,
+/// cobb prints a warning, since the replay isn't following the trace anymore.
+/// The OS still decides exactly when the threads run, so a data race might
+/// need a few tries to show up again. As with `run_single_iteration`, the
+/// replayed iteration is number 0 as far as `TestCtx::iteration` goes.
+pub fn run_test_replay<T: Send + Sync + 'static>(mut test: TestCfg<T>, trace: Trace) {
+    let name = test.name.unwrap_or("cobb");
+    if let Err(e) = trace.check() {
+        let e = CobbError::ConfigInvalid(e);
+        eprintln!("{}: {}", name, e);
+        std::panic::resume_unwind(Box::new(e));
+    }
+    let hooks = Hooks::from_cfg(&test);
+    for_replay(&mut test, &trace);
+    let replay = Replay {
+        seed: trace.seed,
+        trace: Some(trace),
+        ..Default::default()
+    };
+    if let Err(e) = try_run_with_hooks(test, hooks, Some(replay)) {
+        eprintln!("{}: {:#}", name, e);
+        std::panic::resume_unwind(Box::new(e));
+    }
+}
+
+/// Makes `test` run a single iteration set up the way `trace` says.
+fn for_replay<T>(test: &mut TestCfg<T>, trace: &Trace) {
+    test.iterations = 1;
+    test.groups = 1;
+    test.threads = trace.threads.len();
+    test.thread_range = None;
+    test.oversubscribe = None;
+    test.priorities = trace.priorities.clone();
+    test.reprioritize = None;
+    test.on_reprioritize = None;
+    test.inversion = None;
+    test.random_participation = false;
+}
+
+type PhaseFn<T> = Arc<dyn Fn(&T, &TestCtx) + Send + Sync>;
+type OwnerFn<T> = Arc<dyn Fn(&mut T, &TestCtx) + Send + Sync>;
+type AfterEachFn<T> = Arc<dyn Fn(&T, &IterCtx<'_>) + Send + Sync>;
+
+/// The callbacks of a test. These come from the `TestCfg` for `run_test`, but
+/// don't have to be plain `fn`s, which lets `run_test_dyn` share the driver.
+struct Hooks<T> {
+    /// Gets passed the number of threads in the group.
+    setup: Arc<dyn Fn(usize) -> T + Send + Sync>,
+    teardown: Arc<dyn Fn(&mut T) + Send + Sync>,
+    phases: Vec<PhaseFn<T>>,
+    owner: Option<OwnerFn<T>>,
+    before_each: Arc<dyn Fn(&T) + Send + Sync>,
+    after_each: AfterEachFn<T>,
+}
+
+impl<T> Clone for Hooks<T> {
+    fn clone(&self) -> Self {
+        Self {
+            setup: Arc::clone(&self.setup),
+            teardown: Arc::clone(&self.teardown),
+            phases: self.phases.clone(),
+            owner: self.owner.clone(),
+            before_each: Arc::clone(&self.before_each),
+            after_each: Arc::clone(&self.after_each),
+        }
+    }
+}
+
+impl<T: 'static> Hooks<T> {
+    fn from_cfg(test: &TestCfg<T>) -> Self {
+        let phases = if test.phases.is_empty() {
+            vec![test.test]
+        } else {
+            test.phases.clone()
+        };
+        let setup = test.setup;
+        Self {
+            setup: Arc::new(move |_| setup()),
+            teardown: Arc::new(test.teardown),
+            phases: phases
+                .into_iter()
+                .map(|f| Arc::new(f) as PhaseFn<T>)
+                .collect(),
+            owner: test.owner.map(|f| Arc::new(f) as OwnerFn<T>),
+            before_each: Arc::new(test.before_each),
+            after_each: Arc::new(test.after_each),
+        }
+    }
+}
+
+/// State shared by all the threads of a run, across groups.
+struct RunShared {
+    seed: u64,
+    stop: AtomicBool,
+    deadline: Option<Instant>,
+    violations: violation::Violations,
+    failures: std::sync::Mutex<Vec<CobbError>>,
+    /// Schedule point counts, if `TestCfg::sp_report` is set.
+    sites: Option<coverage::Sites>,
+    observer: Option<Arc<dyn Observer>>,
+    /// If `TestCfg::events` is set.
+    events: Option<events::Events>,
+    /// Where each group leaves its timings.
+    stats: Vec<stats::Slot>,
+    /// The number of iterations that have passed, across groups.
+    passed: AtomicUsize,
+    /// If `TestCfg::interleaving_report` is set.
+    interleavings: Option<interleaving::Interleavings>,
+    outcomes: outcome::Outcomes,
+    spurious_wakeups: f64,
+    fence_chance: f64,
+    intensity: f64,
+    sp_probability: f64,
+    /// The `TestCfg::escalate` scale, on top of `intensity`.
+    level: escalate::Level,
+    /// The test fn timings, if `TestCfg::calibrate` is set.
+    calibration: Option<calibrate::Calibration>,
+    replay: Option<Replay>,
+    /// The traces of the failing iterations, if `TestCfg::artifact_dir` is
+    /// set.
+    failed_traces: std::sync::Mutex<Vec<Trace>>,
+    /// With the `tracing` feature, the run's span, which the groups' are in.
+    span: instrument::Span,
+}
+
+/// What `run_single_iteration`, `run_test_replay` or `fuzz::run` is
+/// replaying.
+#[derive(Default)]
+struct Replay {
+    /// The iteration seed to use for every iteration.
+    seed: u64,
+    trace: Option<Trace>,
+    /// The fuzzer input making the scheduling decisions.
+    input: Option<Arc<fuzz::Input>>,
+    /// Don't announce it, since there's going to be a lot of them.
+    quiet: bool,
+    /// Where to leave each iteration's trace, for `check_determinism`.
+    capture: Option<Arc<std::sync::Mutex<Vec<Trace>>>>,
+}
+
+/// The things that get their own random numbers, derived from the master
+/// seed (see `RunShared::seed_for`), or an iteration's seed.
+#[derive(Clone, Copy)]
+enum Stream {
+    Driver,
+    Sp,
+    User,
+    Suspender,
+    Disturbers,
+    Iteration,
+    Coop,
+}
+
+/// The seed for `stream` in group `group` (and for thread or iteration
+/// `index`, for the ones there are several of), out of `seed`.
+fn derive_seed(seed: u64, stream: Stream, group: usize, index: usize) -> u64 {
+    let seed = split_seed(seed, stream as u64);
+    let seed = split_seed(seed, group as u64);
+    split_seed(seed, index as u64)
+}
+
+/// A seed for the `part`th thing derived from `seed`. Each step goes through
+/// the splitmix64 finalizer, so seeds that differ only in `part`, or that were
+/// derived along different paths, give unrelated sequences.
+pub(crate) fn split_seed(seed: u64, part: u64) -> u64 {
+    splitmix64(seed ^ splitmix64(part.wrapping_add(0x9E37_79B9_7F4A_7C15)))
+}
+
+fn splitmix64(z: u64) -> u64 {
+    let z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+impl RunShared {
+    fn seed_for(&self, stream: Stream, group: usize, index: usize) -> u64 {
+        derive_seed(self.seed, stream, group, index)
+    }
+
+    /// The seed everything random about iteration `rep` of group `group`
+    /// comes from.
+    fn iteration_seed(&self, group: usize, rep: usize) -> u64 {
+        match &self.replay {
+            Some(r) => r.seed,
+            None => self.seed_for(Stream::Iteration, group, rep),
+        }
+    }
+
+    fn fail(&self, e: CobbError) {
+        instrument::failure(&e);
+        if let Some(o) = &self.observer {
+            o.on_failure(&e);
+        }
+        if let Some(ev) = &self.events {
+            ev.failure(&e);
+        }
+        self.failures
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .push(e);
+    }
+}
+
+/// Runs `f` (one of the callbacks a group's driver calls), recording an error
+/// made by `err` from the panic message and details if it panics.
+fn run_hook(
+    run: &RunShared,
+    err: impl FnOnce(String, Option<Arc<PanicDetails>>) -> CobbError,
+    f: impl FnOnce(),
+) -> bool {
+    match panics::catch(f) {
+        Ok(()) => true,
+        Err((message, details)) => {
+            run.fail(err(message, Some(details)));
+            false
+        }
+    }
+}
+
+fn validate<T>(test: &TestCfg<T>) -> Result<(), String> {
+    if let Some(r) = &test.thread_range {
+        if *r.start() < 1 || r.is_empty() {
+            return Err(format!(
+                "thread_range must be non-empty and start at 1 or more, got {:?}",
+                r
+            ));
+        }
+    }
+    if let Some(r) = &test.sub_iteration_range {
+        if r.is_empty() {
+            return Err(format!(
+                "sub_iteration_range must not be empty, got {:?}",
+                r
+            ));
+        }
+    }
+    if !(test.intensity >= 0.0 && test.intensity.is_finite()) {
+        return Err(format!(
+            "intensity must be a finite number, 0 or more, got {}",
+            test.intensity
+        ));
+    }
+    if !(0.0..=1.0).contains(&test.sp_probability) {
+        return Err(format!(
+            "sp_probability must be between 0 and 1, got {}",
+            test.sp_probability
+        ));
+    }
+    if let Some(e) = &test.escalate {
+        e.validate()?;
+    }
+    if let Some(c) = &test.confidence {
+        c.validate()?;
+    }
+    match &test.reprioritize_cadence {
+        Cadence::Every(0) => return Err("reprioritize_cadence must not be Every(0)".into()),
+        Cadence::Between(r) if r.is_empty() || *r.start() == 0 => {
+            return Err(format!(
+                "reprioritize_cadence must be a non-empty range starting at 1 or more, got {:?}",
+                r
+            ));
+        }
+        Cadence::Chance(p) if !(0.0..=1.0).contains(p) => {
+            return Err(format!(
+                "reprioritize_cadence chance must be between 0 and 1, got {}",
+                p
+            ));
+        }
+        _ => {}
+    }
+    if let Some(o) = &test.oversubscribe {
+        if o.factor == 0 || o.cores == Some(0) {
+            return Err(format!(
+                "oversubscribe needs at least one thread and one core, got {:?}",
+                o
+            ));
+        }
+    }
+    if let Some(inv) = &test.inversion {
+        let threads = test
+            .thread_range
+            .as_ref()
+            .map_or(test.threads, |r| *r.end());
+        if inv.holder >= threads {
+            return Err(format!(
+                "inversion holder must be one of the {} threads, got {}",
+                threads, inv.holder
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Applies `TestCfg::intensity` to the knobs other than the schedule points
+/// (which scale it as they go).
+fn apply_intensity<T>(test: &mut TestCfg<T>) {
+    let k = f64::from(test.intensity);
+    if k == 1.0 {
+        return;
+    }
+    let chance = |p: f64| (p * k).min(1.0);
+    test.fence_chance = chance(test.fence_chance);
+    test.spurious_wakeups = chance(test.spurious_wakeups);
+    test.freeze.chance = chance(test.freeze.chance);
+    test.freeze.duration = test.freeze.duration.mul_f64(k);
+    if let Some(s) = &mut test.suspend {
+        // Suspend more often, as well as for longer.
+        s.every = s.every.div_f64(k.max(0.001));
+        s.max_pause = s.max_pause.mul_f64(k);
+    }
+    if let Some(p) = &mut test.preempt {
+        p.min = p.min.div_f64(k.max(0.001));
+        p.max = p.max.div_f64(k.max(0.001));
+    }
+}
+
+fn run_with_hooks<T: Send + Sync + 'static>(test: TestCfg<T>, hooks: Hooks<T>) {
+    let name = test.name.unwrap_or("cobb");
+    if let Err(e) = try_run_with_hooks(test, hooks, None) {
+        eprintln!("{}: {:#}", name, e);
+        std::panic::resume_unwind(Box::new(e));
+    }
+}
+
+fn try_run_with_hooks<T: Send + Sync + 'static>(
+    mut test: TestCfg<T>,
+    hooks: Hooks<T>,
+    replay: Option<Replay>,
+) -> Result<RunReport, CobbError> {
+    config::apply_env_config(&mut test).map_err(CobbError::ConfigInvalid)?;
+    validate(&test).map_err(CobbError::ConfigInvalid)?;
+    // Pick it here rather than in `run_validated`, so the minimizer's
+    // choices come from it too.
+    test.seed.get_or_insert_with(|| Rng::new().gen());
+    if let (Some(dir), None) = (&test.corpus_dir, &replay) {
+        replay_corpus(dir, &test, &hooks)?;
+    }
+    let minimize = match (test.minimize, &replay) {
+        (Some(m), None) => Some((m, test.clone(), hooks.clone())),
+        _ => None,
+    };
+    let res = run_validated(test, hooks, replay);
+    if let (Err(e), Some((m, test, hooks))) = (&res, minimize) {
+        minimize::run(m, test, hooks, e);
+    }
+    res
+}
+
+/// Replays the entries in the corpus in `dir` for `test`, stopping at the
+/// first that fails.
+fn replay_corpus<T: Send + Sync + 'static>(
+    dir: &std::path::Path,
+    test: &TestCfg<T>,
+    hooks: &Hooks<T>,
+) -> Result<(), CobbError> {
+    let name = test.name.unwrap_or("cobb");
+    let entries = corpus::entries(dir, name);
+    if !entries.is_empty() {
+        eprintln!("{}: replaying the corpus ({} entries)", name, entries.len());
+    }
+    for (path, trace) in entries {
+        if let Err(e) = trace.check() {
+            eprintln!("{}: skipping corpus entry {}: {}", name, path.display(), e);
+            continue;
+        }
+        let mut cfg = test.clone();
+        for_replay(&mut cfg, &trace);
+        cfg.corpus_dir = None;
+        cfg.minimize = None;
+        let replay = Replay {
+            seed: trace.seed,
+            trace: Some(trace),
+            ..Default::default()
+        };
+        if let Err(e) = run_validated(cfg, hooks.clone(), Some(replay)) {
+            eprintln!("{}: corpus entry {} still fails", name, path.display());
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Runs a test whose config has been checked.
+fn run_validated<T: Send + Sync + 'static>(
+    mut test: TestCfg<T>,
+    hooks: Hooks<T>,
+    replay: Option<Replay>,
+) -> Result<RunReport, CobbError> {
+    let start = Instant::now();
+    apply_intensity(&mut test);
+    let seed = test.seed.unwrap_or_else(|| Rng::new().gen());
+    let name = test.name.unwrap_or("cobb");
+    let confidence = test.confidence.filter(|_| replay.is_none());
+    let timing_report = test.timing_report;
+    if let Some(c) = &confidence {
+        let total = c.iterations();
+        test.iterations = total.div_ceil(test.groups.max(1));
+        eprintln!(
+            "{}: running {} iterations, to rule out a failure rate of {:e} with {}% confidence",
+            name,
+            test.iterations * test.groups,
+            c.failure_rate,
+            c.level * 100.0
+        );
+    }
+    match &replay {
+        Some(Replay { quiet: true, .. }) => {}
+        Some(Replay { trace: Some(t), .. }) => eprintln!(
+            "{}: replaying the trace of group {} iteration {}",
+            name, t.group, t.iteration
+        ),
+        Some(r) => eprintln!("{}: replaying iteration seed {:#x}", name, r.seed),
+        None => eprintln!("{}: using seed {:#x}", name, seed),
+    }
+    // There'd be a lot of these for quiet replays, and nothing to say.
+    let events = match (&test.events, &replay) {
+        (_, Some(Replay { quiet: true, .. })) | (None, _) => None,
+        (Some(sink), _) => match events::Events::open(sink, name) {
+            Ok(ev) => Some(ev),
+            Err(e) => {
+                eprintln!("{}: failed to open the event output: {}", name, e);
+                None
+            }
+        },
+    };
+    if let Some(ev) = &events {
+        let event = ev
+            .event("run_started")
+            .seed("seed", seed)
+            .num("groups", test.groups.max(1) as u64)
+            .num("iterations", test.iterations as u64)
+            .bool("replay", replay.is_some());
+        ev.emit(event);
+    }
+    let (junit_dir, html_dir) = match &replay {
+        Some(Replay { quiet: true, .. }) => (None, None),
+        _ => (test.junit_dir.clone(), test.html_dir.clone()),
+    };
+    let (sp_report, interleaving_report) = (test.sp_report, test.interleaving_report);
+    let expected_outcomes = std::mem::take(&mut test.expected_outcomes);
+    // The test's gone by the time there's a failure to write about.
+    let artifact_dir = test.artifact_dir.clone();
+    let config = match artifact_dir.is_some() || html_dir.is_some() {
+        true => artifact::describe(&test),
+        false => String::new(),
+    };
+    let run = Arc::new(RunShared {
+        seed,
+        stop: AtomicBool::new(false),
+        deadline: test.max_total_time.map(|t| Instant::now() + t),
+        violations: Default::default(),
+        failures: Default::default(),
+        sites: (sp_report || html_dir.is_some()).then(Default::default),
+        observer: test.observer.clone(),
+        events,
+        stats: (0..test.groups.max(1))
+            .map(|_| stats::Slot::new())
+            .collect(),
+        passed: AtomicUsize::new(0),
+        outcomes: Default::default(),
+        interleavings: (interleaving_report || html_dir.is_some())
+            .then(|| interleaving::Interleavings::new(test.groups)),
+        spurious_wakeups: test.spurious_wakeups,
+        fence_chance: test.fence_chance,
+        intensity: f64::from(test.intensity),
+        sp_probability: test.sp_probability,
+        level: escalate::Level::new(test.escalate.as_ref()),
+        calibration: test.calibrate.then(Default::default),
+        replay,
+        failed_traces: Default::default(),
+        span: instrument::Span::run(name, seed),
+    });
+    let run_span = run.span.clone();
+    let _run_entered = run_span.enter();
+    let mut failed = vec![];
     if test.groups <= 1 || cfg!(miri) {
-        run_group(test, 0);
+        if let Err(e) = panics::catch(|| run_group(test, hooks, 0, Arc::clone(&run))) {
+            failed.push((e, 0));
+        }
     } else {
-        let name = test.name.unwrap_or("cobb");
         let join_handles = (0..test.groups)
             .map(|tg| {
                 let test_for_group = test.clone();
+                let hooks = hooks.clone();
+                let run = Arc::clone(&run);
                 let jh = std::thread::Builder::new()
                     .name(format!("{} group {} driver", name, tg))
-                    .spawn(move || run_group(test_for_group, tg))
+                    .spawn(move || panics::catch(|| run_group(test_for_group, hooks, tg, run)))
                     .unwrap_or_else(|e| {
                         panic!("Failed to launch driver for test group {}: {:?}", tg, e)
                     });
@@ -141,39 +1274,174 @@ pub fn run_test<T: Send + Sync + 'static>(test: TestCfg<T>) {
             })
             .collect::<Vec<_>>();
 
-        let mut failed = vec![];
         for (jh, group_idx) in join_handles {
-            jh.join().unwrap_or_else(|e| {
-                eprintln!(
-                    "{}: Group {} failed with error: {}",
-                    name,
-                    group_idx,
-                    extract_msg(&e)
-                );
-                failed.push((e, group_idx));
-            });
+            match jh.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => failed.push((e, group_idx)),
+                Err(e) => {
+                    failed.push(((extract_msg(&*e), PanicDetails::from_payload(e)), group_idx))
+                }
+            }
         }
-        if !failed.is_empty() {
-            eprintln!(
-                "{}: {} groups failed: {:?}",
-                name,
-                failed.len(),
-                failed.iter().map(|f| f.1).collect::<Vec<_>>()
-            );
-            std::panic::resume_unwind(failed.pop().unwrap().0);
+    }
+    let mut failures = std::mem::take(
+        &mut *run
+            .failures
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner),
+    );
+    failures.extend(failed.into_iter().map(|((message, details), group)| {
+        CobbError::HookPanicked {
+            group,
+            hook: "driver",
+            iteration: None,
+            message,
+            details: Some(details),
         }
+    }));
+    if let (Some(sites), true) = (&run.sites, sp_report) {
+        eprintln!("{}: {}", name, sites.report());
+    }
+    if let (Some(i), true) = (&run.interleavings, interleaving_report) {
+        eprintln!("{}: {}", name, i.report());
+    }
+    if let Some(e) = run.violations.to_error() {
+        if let Some(o) = &run.observer {
+            o.on_failure(&e);
+        }
+        if let Some(ev) = &run.events {
+            ev.failure(&e);
+        }
+        failures.push(e);
+    }
+    if let (Some(c), true) = (&confidence, failures.is_empty()) {
+        let passed = run.passed.load(Ordering::Relaxed);
+        eprintln!(
+            "{}: {} iterations passed, so with {}% confidence the failure rate is below {:.2e}",
+            name,
+            passed,
+            c.level * 100.0,
+            c.bound(passed)
+        );
+    }
+    let traces = std::mem::take(
+        &mut *run
+            .failed_traces
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner),
+    );
+    if !failures.is_empty() {
+        eprintln!(
+            "{}: failed with seed {:#x} (set COBB_SEED={:#x} to run it again)",
+            name, seed, seed
+        );
+        if let Some(dir) = &artifact_dir {
+            match artifact::write(dir, name, &config, seed, &failures, &traces) {
+                Ok(path) => eprintln!("{}: wrote a reproducer to {}", name, path.display()),
+                Err(e) => eprintln!("{}: failed to write a reproducer: {}", name, e),
+            }
+        }
+    }
+    if let Some(ev) = &run.events {
+        let event = ev
+            .event("run_finished")
+            .seed("seed", seed)
+            .bool("passed", failures.is_empty())
+            .num(
+                "iterations_passed",
+                run.passed.load(Ordering::Relaxed) as u64,
+            )
+            .num("failures", failures.len() as u64)
+            .float("wall_time", start.elapsed().as_secs_f64());
+        ev.emit(event);
+    }
+    let mut report = stats::report(&run.stats, start.elapsed());
+    report.outcomes = run.outcomes.get(&expected_outcomes);
+    let quiet = matches!(run.replay, Some(Replay { quiet: true, .. }));
+    if let (Some(s), false) = (outcome::report(&report.outcomes), quiet) {
+        eprintln!("{}: {}", name, s);
+    }
+    if let Some(dir) = &junit_dir {
+        if let Err(e) = junit::write(dir, name, seed, &report, &failures) {
+            eprintln!("{}: failed to write the JUnit report: {}", name, e);
+        }
+    }
+    if let Some(dir) = &html_dir {
+        let page = html::Page {
+            name,
+            seed,
+            config: &config,
+            failures: &failures,
+            report: &report,
+            sites: run.sites.as_ref(),
+            interleavings: run.interleavings.as_ref(),
+            traces: &traces,
+        };
+        match page.write(dir) {
+            Ok(path) => eprintln!("{}: wrote a report to {}", name, path.display()),
+            Err(e) => eprintln!("{}: failed to write the HTML report: {}", name, e),
+        }
+    }
+    match failures.len() {
+        0 => {
+            if timing_report {
+                eprint!("{}: timing:\n{}", name, report);
+            }
+            Ok(report)
+        }
+        1 => Err(failures.pop().unwrap()),
+        _ => Err(CobbError::Multiple(failures)),
     }
 }
 
-fn run_group<T: Send + Sync + 'static>(test: TestCfg<T>, group_idx: usize) {
-    let threads = test.threads;
+fn run_group<T: Send + Sync + 'static>(
+    test: TestCfg<T>,
+    hooks: Hooks<T>,
+    group_idx: usize,
+    run: Arc<RunShared>,
+) {
+    let mut rng = Rng::with_seed(run.seed_for(Stream::Driver, group_idx, 0));
+    let single_core = test.inversion.is_some_and(|inv| inv.single_core);
+    let partition = test.partition_cores && test.groups > 1;
+    let confine = test.oversubscribe.and_then(|o| o.cores);
+    let mut cpus =
+        if single_core || test.repin.is_some() || partition || test.oversubscribe.is_some() {
+            affinity::allowed()
+        } else {
+            vec![]
+        };
+    if partition {
+        cpus = affinity::partition(&cpus, test.groups, group_idx);
+    }
+    if let Some(n) = confine {
+        cpus.truncate(n);
+    }
+    let (min_threads, threads) = match (&test.oversubscribe, &test.thread_range) {
+        (Some(o), _) => {
+            let cores = match cpus.len() {
+                0 => thread::available_parallelism()
+                    .map_or(1, |n| n.get())
+                    .min(confine.unwrap_or(usize::MAX)),
+                n => n,
+            };
+            (2, (o.factor * cores).max(2))
+        }
+        (None, Some(r)) => {
+            let (lo, hi) = (*r.start(), *r.end());
+            (lo, rng.between(lo..hi + 1))
+        }
+        (None, None) => (2, test.threads),
+    };
     let iterations = if cfg!(miri) {
         test.iterations.max(100)
     } else {
         test.iterations
     };
-    let verbose = matches!(option_env!("COBB_VERBOSE"), Some(s) if s != "" && s != "0");
+    let verbosity = test.verbosity;
+    let mut progress_report = stats::Progress::new(iterations);
     let test_name = test.name.unwrap_or("cobb");
+    let group_span = run.span.group(group_idx, threads);
+    let _group_entered = group_span.enter();
     let after_events = (0..threads)
         .map(|_| Event::new_shared())
         .collect::<Vec<_>>();
@@ -181,22 +1449,170 @@ fn run_group<T: Send + Sync + 'static>(test: TestCfg<T>, group_idx: usize) {
         .map(|_| Event::new_shared())
         .collect::<Vec<_>>();
     let mut order = (0..threads).collect::<Vec<_>>();
+    let mut order_picker = start::OrderPicker::new(test.start_order);
+    let mut escalator = test
+        .escalate
+        .filter(|_| group_idx == 0)
+        .map(escalate::Escalator::new);
+    let mut since_reprioritize = 0;
+    let mut reprioritize_gap = test.reprioritize_cadence.gap(run.level.get(), &mut rng);
     let pri_states = (0..threads)
+        .map(|i| {
+            Arc::new(AtomicBool::new(match test.inversion {
+                Some(inv) => i != inv.holder,
+                None => test.priorities.get(i) != Some(&Priority::Lo),
+            }))
+        })
+        .collect::<Vec<_>>();
+    let active_states = (0..threads)
         .map(|_| Arc::new(AtomicBool::new(true)))
         .collect::<Vec<_>>();
-    let state = Arc::new(RwLock::new(CachePad::new((test.setup)())));
+    let mut participants = threads;
+    let finished = Arc::new(AtomicBool::new(false));
+    let capturing = run.replay.as_ref().is_some_and(|r| r.capture.is_some());
+    let trace_slots = (test.trace_dir.is_some()
+        || test.corpus_dir.is_some()
+        || test.artifact_dir.is_some()
+        || test.html_dir.is_some()
+        || capturing)
+        .then(|| Arc::new(trace::Slots::new(threads)));
+    let message_slots = Arc::new(messages::Slots::new(threads));
+    let output_slots = Arc::new(messages::Slots::new(threads));
+    let dump_messages = |rep: usize| {
+        message_slots.dump(test_name, "trace! messages", group_idx, rep);
+        output_slots.dump(test_name, "output", group_idx, rep);
+    };
+    let scratch = (0..threads)
+        .map(|_| Arc::new(ScratchSlot::default()))
+        .collect::<Vec<_>>();
+    let frozen = Arc::new(AtomicBool::new(false));
+    let iteration_seed = Arc::new(AtomicU64::new(0));
+    let gate =
+        (test.start_mode == StartMode::SpinGate).then(|| Arc::new(start::SpinGate::default()));
+    let allowed = match test.numa {
+        Some(_) => affinity::allowed(),
+        None => vec![],
+    };
+    let nodes = affinity::numa_nodes(&allowed);
+    let repin = test.repin.filter(|_| !single_core && !cpus.is_empty());
+    let cpu_states = (0..threads)
+        .map(|i| {
+            Arc::new(AtomicUsize::new(match cpus.len() {
+                // Each group gets its own core, where there are enough.
+                n if single_core && n != 0 && partition => cpus[0],
+                n if single_core && n != 0 => cpus[group_idx % n],
+                _ => test.affinity.get(i).copied().unwrap_or(affinity::UNPINNED),
+            }))
+        })
+        .collect::<Vec<_>>();
+    let suspend_targets = Arc::new(
+        (0..threads)
+            .map(|_| Arc::new(suspend::Target::default()))
+            .collect::<Vec<_>>(),
+    );
+    let statuses = Arc::new(
+        (0..threads)
+            .map(|_| Arc::new(watchdog::ThreadStatus::default()))
+            .collect::<Vec<_>>(),
+    );
+    let progress = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let group_failed = Arc::new(AtomicBool::new(false));
+    let barrier = Arc::new(Barrier::default());
+    let pct = match test.schedule {
+        ScheduleMode::Pct { depth } => Some(Arc::new(pct::PctShared::new(threads, depth))),
+        _ => None,
+    };
+    let input = run.replay.as_ref().and_then(|r| r.input.clone());
+    let coop = match (&input, test.schedule) {
+        (Some(input), _) => Some(Arc::new(coop::CoopShared::fuzzed(
+            threads,
+            Arc::clone(input),
+        ))),
+        (
+            None,
+            ScheduleMode::Cooperative
+            | ScheduleMode::Systematic { .. }
+            | ScheduleMode::CoverageGuided,
+        ) => Some(Arc::new(coop::CoopShared::new(
+            threads,
+            test.schedule,
+            run.seed_for(Stream::Coop, group_idx, 0),
+        ))),
+        (None, ScheduleMode::Random | ScheduleMode::Pct { .. }) => None,
+    };
+    let mut times = stats::GroupTimes::new(&run.stats[group_idx], group_idx, threads);
+    let mut initial = None;
+    let setup_err = |message, details| CobbError::SetupPanicked {
+        group: group_idx,
+        message,
+        details,
+    };
+    let setup_start = Instant::now();
+    let ok = run_hook(&run, setup_err, || initial = Some((hooks.setup)(threads)));
+    times.report.setup_time += setup_start.elapsed();
+    if !ok {
+        return;
+    }
+    let state = Arc::new(RwLock::new(CachePad::new(initial.unwrap())));
     // let mut thread_controllers = Vec::with_capacity(threads);
     let join_handles = (0..threads)
         .map(|thread_index| {
             let thread_control = TestThread {
+                group: group_idx,
                 index: thread_index,
+                count: threads,
                 sub_iterations: test.sub_iterations,
-                iters: iterations,
-                test_fn: test.test,
+                sub_iteration_range: test.sub_iteration_range.clone(),
+                finished: Arc::clone(&finished),
+                run: Arc::clone(&run),
+                phases: hooks.phases.clone(),
+                owner: hooks.owner.clone(),
                 test_state: Arc::clone(&state),
                 before_event: Arc::clone(&before_evts[thread_index]),
                 after_event: Arc::clone(&after_events[thread_index]),
                 pri: Arc::clone(&pri_states[thread_index]),
+                low_priority: test.low_priority,
+                realtime: test.realtime,
+                active: Arc::clone(&active_states[thread_index]),
+                barrier: Arc::clone(&barrier),
+                scratch: Arc::clone(&scratch[thread_index]),
+                trace_slots: trace_slots.clone(),
+                message_slots: Arc::clone(&message_slots),
+                trace_messages: test.trace_messages,
+                output_slots: Arc::clone(&output_slots),
+                capture_output: test.capture_output,
+                statuses: Arc::clone(&statuses),
+                suspend_targets: Arc::clone(&suspend_targets),
+                freeze: test.freeze,
+                frozen: Arc::clone(&frozen),
+                iteration_seed: Arc::clone(&iteration_seed),
+                gate: gate.clone(),
+                cpu: Arc::clone(&cpu_states[thread_index]),
+                cpu_set: match test.numa {
+                    Some(numa) if !nodes.is_empty() => {
+                        let node = if numa.spread { thread_index } else { group_idx };
+                        nodes[node % nodes.len()].clone()
+                    }
+                    _ if partition || confine.is_some() => cpus.clone(),
+                    _ => vec![],
+                },
+                group_failed: Arc::clone(&group_failed),
+                span: group_span.thread(group_idx, thread_index),
+                make_scheduler: match (test.scheduler, &pct, &coop) {
+                    (Some(f), _, _) => Box::new(move || f(thread_index, threads)),
+                    (None, Some(pct), _) => {
+                        let pct = Arc::clone(pct);
+                        Box::new(move || Box::new(pct::PctScheduler::new(pct, thread_index)))
+                    }
+                    (None, None, Some(coop)) => {
+                        let coop = Arc::clone(coop);
+                        Box::new(move || Box::new(coop::CoopScheduler::new(coop, thread_index)))
+                    }
+                    (None, None, None) => {
+                        let weights = test.sp_weights;
+                        Box::new(move || Box::new(RandomScheduler::new(weights)))
+                    }
+                },
             };
             let jh = std::thread::Builder::new()
                 .name(format!(
@@ -213,141 +1629,620 @@ fn run_group<T: Send + Sync + 'static>(test: TestCfg<T>, group_idx: usize) {
             (jh, thread_index)
         })
         .collect::<Vec<(JoinHandle<()>, usize)>>();
-    let mut rng = Rng::new();
+    let _watchdog = test.watchdog.map(|timeout| {
+        let threads = join_handles
+            .iter()
+            .map(|(jh, i)| {
+                let name = jh.thread().name().unwrap_or("<unnamed>").to_string();
+                (name, Arc::clone(&statuses[*i]))
+            })
+            .collect();
+        watchdog::Watchdog::spawn(
+            format!("{} group {}", test_name, group_idx),
+            timeout,
+            Arc::clone(&progress),
+            threads,
+        )
+    });
+    let suspender = test.suspend.filter(|_| suspend::SUPPORTED).map(|cfg| {
+        let threads = statuses
+            .iter()
+            .cloned()
+            .zip(suspend_targets.iter().cloned())
+            .collect();
+        suspend::Suspender::spawn(
+            format!("{} group {}", test_name, group_idx),
+            cfg,
+            threads,
+            Rng::with_seed(run.seed_for(Stream::Suspender, group_idx, 0)),
+        )
+    });
+    let disturbers = (!test.disturbers.is_empty() && !cfg!(miri)).then(|| {
+        disturb::Disturbers::spawn(
+            &format!("{} group {}", test_name, group_idx),
+            &test.disturbers,
+            run.seed_for(Stream::Disturbers, group_idx, 0),
+        )
+    });
+    let preempter = test
+        .preempt
+        .filter(|_| preempt::SUPPORTED)
+        .map(|cfg| preempt::Preempter::start(cfg, &mut rng));
+    if verbosity >= 2 && (test.thread_range.is_some() || test.oversubscribe.is_some()) {
+        eprintln!(
+            "{}: group {} using {} threads",
+            test_name, group_idx, threads
+        );
+    }
+    if let Some(o) = &run.observer {
+        o.on_group_start(group_idx, threads);
+    }
+    if let Some(ev) = &run.events {
+        let event = ev
+            .event("group_started")
+            .num("group", group_idx as u64)
+            .num("threads", threads as u64);
+        ev.emit(event);
+    }
+    let make_trace = |rep: usize, seed: u64, order: &[usize]| {
+        let slots = trace_slots.as_ref()?;
+        Some(Trace {
+            test: test_name.to_string(),
+            group: group_idx,
+            iteration: rep,
+            seed,
+            order: order.to_vec(),
+            priorities: pri_states
+                .iter()
+                .map(|p| match p.load(Ordering::Relaxed) {
+                    true => Priority::Hi,
+                    false => Priority::Lo,
+                })
+                .collect(),
+            benched: (0..threads)
+                .filter(|&i| !active_states[i].load(Ordering::Relaxed))
+                .collect(),
+            threads: slots.get(),
+        })
+    };
+    let save_trace = |rep: usize, seed: u64, order: &[usize]| {
+        let Some(trace) = make_trace(rep, seed, order) else {
+            return;
+        };
+        if let Some(dir) = &test.trace_dir {
+            match trace.save_in(dir) {
+                Ok(path) => eprintln!("{}: wrote the trace to {}", test_name, path.display()),
+                Err(e) => eprintln!("{}: failed to write the trace: {}", test_name, e),
+            }
+        }
+        // A replay failing again doesn't need another entry.
+        if let (Some(dir), None) = (&test.corpus_dir, &run.replay) {
+            match corpus::save(dir, &trace) {
+                Ok(path) => eprintln!("{}: added {} to the corpus", test_name, path.display()),
+                Err(e) => eprintln!("{}: failed to add to the corpus: {}", test_name, e),
+            }
+        }
+        if test.artifact_dir.is_some() || test.html_dir.is_some() {
+            run.failed_traces
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .push(trace);
+        }
+    };
     for rep in 0..iterations {
-        if verbose && group_idx == 0 {
+        if verbosity >= 3 && group_idx == 0 {
             eprintln!("{}/{}:", rep, iterations);
         }
-        if test.reprioritize.is_some() && rep != 0 && (rep % 200) == 0 && !cfg!(miri) {
-            if verbose && group_idx == 0 {
-                eprintln!("reprioritize");
-            }
-            let pris = match test.reprioritize.unwrap() {
-                PrioritizeMode::Random => rng.between(1..threads - 1),
-                PrioritizeMode::MostlyHi => 1,
-                PrioritizeMode::MostlyLo => threads - 1,
-                PrioritizeMode::Count(n) => n,
+        // Reseeding each iteration means any one can be rerun on its own.
+        let iter_seed = run.iteration_seed(group_idx, rep);
+        let iteration_span = group_span.iteration(rep, iter_seed);
+        let _iteration_entered = iteration_span.enter();
+        rng = Rng::with_seed(iter_seed);
+        iteration_seed.store(iter_seed, Ordering::Relaxed);
+        // Escalation makes these more frequent, up to every iteration.
+        let cadence = &test.reprioritize_cadence;
+        let reprioritize_point = !cfg!(miri)
+            && match rep {
+                0 => test.reprioritize_at_start,
+                _ => cadence.due(
+                    since_reprioritize,
+                    reprioritize_gap,
+                    run.level.get(),
+                    &mut rng,
+                ),
             };
-            for i in (0..threads).map(|i| order[i]) {
-                pri_states[i].store(i < pris, Ordering::Relaxed);
+        if reprioritize_point {
+            since_reprioritize = 0;
+            reprioritize_gap = cadence.gap(run.level.get(), &mut rng);
+        }
+        if repin == Some(Repin::EachIteration)
+            || (repin == Some(Repin::OnReprioritize) && reprioritize_point)
+        {
+            let assignment = affinity::random_assignment(&cpus, threads, &mut rng);
+            for (state, cpu) in cpu_states.iter().zip(assignment) {
+                state.store(cpu, Ordering::Relaxed);
+            }
+        }
+        if (test.reprioritize.is_some() || test.on_reprioritize.is_some())
+            && test.inversion.is_none()
+            && reprioritize_point
+        {
+            if verbosity >= 2 {
+                eprintln!(
+                    "{}: group {} reprioritizing in iteration {}",
+                    test_name, group_idx, rep
+                );
+            }
+            match &test.reprioritize {
+                Some(PrioritizeMode::Alternating) => {
+                    for state in &pri_states {
+                        state.fetch_xor(true, Ordering::Relaxed);
+                    }
+                }
+                Some(PrioritizeMode::RoundRobin) => {
+                    let mut pris = pri_states
+                        .iter()
+                        .map(|p| p.load(Ordering::Relaxed))
+                        .collect::<Vec<_>>();
+                    if pris.iter().all(|&p| p == pris[0]) {
+                        pris.iter_mut().enumerate().for_each(|(i, p)| *p = i == 0);
+                    } else {
+                        pris.rotate_right(1);
+                    }
+                    for (state, pri) in pri_states.iter().zip(pris) {
+                        state.store(pri, Ordering::Relaxed);
+                    }
+                }
+                Some(PrioritizeMode::Custom(f)) => {
+                    let mut pris = pri_states
+                        .iter()
+                        .map(|p| p.load(Ordering::Relaxed))
+                        .collect::<Vec<_>>();
+                    f(rep, &mut pris);
+                    for (state, pri) in pri_states.iter().zip(pris) {
+                        state.store(pri, Ordering::Relaxed);
+                    }
+                }
+                Some(PrioritizeMode::PerThreadPattern(pattern)) => {
+                    for (i, state) in pri_states.iter().enumerate() {
+                        state.store(pattern.get(i).copied().unwrap_or(true), Ordering::Relaxed);
+                    }
+                }
+                Some(mode) => {
+                    let pris = match *mode {
+                        PrioritizeMode::MostlyHi => 1,
+                        PrioritizeMode::MostlyLo => threads - 1,
+                        PrioritizeMode::Count(n) => n,
+                        _ => rng.between(1..(threads - 1).max(2)),
+                    };
+                    for (n, &i) in order.iter().enumerate() {
+                        pri_states[i].store(n < pris, Ordering::Relaxed);
+                    }
+                }
+                None => {}
+            }
+            if let Some(f) = test.on_reprioritize {
+                let mut pris = pri_states
+                    .iter()
+                    .map(|p| match p.load(Ordering::Relaxed) {
+                        true => Priority::Hi,
+                        false => Priority::Lo,
+                    })
+                    .collect::<Vec<_>>();
+                f(rep, &mut pris);
+                for (state, pri) in pri_states.iter().zip(pris) {
+                    state.store(pri == Priority::Hi, Ordering::Relaxed);
+                }
+            }
+            if run.observer.is_some() || instrument::ENABLED {
+                let pris = pri_states
+                    .iter()
+                    .map(|p| match p.load(Ordering::Relaxed) {
+                        true => Priority::Hi,
+                        false => Priority::Lo,
+                    })
+                    .collect::<Vec<_>>();
+                instrument::reprioritized(group_idx, rep, &pris);
+                if let Some(o) = &run.observer {
+                    o.on_reprioritize(group_idx, rep, &pris);
+                }
+            }
+        }
+        since_reprioritize += 1;
+        order_picker.pick(&mut order, rep, &mut rng);
+        if let Some(t) = run.replay.as_ref().and_then(|r| r.trace.as_ref()) {
+            order.copy_from_slice(&t.order);
+            for (i, state) in active_states.iter().enumerate() {
+                state.store(!t.benched.contains(&i), Ordering::Relaxed);
+            }
+            participants = threads - t.benched.len();
+        }
+        if let Some(input) = &input {
+            for (i, o) in order.iter_mut().enumerate() {
+                *o = i;
+            }
+            input.shuffle(&mut order);
+        }
+        if let Some(p) = &preempter {
+            p.rearm(&mut rng);
+        }
+        if test.random_participation && threads > min_threads {
+            // Bench the threads at the end of `order`, which are a random
+            // subset unless `start_order` isn't random.
+            participants = rng.between(min_threads..threads + 1);
+            if hooks.owner.is_some() {
+                // Keep the owner in, by swapping it into the prefix.
+                let pos = order.iter().position(|&i| i == 0).unwrap();
+                if pos >= participants {
+                    order.swap(pos, participants - 1);
+                }
+            }
+            for (n, &i) in order.iter().enumerate() {
+                active_states[i].store(n < participants, Ordering::Relaxed);
+            }
+            if verbosity >= 3 && group_idx == 0 {
+                eprintln!("{} threads participating", participants);
+            }
+        }
+        if let Some(coop) = &coop {
+            let seed = derive_seed(iter_seed, Stream::Coop, group_idx, 0);
+            if !coop.new_iteration(seed, |t| active_states[t].load(Ordering::Relaxed)) {
+                break;
             }
         }
-        rng.shuffle(&mut order);
         if rep == 0 {
-            if verbose && group_idx == 0 {
+            if verbosity >= 3 && group_idx == 0 {
                 eprintln!("first iteration setup:");
             }
-            let testv = (test.setup)();
-            **state
-                .write()
-                .unwrap_or_else(std::sync::PoisonError::into_inner) = testv;
+            let state_node = test
+                .numa
+                .and_then(|numa| numa.state_node)
+                .filter(|_| !nodes.is_empty());
+            let pinned =
+                state_node.map(|node| affinity::pin_current_scoped(&nodes[node % nodes.len()]));
+            let setup_start = Instant::now();
+            let ok = run_hook(&run, setup_err, || {
+                let testv = (hooks.setup)(threads);
+                **state
+                    .write()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner) = testv;
+            });
+            times.report.setup_time += setup_start.elapsed();
+            drop(pinned);
+            if !ok {
+                break;
+            }
         }
 
-        if verbose && group_idx == 0 {
+        if verbosity >= 3 && group_idx == 0 {
             eprintln!("before_each:");
         }
-        {
-            (test.before_each)(
+        let hook_err = |hook| {
+            move |message, details| CobbError::HookPanicked {
+                group: group_idx,
+                hook,
+                iteration: Some(rep),
+                message,
+                details,
+            }
+        };
+        let ok = run_hook(&run, hook_err("before_each"), || {
+            (hooks.before_each)(
                 &**state
                     .read()
                     .unwrap_or_else(std::sync::PoisonError::into_inner),
-            );
+            )
+        });
+        if !ok {
+            break;
         }
 
-        if verbose && group_idx == 0 {
+        if verbosity >= 3 && group_idx == 0 {
             eprintln!("running threads:");
         }
+        let violations_before = run.violations.count();
+        barrier.reset(participants);
+        if let Some(pct) = &pct {
+            pct.new_iteration(&mut rng, |t| active_states[t].load(Ordering::Relaxed));
+        }
 
-        for i in (0..threads).map(|i| order[i]) {
+        if let Some(i) = &run.interleavings {
+            i.start(group_idx, &order[..participants]);
+        }
+        if let Some(d) = &disturbers {
+            d.set_active(true);
+        }
+        let iteration_start = Instant::now();
+        for (n, i) in (0..threads).map(|i| order[i]).enumerate() {
+            if gate.is_none() {
+                test.start_stagger.wait_before(n, &mut rng);
+            }
             // starting threads 1 at a time gives extra instruction scrambling.
             before_evts[i].notify();
         }
+        if let Some(gate) = &gate {
+            gate.open(threads);
+        }
 
         // this one could be a WFMO if we had such a thing
-        for i in (0..threads).map(|i| order[i]) {
-            after_events[i].wait();
+        match test.iteration_timeout {
+            None => {
+                for i in (0..threads).map(|i| order[i]) {
+                    after_events[i].wait();
+                }
+            }
+            Some(timeout) => {
+                let deadline = Instant::now() + timeout;
+                let stuck = (0..threads)
+                    .map(|i| order[i])
+                    .filter(|&i| {
+                        !after_events[i]
+                            .wait_timeout(deadline.saturating_duration_since(Instant::now()))
+                    })
+                    .map(|i| {
+                        let thread = join_handles[i].0.thread();
+                        let name = thread.name().unwrap_or("<unnamed>");
+                        if join_handles[i].0.is_finished() {
+                            format!("{} (exited)", name)
+                        } else {
+                            name.to_string()
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                if !stuck.is_empty() {
+                    // Don't wait for the stuck threads, or tear down the
+                    // state they're still using.
+                    run.fail(CobbError::Timeout {
+                        group: group_idx,
+                        iteration: rep,
+                        seed: iter_seed,
+                        timeout,
+                        stuck,
+                    });
+                    return;
+                }
+            }
+        }
+        times.iteration(iteration_start.elapsed());
+        if let Some(d) = &disturbers {
+            d.set_active(false);
+        }
+        if let Some(i) = &run.interleavings {
+            i.finish(group_idx);
+        }
+        if let Some(capture) = run.replay.as_ref().and_then(|r| r.capture.as_ref()) {
+            capture
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .extend(make_trace(rep, iter_seed, &order));
+        }
+        // The runners' failures get reported with the rest at the end, and the
+        // state's probably too broken for `after_each` to be meaningful.
+        if group_failed.load(Ordering::Relaxed) {
+            eprintln!(
+                "{}: group {} failed in iteration {} (iteration seed {:#x})",
+                test_name, group_idx, rep, iter_seed
+            );
+            dump_messages(rep);
+            save_trace(rep, iter_seed, &order);
+            break;
         }
-        if verbose && group_idx == 0 {
+        if verbosity >= 3 && group_idx == 0 {
             eprintln!("after_each:");
         }
 
         {
-            (test.after_each)(
-                &**state
-                    .read()
-                    .unwrap_or_else(std::sync::PoisonError::into_inner),
+            let ictx = IterCtx {
+                group: group_idx,
+                iteration: rep,
+                thread_count: threads,
+                scratch: &scratch,
+                run: &run,
+                order: &order,
+                pri_states: &pri_states,
+                active_states: &active_states,
+            };
+            let ok = run_hook(&run, hook_err("after_each"), || {
+                (hooks.after_each)(
+                    &**state
+                        .read()
+                        .unwrap_or_else(std::sync::PoisonError::into_inner),
+                    &ictx,
+                )
+            });
+            if !ok {
+                dump_messages(rep);
+                save_trace(rep, iter_seed, &order);
+                break;
+            }
+        }
+        if run.violations.count() != violations_before {
+            dump_messages(rep);
+            save_trace(rep, iter_seed, &order);
+        }
+        order_picker.finished(
+            &order,
+            run.violations.count() != violations_before,
+            &mut rng,
+        );
+        if let Some(c) = &run.calibration {
+            if group_idx == 0 && rep + 1 == calibrate::Calibration::warmup(iterations) {
+                let typical = c.finish();
+                if verbosity >= 2 {
+                    eprintln!("{}: the test typically takes {:?}", test_name, typical);
+                }
+            }
+        }
+        if let Some(e) = &mut escalator {
+            let failed = run.violations.count() != 0 || run.stop.load(Ordering::Relaxed);
+            if let Some(level) = e.finished(&run.level, failed) {
+                if verbosity >= 2 {
+                    eprintln!("{}: escalating to {}x", test_name, level);
+                }
+            }
+        }
+        progress.store(rep + 1, Ordering::Relaxed);
+        if let Some(o) = &run.observer {
+            if !o.on_iteration_complete(group_idx, rep) {
+                run.stop.store(true, Ordering::Relaxed);
+            }
+        }
+        if verbosity >= 1 && group_idx == 0 && progress_report.due(rep + 1) {
+            eprintln!(
+                "{}: {} of {} iterations done",
+                test_name,
+                rep + 1,
+                iterations
+            );
+        }
+        if run.violations.count() == violations_before {
+            run.passed.fetch_add(1, Ordering::Relaxed);
+        }
+        if run.stop.load(Ordering::Relaxed) {
+            eprintln!(
+                "{}: group {} stopping early after {} of {} iterations",
+                test_name,
+                group_idx,
+                rep + 1,
+                iterations
+            );
+            break;
+        }
+        if run.deadline.is_some_and(|d| Instant::now() >= d) && rep + 1 != iterations {
+            eprintln!(
+                "{}: group {} out of time after {} of {} iterations",
+                test_name,
+                group_idx,
+                rep + 1,
+                iterations
             );
+            break;
         }
     }
+    if let Some(report) = coop.as_ref().and_then(|c| c.report()) {
+        eprintln!("{}: group {} {}", test_name, group_idx, report);
+    }
+    // Make sure nobody's left suspended.
+    drop(suspender);
+    drop(preempter);
     // last kick to get threads out of iteratoin loop
+    finished.store(true, Ordering::Relaxed);
     for i in (0..threads).map(|i| order[i]) {
         before_evts[i].notify();
     }
-    let mut failed = vec![];
     for (jh, thread_index) in join_handles {
-        jh.join().unwrap_or_else(|e| {
-            eprintln!(
-                "{}:Thread {} in group {} failed with error: {}",
-                test_name,
-                thread_index,
-                group_idx,
-                extract_msg(&e)
-            );
-            failed.push((e, thread_index));
-        });
-    }
-    if !failed.is_empty() {
-        eprintln!(
-            "{}: {} threads in group {} failed: {:?}",
-            test_name,
-            failed.len(),
-            group_idx,
-            failed.iter().map(|f| f.1).collect::<Vec<_>>()
-        );
-        std::panic::resume_unwind(failed.pop().unwrap().0);
+        // Panics in the test are caught by the thread itself, so this is
+        // only for cobb's own code.
+        if let Err(e) = jh.join() {
+            run.fail(CobbError::ThreadPanicked {
+                group: group_idx,
+                thread: thread_index,
+                iteration: None,
+                seed: None,
+                message: extract_msg(&*e),
+                regions: vec![],
+                details: Some(PanicDetails::from_payload(e)),
+            });
+        }
     }
-    {
-        (test.teardown)(
+    let teardown_err = |message, details| CobbError::HookPanicked {
+        group: group_idx,
+        hook: "teardown",
+        iteration: None,
+        message,
+        details,
+    };
+    let teardown_start = Instant::now();
+    run_hook(&run, teardown_err, || {
+        (hooks.teardown)(
             &mut **state
                 .write()
                 .unwrap_or_else(std::sync::PoisonError::into_inner),
-        );
-    }
+        )
+    });
+    times.report.teardown_time = teardown_start.elapsed();
 }
 fn extract_msg(e: &(dyn std::any::Any + Send)) -> String {
-    if let Some(s) = e.downcast_ref::<&'static str>() {
+    if let Some(r) = e.downcast_ref::<CobbError>() {
+        r.to_string()
+    } else if let Some(s) = e.downcast_ref::<&'static str>() {
         s.to_string()
     } else if let Some(e) = e.downcast_ref::<String>() {
         e.clone()
     } else {
-        format!("Unknown Any")
+        // It's still in the `PanicDetails`, for whoever knows what it is.
+        "a value that isn't a string (see `PanicDetails::take_payload`)".to_string()
     }
 }
-#[derive(Copy, Clone)]
+/// A small, fast (and not at all cryptographic) random number generator.
+///
+/// Tests can get one seeded by cobb from `TestCtx::rng`.
+#[derive(Copy, Clone, Debug)]
 pub struct Rng(u64);
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl Rng {
+    /// An RNG seeded from the OS's hash randomization. Nothing in cobb uses
+    /// this past picking the master seed (see `TestCfg::seed`).
     pub fn new() -> Self {
         use std::collections::hash_map::RandomState;
         use std::hash::{BuildHasher, Hasher};
         Self(RandomState::new().build_hasher().finish() | 1)
     }
-    // fn spawn(&mut self) -> Self {
-    //     Self((!self.gen()).wrapping_mul(0xc0bb_15_c001))
-    // }
-    fn gen(&mut self) -> u64 {
+    /// An RNG that always produces the same numbers for the same seed.
+    pub fn with_seed(seed: u64) -> Self {
+        // The splitmix64 finalizer, so that similar seeds don't give similar
+        // sequences.
+        Self(splitmix64(seed.wrapping_add(0x9E37_79B9_7F4A_7C15)) | 1)
+    }
+    /// Returns a random `u64`.
+    pub fn gen(&mut self) -> u64 {
         let x = self.0 ^ (self.0 >> 12);
         let x = x ^ (x << 25);
         self.0 = x ^ (x >> 27);
         self.0.wrapping_mul(0x2545F4914F6CDD1D)
     }
-    fn upto(&mut self, top: usize) -> usize {
+    /// Returns a random number in `0..top`. Panics if `top` is 0.
+    pub fn upto(&mut self, top: usize) -> usize {
         self.gen() as usize % top // todo: biased
     }
-    fn between(&mut self, r: core::ops::Range<usize>) -> usize {
+    /// Returns a random number in the range `r`. Panics if it's empty.
+    pub fn between(&mut self, r: core::ops::Range<usize>) -> usize {
         self.upto(r.end - r.start) + r.start
     }
-    fn shuffle<T>(&mut self, v: &mut [T]) {
-        for i in 0..(v.len() - 1) {
+    /// Returns a random `f64` in `0.0..1.0`.
+    pub fn gen_f64(&mut self) -> f64 {
+        (self.gen() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+    /// Flips a coin.
+    pub fn gen_bool(&mut self) -> bool {
+        (self.gen() >> 63) != 0
+    }
+    /// Returns true with probability `p` (clamped to `0.0..=1.0`).
+    pub fn chance(&mut self, p: f64) -> bool {
+        self.gen_f64() < p
+    }
+    /// Returns a random element of `v`, or `None` if it's empty.
+    pub fn choose<'a, T>(&mut self, v: &'a [T]) -> Option<&'a T> {
+        if v.is_empty() {
+            None
+        } else {
+            Some(&v[self.upto(v.len())])
+        }
+    }
+    /// Fills `buf` with random bytes.
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.gen().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+    /// Shuffles `v` in place.
+    pub fn shuffle<T>(&mut self, v: &mut [T]) {
+        for i in 0..v.len().saturating_sub(1) {
             v.swap(i, self.between(i..v.len()));
         }
     }
@@ -355,20 +2250,84 @@ impl Rng {
 
 #[repr(align(64))]
 struct TestThread<T> {
+    group: usize,
     index: usize,
-    iters: usize,
+    count: usize,
+    finished: Arc<AtomicBool>,
+    run: Arc<RunShared>,
     sub_iterations: usize,
+    sub_iteration_range: Option<core::ops::RangeInclusive<usize>>,
     test_state: Arc<RwLock<CachePad<T>>>,
-    test_fn: fn(&T, &TestCtx),
+    phases: Vec<PhaseFn<T>>,
+    owner: Option<OwnerFn<T>>,
     before_event: Arc<Event>,
     after_event: Arc<Event>,
     pri: Arc<AtomicBool>,
+    low_priority: LowPriority,
+    realtime: Option<Realtime>,
+    active: Arc<AtomicBool>,
+    barrier: Arc<Barrier>,
+    scratch: Arc<ScratchSlot>,
+    trace_slots: Option<Arc<trace::Slots>>,
+    message_slots: Arc<messages::Slots>,
+    trace_messages: usize,
+    output_slots: Arc<messages::Slots>,
+    capture_output: bool,
+    statuses: Arc<Vec<Arc<watchdog::ThreadStatus>>>,
+    /// All the threads in the group, as in `statuses`.
+    suspend_targets: Arc<Vec<Arc<suspend::Target>>>,
+    freeze: FreezeCfg,
+    frozen: Arc<AtomicBool>,
+    /// Set by the driver before each iteration.
+    iteration_seed: Arc<AtomicU64>,
+    gate: Option<Arc<start::SpinGate>>,
+    /// The CPU to pin the thread to, or `affinity::UNPINNED`.
+    cpu: Arc<AtomicUsize>,
+    /// The CPUs to confine the thread to, if any: the group's, with
+    /// `TestCfg::partition_cores` or `Oversubscribe::cores`, or a node's with
+    /// `TestCfg::numa`.
+    cpu_set: Vec<usize>,
+    group_failed: Arc<AtomicBool>,
+    span: instrument::Span,
+    make_scheduler: Box<dyn FnOnce() -> Box<dyn Scheduler> + Send>,
 }
 
 pub struct TestCtx {
+    group: usize,
     thread_index: usize,
+    thread_count: usize,
+    iteration: usize,
+    iteration_seed: u64,
     sub_iter: usize,
     rng: std::cell::Cell<Rng>,
+    user_rng: std::cell::RefCell<Rng>,
+    barrier: Arc<Barrier>,
+    scratch: Arc<ScratchSlot>,
+    /// The panics of this iteration's `scope` helpers.
+    helper_panics: std::sync::Mutex<Vec<panics::Caught>>,
+    run: Arc<RunShared>,
+    /// The statuses of all the threads in the group.
+    statuses: Arc<Vec<Arc<watchdog::ThreadStatus>>>,
+    suspend_targets: Arc<Vec<Arc<suspend::Target>>>,
+    scheduler: std::cell::RefCell<Box<dyn Scheduler>>,
+    sites: Option<std::cell::RefCell<coverage::SiteCounts>>,
+    freeze: FreezeCfg,
+    /// Whether a thread in the group is in `freeze_candidate`.
+    frozen: Arc<AtomicBool>,
+    /// This iteration's events, with `TestCfg::trace_dir`.
+    trace: Option<std::cell::RefCell<Vec<TraceEvent>>>,
+    /// This iteration's `trace!` messages.
+    messages: std::cell::RefCell<messages::Ring>,
+    /// This iteration's output, with `TestCfg::capture_output`.
+    output: std::cell::RefCell<messages::Ring>,
+    /// Added to `RunShared::outcomes` when the thread exits.
+    outcomes: std::cell::RefCell<outcome::Counts>,
+    /// The events left to replay, with `run_test_replay`, and whether we've
+    /// warned about them not matching yet.
+    replaying: Option<(
+        std::cell::RefCell<std::vec::IntoIter<TraceEvent>>,
+        std::cell::Cell<bool>,
+    )>,
 }
 impl TestCtx {
     /// The index of your thread, in the range between 0 and the specified
@@ -376,77 +2335,845 @@ impl TestCtx {
     pub fn thread_index(&self) -> usize {
         self.thread_index
     }
+    /// The index of the test group this thread belongs to, between 0 and
+    /// `TestCfg::groups`. Groups run concurrently, so use this to partition
+    /// any external resources (ports, temp files, ...) between them.
+    pub fn group(&self) -> usize {
+        self.group
+    }
+    /// The number of threads running the test in this group. Usually this is
+    /// `TestCfg::threads`, but it's picked randomly if `TestCfg::thread_range`
+    /// is used.
+    ///
+    /// Note that with `TestCfg::random_participation`, not all of these
+    /// threads run every iteration.
+    pub fn thread_count(&self) -> usize {
+        self.thread_count
+    }
+    /// Which top-level iteration you're on, between 0 and
+    /// `TestCfg::iterations`. Handy for only exercising a path every so often,
+    /// or for saying which iteration blew up in a failure message.
+    pub fn iteration(&self) -> usize {
+        self.iteration
+    }
+    /// The seed everything random about this iteration comes from. Pass it
+    /// to `run_single_iteration` to run the iteration again.
+    pub fn iteration_seed(&self) -> u64 {
+        self.iteration_seed
+    }
     /// Which iteration you're on between 0 and `TestCfg::sub_iterations` (which
     /// is usually 1).
     pub fn sub_iteration(&self) -> usize {
         self.sub_iter
     }
+    /// A random number generator for this thread, seeded by cobb.
+    ///
+    /// It's separate from the one used to decide what `sp` does, so using it
+    /// doesn't change the schedule points' behavior.
+    ///
+    /// # Panics
+    /// If the returned value from a previous call is still alive.
+    pub fn rng(&self) -> std::cell::RefMut<'_, Rng> {
+        self.user_rng.borrow_mut()
+    }
+    /// Records that this thread did `op`, for `TestCfg::interleaving_report`.
+    /// Iterations where the threads do the same things in the same order
+    /// count as the same interleaving, so calling this for the operations
+    /// that matter (say, each push and pop, along with what got popped)
+    /// gives a truer count than going by schedule points alone. Does
+    /// nothing if the report is off.
+    pub fn observe(&self, op: impl std::hash::Hash) {
+        use std::hash::{BuildHasher, BuildHasherDefault};
+        if let Some(i) = &self.run.interleavings {
+            let hash = BuildHasherDefault::<std::collections::hash_map::DefaultHasher>::default()
+                .hash_one(op);
+            i.observe(self.group, self.thread_index, hash);
+        }
+    }
+    /// This thread's scratch storage, which persists across sub-iterations and
+    /// iterations, and can be inspected from `after_each` with
+    /// `IterCtx::scratch`. It starts out as `S::default()`.
+    ///
+    /// # Panics
+    /// If the scratch storage is already in use, either from a previous call
+    /// that's still alive, or because it holds a value of a different type.
+    pub fn scratch<S: Default + Send + 'static>(&self) -> Scratch<'_, S> {
+        let mut guard = match self.scratch.try_lock() {
+            Ok(g) => g,
+            Err(std::sync::TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(std::sync::TryLockError::WouldBlock) => {
+                panic!("Cobb: scratch storage is already borrowed")
+            }
+        };
+        match &*guard {
+            None => *guard = Some(Box::new(S::default())),
+            Some(v) => assert!(
+                v.is::<S>(),
+                "Cobb: scratch storage holds a value of a different type than {}",
+                std::any::type_name::<S>(),
+            ),
+        }
+        Scratch {
+            guard,
+            _boo: core::marker::PhantomData,
+        }
+    }
+    /// Runs `f`, which can spawn helper threads with `Helpers::spawn`, and
+    /// joins them before returning, like `std::thread::scope`. So the helpers
+    /// are done before the driver hears this thread is, and they can borrow
+    /// the test state or anything else that outlives the call. This is for
+    /// testing things that involve spawning threads, like a once-cell
+    /// initialized from a fresh thread:
+    ///
+    /// ```ignore
+    /// test: |cell, tctx| {
+    ///     tctx.scope(|s| {
+    ///         s.spawn(|| cell.get_or_init(|| 1));
+    ///         cell.get_or_init(|| 2);
+    ///     });
+    /// },
+    /// ```
+    ///
+    /// If a helper panics, that's reported as a failure of this thread (with
+    /// the helper's name in the panic's `PanicDetails::thread`), rather than
+    /// being propagated from `scope`.
+    pub fn scope<'env, R>(
+        &'env self,
+        f: impl for<'scope> FnOnce(&Helpers<'scope, 'env>) -> R,
+    ) -> R {
+        thread::scope(|scope| {
+            f(&Helpers {
+                scope,
+                panics: &self.helper_panics,
+                spawned: std::cell::Cell::new(0),
+            })
+        })
+    }
+    /// Somewhere to write this thread's output to, rather than `println!`.
+    /// With `TestCfg::capture_output`, it's kept and printed if the
+    /// iteration fails, along with the other threads' in the order it was
+    /// written. Otherwise it's printed right away, a line at a time, marked
+    /// with the group and thread.
+    ///
+    /// ```
+    /// # use std::io::Write;
+    /// cobb::run_test(cobb::TestCfg::<()> {
+    ///     setup: || (),
+    ///     test: |_, ctx| {
+    ///         let _ = writeln!(ctx.stdout(), "thread {} here", ctx.thread_index());
+    ///     },
+    ///     capture_output: true,
+    ///     ..Default::default()
+    /// });
+    /// ```
+    pub fn stdout(&self) -> ThreadOutput<'_> {
+        ThreadOutput::new(self, false)
+    }
+    /// Like `stdout`, but for stderr.
+    pub fn stderr(&self) -> ThreadOutput<'_> {
+        ThreadOutput::new(self, true)
+    }
+    /// Asks cobb to stop once the current iteration is over (in every group),
+    /// rather than running the remaining ones. Handy once whatever you were
+    /// hunting for has been found and logged.
+    pub fn stop(&self) {
+        self.run.stop.store(true, Ordering::Relaxed);
+    }
+    /// Records that this iteration turned out a particular way (say, which
+    /// thread won a race), for the report at the end of the run of how often
+    /// each outcome happened. If one that's possible never shows up, the test
+    /// probably isn't exercising what it's meant to. See also
+    /// `TestCfg::expected_outcomes`.
+    pub fn outcome(&self, key: &str) {
+        outcome::bump(&mut self.outcomes.borrow_mut(), key);
+    }
+    /// Records that an invariant was violated, without panicking, so that the
+    /// test keeps running and finds out how often it happens. The test fails
+    /// once it's done, listing the violations. See also `soft_assert!`.
+    #[track_caller]
+    pub fn report_violation(&self, message: impl Into<String>) {
+        self.run.violations.record(Violation {
+            group: self.group,
+            thread: Some(self.thread_index),
+            iteration: self.iteration,
+            message: message.into(),
+            location: std::panic::Location::caller(),
+            regions: self
+                .statuses
+                .iter()
+                .enumerate()
+                .flat_map(|(t, s)| s.regions().iter().map(|&r| (t, r)).collect::<Vec<_>>())
+                .collect(),
+        });
+    }
+    /// Marks a critical section, until the returned guard is dropped. Entering
+    /// and leaving it are both schedule points (taken once inside), and
+    /// failures say which regions the threads were in at the time, like
+    /// "thread 3 was inside 'publish'".
+    ///
+    /// ```
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// cobb::run_test(cobb::TestCfg::<AtomicUsize> {
+    ///     setup: || AtomicUsize::new(0),
+    ///     test: |n, ctx| {
+    ///         let _g = ctx.region("publish");
+    ///         n.fetch_add(1, Ordering::Release);
+    ///     },
+    ///     ..Default::default()
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn region(&self, name: &'static str) -> Region<'_> {
+        let location = std::panic::Location::caller();
+        self.status().regions().push(name);
+        self.record(|| TraceEvent::Enter(name.to_string()));
+        self.sp_at(location, 1.0, 1.0, SpHint::None);
+        Region {
+            ctx: self,
+            name,
+            location,
+        }
+    }
+    /// Marks a point where it'd be interesting for this thread to stall, like
+    /// between a CAS and the store that follows it. Occasionally (per
+    /// `TestCfg::freeze`), the thread freezes here for a while, as long as no
+    /// other thread in the group is frozen, so the others get to run through
+    /// the window. That's much more reliable than hoping a schedule point
+    /// lands a preemption in a window a few instructions wide.
+    #[track_caller]
+    pub fn freeze_candidate(&self) {
+        self.status().reached_sp(Location::caller());
+        let calibration = self.run.calibration.as_ref();
+        if calibration.is_some_and(|c| c.warming_up()) {
+            return;
+        }
+        let mut rng = self.rng.get();
+        let level = self.run.level.get();
+        let freeze = rng.chance((self.freeze.chance * level).min(1.0));
+        let duration = match calibration.and_then(|c| c.typical()) {
+            Some(t) => calibrate::relative_delay(t, rng.gen()),
+            None => self.freeze.duration,
+        };
+        self.rng.set(rng);
+        if !freeze || self.frozen.swap(true, Ordering::Acquire) {
+            return;
+        }
+        // The cooperative schedulers only run one thread at a time anyway, so
+        // all they need is to switch to another one.
+        if !self.scheduler.borrow_mut().contended() {
+            thread::sleep(duration.mul_f64(level));
+        }
+        self.frozen.store(false, Ordering::Release);
+    }
+    fn status(&self) -> &watchdog::ThreadStatus {
+        &self.statuses[self.thread_index]
+    }
+    /// Blocks until every thread participating in this iteration has reached
+    /// it. Useful for lining threads up right before the racy operation.
+    ///
+    /// Every participating thread must call this the same number of times per
+    /// iteration, or the test will deadlock.
+    pub fn barrier(&self) {
+        self.scheduler.borrow_mut().before_block();
+        self.barrier.wait();
+        self.scheduler.borrow_mut().after_block();
+    }
     /// Hint that if your thread got scheduled at this point, it may help expose
     /// bugs.
+    ///
+    /// What actually happens here is up to the `TestCfg::scheduler`.
+    #[track_caller]
     pub fn sp(&self) {
-        // self.sub_iter
-        let mut rng = self.rng.get();
-        let val = rng.gen();
-        self.rng.set(rng);
-        // if (val % 100) < 50
-        {
-            schedule_point((val >> 24) as u8);
+        self.sp_with(1.0, 1.0);
+    }
+    /// Like `sp`, but for a critical window, where the thread should be held
+    /// up for longer than usual.
+    #[track_caller]
+    pub fn sp_long(&self) {
+        self.sp_with(1.0, 4.0);
+    }
+    /// Like `sp`, but for spots where a little jitter is plenty (say, because
+    /// they're hit very often).
+    #[track_caller]
+    pub fn sp_short(&self) {
+        self.sp_with(1.0, 0.25);
+    }
+    /// A schedule point that only does anything `probability` of the time, and
+    /// perturbs the thread `intensity` times as hard as `sp` does when it
+    /// does: above 1.0 sleeps and yields more, and for longer, and below it
+    /// mostly spins a little.
+    ///
+    /// These are hints, which the default scheduler follows. PCT and the
+    /// cooperative modes ignore them and treat every schedule point the same.
+    #[track_caller]
+    pub fn sp_with(&self, probability: f64, intensity: f64) {
+        self.sp_at(Location::caller(), probability, intensity, SpHint::None);
+    }
+    /// Like `sp`, but says what's going on here, so the scheduler can pick a
+    /// perturbation that suits it. See `SpHint`.
+    ///
+    /// As with `sp_with`, PCT and the cooperative modes ignore the hint. It
+    /// shows up in `TestCfg::sp_report` either way.
+    #[track_caller]
+    pub fn sp_hint(&self, hint: SpHint) {
+        self.sp_at(Location::caller(), 1.0, 1.0, hint);
+    }
+    /// A schedule point that hands the rest of this thread's timeslice
+    /// straight to the thread with index `thread_index` (in this group), so
+    /// that it runs next, for much finer control over the interleaving than
+    /// `sp` gives. This is `thread_switch` on Apple platforms. Elsewhere (or if
+    /// there's no such thread, or it's this one), it just yields.
+    ///
+    /// Unlike the other schedule points, this doesn't go through the
+    /// `TestCfg::scheduler`: it always hands off.
+    #[track_caller]
+    pub fn sp_handoff(&self, thread_index: usize) {
+        let location = Location::caller();
+        self.status().reached_sp(location);
+        if matches!(&self.run.calibration, Some(c) if c.warming_up()) {
+            return;
+        }
+        let target = self
+            .suspend_targets
+            .get(thread_index)
+            .filter(|_| thread_index != self.thread_index);
+        // The handoff happens again anyway, this just keeps the replay in
+        // step.
+        let _ = self.replayed(location);
+        if !target.is_some_and(|t| suspend::switch_to(t)) {
+            thread::yield_now();
+        }
+        instrument::schedule_point(location, SpHint::None, SpAction::Handoff(thread_index));
+        self.record(|| TraceEvent::Sp {
+            location: trace::location(location),
+            hint: SpHint::None,
+            action: SpAction::Handoff(thread_index),
+        });
+        if let Some(sites) = &self.sites {
+            let mut sites = sites.borrow_mut();
+            let s = sites.entry(location).or_default();
+            s.hits += 1;
+            s.yields += 1;
+        }
+    }
+    /// What the thread did at its next schedule point in the trace being
+    /// replayed, if any.
+    fn replayed(&self, location: &Location<'_>) -> Option<SpAction> {
+        let (events, warned) = self.replaying.as_ref()?;
+        let next = events.borrow_mut().find_map(|e| match e {
+            TraceEvent::Sp {
+                location, action, ..
+            } => Some((location, action)),
+            _ => None,
+        });
+        let (at, action) = match next {
+            Some((at, action)) => (Some(at), action),
+            None => (None, SpAction::Nothing),
+        };
+        let here = trace::location(location);
+        // Shrinking a trace sends replays off track all the time.
+        let quiet = matches!(self.run.replay, Some(Replay { quiet: true, .. }));
+        if at.as_ref() != Some(&here) && !quiet && !warned.replace(true) {
+            eprintln!(
+                "cobb: thread {} hit a schedule point at {} where the trace has {}, so the replay's gone off track",
+                self.thread_index,
+                here,
+                at.as_deref().unwrap_or("none left")
+            );
+        }
+        Some(action)
+    }
+    /// Adds an event to the trace, if there is one.
+    fn record(&self, event: impl FnOnce() -> TraceEvent) {
+        if let Some(t) = &self.trace {
+            t.borrow_mut().push(event());
+        }
+    }
+    fn sp_at(
+        &self,
+        location: &'static Location<'static>,
+        probability: f64,
+        intensity: f64,
+        hint: SpHint,
+    ) {
+        self.status().reached_sp(location);
+        let body_time = match &self.run.calibration {
+            Some(c) if c.warming_up() => return,
+            Some(c) => c.typical(),
+            None => None,
+        };
+        let info = SpInfo {
+            group: self.group,
+            thread_index: self.thread_index,
+            thread_count: self.thread_count,
+            iteration: self.iteration,
+            sub_iteration: self.sub_iter,
+            location,
+            rng: &self.rng,
+            probability: probability * self.run.sp_probability,
+            intensity: intensity * self.run.intensity * self.run.level.get(),
+            body_time,
+            hint,
+            yielded: std::cell::Cell::new(false),
+            action: std::cell::Cell::new(SpAction::Other),
+        };
+        // Other schedulers have their own ideas about what should happen,
+        // which the trace can't capture.
+        match self.replayed(location) {
+            Some(action) if action != SpAction::Other => {
+                info.action.set(action);
+                info.yielded.set(action.perform());
+            }
+            _ => self.scheduler.borrow_mut().perturb(&info),
+        }
+        instrument::schedule_point(location, hint, info.action.get());
+        self.record(|| TraceEvent::Sp {
+            location: trace::location(location),
+            hint,
+            action: info.action.get(),
+        });
+        if let Some(i) = &self.run.interleavings {
+            let site = location as *const Location<'_> as usize as u64;
+            i.observe(self.group, self.thread_index, site);
+        }
+        if let Some(sites) = &self.sites {
+            let mut sites = sites.borrow_mut();
+            let s = sites.entry(location).or_default();
+            s.hits += 1;
+            s.yields += u64::from(info.yielded.get());
+            s.hint = hint;
         }
     }
 }
 
-fn set_own_priority(_high: bool) {
-    /*
-    #[cfg(all(target_vendor = "apple", not(miri)))]
-    {
-        const PRIO_DARWIN_THREAD: i32 = 3;
-        const PRIO_DARWIN_BG: i32 = 4096;
-        extern "C" {
-            fn setpriority(which: i32, who: u32, prio: i32) -> i32;
+/// Returned by `TestCtx::region`. Leaves the region when dropped.
+#[must_use = "the region ends as soon as this is dropped"]
+pub struct Region<'a> {
+    ctx: &'a TestCtx,
+    name: &'static str,
+    location: &'static Location<'static>,
+}
+
+impl Drop for Region<'_> {
+    fn drop(&mut self) {
+        // If we're unwinding, stay in it, so the failure can say where the
+        // panic happened.
+        if thread::panicking() {
+            return;
+        }
+        self.ctx.sp_at(self.location, 1.0, 1.0, SpHint::None);
+        self.ctx.record(|| TraceEvent::Leave(self.name.to_string()));
+        let mut regions = self.ctx.status().regions();
+        if let Some(i) = regions.iter().rposition(|&r| r == self.name) {
+            regions.remove(i);
         }
-        let pri = if _high { 0 } else { PRIO_DARWIN_BG };
-        unsafe { setpriority(PRIO_DARWIN_THREAD, 0, pri) };
-    }*/
+    }
+}
+
+/// Formats regions like `'outer' > 'inner'`.
+pub(crate) fn describe_regions(regions: &[&str]) -> String {
+    regions
+        .iter()
+        .map(|r| format!("'{}'", r))
+        .collect::<Vec<_>>()
+        .join(" > ")
+}
+
+/// Passed to `TestCfg::after_each`, after all the threads have finished an
+/// iteration.
+pub struct IterCtx<'a> {
+    group: usize,
+    iteration: usize,
+    thread_count: usize,
+    scratch: &'a [Arc<ScratchSlot>],
+    run: &'a RunShared,
+    order: &'a [usize],
+    pri_states: &'a [Arc<AtomicBool>],
+    active_states: &'a [Arc<AtomicBool>],
+}
+impl IterCtx<'_> {
+    /// The index of the test group, as in `TestCtx::group`.
+    pub fn group(&self) -> usize {
+        self.group
+    }
+    /// The iteration that just finished, as in `TestCtx::iteration`.
+    pub fn iteration(&self) -> usize {
+        self.iteration
+    }
+    /// The number of threads in this group, as in `TestCtx::thread_count`.
+    pub fn thread_count(&self) -> usize {
+        self.thread_count
+    }
+    /// The order the threads were started in for this iteration.
+    pub fn start_order(&self) -> &[usize] {
+        self.order
+    }
+    /// Whether thread `thread_index` was marked as high priority this
+    /// iteration (see `TestCfg::reprioritize`).
+    pub fn is_high_priority(&self, thread_index: usize) -> bool {
+        self.pri_states[thread_index].load(Ordering::Relaxed)
+    }
+    /// Whether thread `thread_index` ran the test this iteration (see
+    /// `TestCfg::random_participation`).
+    pub fn participated(&self, thread_index: usize) -> bool {
+        self.active_states[thread_index].load(Ordering::Relaxed)
+    }
+    /// Asks cobb not to run any more iterations (in any group). See
+    /// `TestCtx::stop`.
+    pub fn stop(&self) {
+        self.run.stop.store(true, Ordering::Relaxed);
+    }
+    /// Like `TestCtx::outcome`, for outcomes worked out in `after_each`.
+    pub fn outcome(&self, key: &str) {
+        self.run.outcomes.record(key);
+    }
+    /// Like `TestCtx::report_violation`, for invariants checked in
+    /// `after_each`.
+    #[track_caller]
+    pub fn report_violation(&self, message: impl Into<String>) {
+        self.run.violations.record(Violation {
+            group: self.group,
+            thread: None,
+            iteration: self.iteration,
+            message: message.into(),
+            location: std::panic::Location::caller(),
+            regions: vec![],
+        });
+    }
+    /// The scratch storage of thread `thread_index` (see `TestCtx::scratch`),
+    /// or `None` if that thread hasn't used it, or used it with another type.
+    pub fn scratch<S: 'static>(&self, thread_index: usize) -> Option<Scratch<'_, S>> {
+        let guard = self.scratch[thread_index]
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if guard.as_ref()?.is::<S>() {
+            Some(Scratch {
+                guard,
+                _boo: core::marker::PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl core::fmt::Debug for IterCtx<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let threads = 0..self.thread_count;
+        f.debug_struct("IterCtx")
+            .field("group", &self.group)
+            .field("iteration", &self.iteration)
+            .field("start_order", &self.order)
+            .field(
+                "high_priority",
+                &threads
+                    .clone()
+                    .filter(|&i| self.is_high_priority(i))
+                    .collect::<Vec<_>>(),
+            )
+            .field(
+                "participants",
+                &threads
+                    .filter(|&i| self.participated(i))
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+type ScratchSlot = std::sync::Mutex<Option<Box<dyn std::any::Any + Send>>>;
+
+/// Access to a thread's scratch storage. See `TestCtx::scratch`.
+pub struct Scratch<'a, S> {
+    guard: std::sync::MutexGuard<'a, Option<Box<dyn std::any::Any + Send>>>,
+    _boo: core::marker::PhantomData<S>,
+}
+impl<S: 'static> core::ops::Deref for Scratch<'_, S> {
+    type Target = S;
+    #[inline]
+    fn deref(&self) -> &S {
+        self.guard.as_ref().and_then(|v| v.downcast_ref()).unwrap()
+    }
+}
+impl<S: 'static> core::ops::DerefMut for Scratch<'_, S> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut S {
+        self.guard.as_mut().and_then(|v| v.downcast_mut()).unwrap()
+    }
+}
+
+/// Spawns helper threads from `TestCtx::scope`.
+pub struct Helpers<'scope, 'env> {
+    scope: &'scope thread::Scope<'scope, 'env>,
+    panics: &'env std::sync::Mutex<Vec<panics::Caught>>,
+    spawned: std::cell::Cell<usize>,
+}
+impl<'scope, 'env> Helpers<'scope, 'env> {
+    /// Spawns a helper thread, which is joined at the end of the
+    /// `TestCtx::scope` call this came from.
+    pub fn spawn<F: FnOnce() + Send + 'scope>(&self, f: F) {
+        let name = format!(
+            "{} helper {}",
+            thread::current().name().unwrap_or("cobb runner"),
+            self.spawned.get()
+        );
+        self.spawned.set(self.spawned.get() + 1);
+        let panics = self.panics;
+        std::thread::Builder::new()
+            .name(name)
+            .spawn_scoped(self.scope, move || {
+                if let Err(e) = panics::catch(f) {
+                    panics.lock().unwrap_or_else(|e| e.into_inner()).push(e);
+                }
+            })
+            .unwrap_or_else(|e| panic!("Cobb: failed to launch helper thread: {:?}", e));
+    }
 }
 
 fn run_test_thread<T: Send + Sync + 'static>(t: TestThread<T>) {
     let TestThread {
+        group,
         index: thread_index,
+        count: thread_count,
         sub_iterations,
-        iters,
+        sub_iteration_range,
+        finished,
+        run,
         test_state,
-        test_fn,
+        phases,
+        owner,
         before_event,
         after_event,
         pri,
+        low_priority,
+        realtime,
+        active,
+        barrier,
+        scratch,
+        trace_slots,
+        message_slots,
+        trace_messages,
+        output_slots,
+        capture_output,
+        statuses,
+        suspend_targets,
+        freeze,
+        frozen,
+        iteration_seed,
+        gate,
+        cpu,
+        cpu_set,
+        group_failed,
+        span,
+        make_scheduler,
     } = t;
+    let _thread_entered = span.enter();
+    let mut cur_cpu = affinity::UNPINNED;
+    if !cpu_set.is_empty() {
+        affinity::pin_current(&cpu_set);
+    }
+    let mut gate_seen = 0;
+    let suspend_target = Arc::clone(&suspend_targets[thread_index]);
+    let _suspendable = suspend::register(&suspend_target);
     let want_pri = pri.load(Ordering::Relaxed);
-    set_own_priority(want_pri);
+    priority::set_current(want_pri, low_priority, realtime);
     let mut cur_pri = want_pri;
-    before_event.wait(); //.unwrap_or_else(std::sync::PoisonError::into_inner);
 
+    let sites = run.sites.as_ref().map(|_| Default::default());
     let mut tctx = TestCtx {
+        group,
         thread_index,
+        thread_count,
+        iteration: 0,
+        iteration_seed: 0,
         sub_iter: 0,
-        rng: std::cell::Cell::new(Rng::new()),
+        rng: std::cell::Cell::new(Rng::with_seed(run.seed_for(
+            Stream::Sp,
+            group,
+            thread_index,
+        ))),
+        user_rng: std::cell::RefCell::new(Rng::with_seed(run.seed_for(
+            Stream::User,
+            group,
+            thread_index,
+        ))),
+        barrier,
+        scratch,
+        helper_panics: std::sync::Mutex::new(vec![]),
+        run,
+        statuses,
+        suspend_targets,
+        scheduler: std::cell::RefCell::new(make_scheduler()),
+        sites,
+        freeze,
+        frozen,
+        trace: trace_slots.as_ref().map(|_| Default::default()),
+        messages: std::cell::RefCell::new(messages::Ring::new(trace_messages)),
+        output: std::cell::RefCell::new(messages::Ring::new(match capture_output {
+            true => usize::MAX,
+            false => 0,
+        })),
+        outcomes: Default::default(),
+        replaying: None,
     };
-    for _ in 0..iters {
-        {
-            let guard = test_state.read().unwrap();
-            let state: &T = &*guard;
-            for sub_iter in 0..sub_iterations.max(1) {
-                tctx.sub_iter = sub_iter;
-                (test_fn)(state, &tctx);
+    if let Some(t) = tctx.run.replay.as_ref().and_then(|r| r.trace.as_ref()) {
+        let events = t.threads[thread_index].clone().into_iter();
+        tctx.replaying = Some((events.into(), false.into()));
+    }
+    // These stay `None` if measuring CPU time ever fails.
+    let cpu_time = cputime::current().map(|_| Duration::ZERO);
+    let mut times = stats::ThreadReport {
+        thread: thread_index,
+        cpu_time,
+        low_priority_cpu_time: cpu_time,
+        ..Default::default()
+    };
+    for iteration in 0.. {
+        let wait_start = Instant::now();
+        before_event.wait();
+        times.waiting += wait_start.elapsed();
+        // The event's lock orders this with the driver's store.
+        if finished.load(Ordering::Relaxed) {
+            break;
+        }
+        let want_cpu = cpu.load(Ordering::Relaxed);
+        if want_cpu != cur_cpu {
+            affinity::pin_current(&[want_cpu]);
+            cur_cpu = want_cpu;
+        }
+        if let Some(gate) = &gate {
+            gate.pass(&mut gate_seen);
+        }
+        tctx.iteration = iteration;
+        // The event's lock orders this with the driver's store too.
+        let seed = iteration_seed.load(Ordering::Relaxed);
+        tctx.iteration_seed = seed;
+        let iteration_span = span.iteration(iteration, seed);
+        let _iteration_entered = iteration_span.enter();
+        tctx.rng.set(Rng::with_seed(derive_seed(
+            seed,
+            Stream::Sp,
+            group,
+            thread_index,
+        )));
+        *tctx.user_rng.borrow_mut() =
+            Rng::with_seed(derive_seed(seed, Stream::User, group, thread_index));
+        tctx.status().start_iteration();
+        // Benched threads still take part in the event protocol, they just
+        // don't run the test this time around.
+        let running_start = Instant::now();
+        let cpu_start = cputime::current();
+        let res = panics::catch(|| {
+            if !active.load(Ordering::Relaxed) {
+                return;
+            }
+            tctx.scheduler.borrow_mut().begin_iteration(iteration);
+            let sub_iterations = match &sub_iteration_range {
+                Some(r) => {
+                    let mut rng = tctx.rng.get();
+                    let n = rng.between(*r.start()..*r.end() + 1);
+                    tctx.rng.set(rng);
+                    n
+                }
+                None => sub_iterations.max(1),
+            };
+            if let Some(owner) = &owner {
+                // The others wait at the barrier until the owner is done, so
+                // nobody's holding a read guard while it has the write one.
+                if thread_index == 0 {
+                    let _cur = sync::enter(&tctx);
+                    (owner)(
+                        &mut test_state
+                            .write()
+                            .unwrap_or_else(std::sync::PoisonError::into_inner),
+                        &tctx,
+                    );
+                }
+                tctx.barrier();
+            }
+            let guard = test_state
+                .read()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            let state: &T = &guard;
+            for (phase_idx, phase) in phases.iter().enumerate() {
+                if phase_idx != 0 {
+                    tctx.barrier();
+                }
+                for sub_iter in 0..sub_iterations {
+                    tctx.sub_iter = sub_iter;
+                    let _cur = sync::enter(&tctx);
+                    match tctx.run.calibration.as_ref().filter(|c| c.warming_up()) {
+                        Some(c) => {
+                            let start = Instant::now();
+                            (phase)(state, &tctx);
+                            c.record(start.elapsed());
+                        }
+                        None => (phase)(state, &tctx),
+                    }
+                }
+            }
+        });
+        if active.load(Ordering::Relaxed) {
+            let running = running_start.elapsed();
+            let cpu = match (cpu_start, cputime::current()) {
+                (Some(start), Some(end)) => Some(end.saturating_sub(start)),
+                _ => None,
+            };
+            times.iterations += 1;
+            times.running += running;
+            times.cpu_time = times.cpu_time.zip(cpu).map(|(a, b)| a + b);
+            if !cur_pri {
+                times.low_priority_iterations += 1;
+                times.low_priority_running += running;
+                times.low_priority_cpu_time =
+                    times.low_priority_cpu_time.zip(cpu).map(|(a, b)| a + b);
             }
         }
+        let mut panics = vec![];
+        if let Err(e) = res {
+            // Don't leave the others waiting for us at the barrier.
+            tctx.barrier.leave();
+            panics.push(e);
+        }
+        // Helpers that panicked count as this thread failing too, each of
+        // them separately.
+        let helper_panics = tctx.helper_panics.get_mut();
+        panics.append(helper_panics.unwrap_or_else(|e| e.into_inner()));
+        let regions = match panics.is_empty() {
+            true => vec![],
+            false => core::mem::take(&mut *tctx.status().regions()),
+        };
+        for (message, details) in panics {
+            tctx.run.fail(CobbError::ThreadPanicked {
+                group,
+                thread: thread_index,
+                iteration: Some(iteration),
+                seed: Some(tctx.iteration_seed),
+                message,
+                regions: regions.clone(),
+                details: Some(details),
+            });
+            group_failed.store(true, Ordering::Relaxed);
+        }
+        tctx.scheduler.borrow_mut().end_iteration();
+        tctx.status().finish_iteration(iteration);
+        if let (Some(events), Some(slots)) = (&tctx.trace, &trace_slots) {
+            slots.put(thread_index, &mut events.borrow_mut());
+        }
+        message_slots.put(thread_index, &mut tctx.messages.borrow_mut());
+        output_slots.put(thread_index, &mut tctx.output.borrow_mut());
         after_event.notify();
         let want_pri = pri.load(Ordering::Relaxed);
         if want_pri != cur_pri {
-            set_own_priority(want_pri);
+            priority::set_current(want_pri, low_priority, realtime);
             cur_pri = want_pri;
         }
-        before_event.wait();
     }
+    if let (Some(all), Some(mine)) = (&tctx.run.sites, &tctx.sites) {
+        all.merge(&mine.borrow());
+    }
+    tctx.run.outcomes.merge(&tctx.outcomes.borrow());
+    tctx.run.stats[group].thread_done(times);
 }
 #[derive(Default)]
 pub struct Event {
@@ -468,6 +3195,19 @@ impl Event {
             .unwrap_or_else(std::sync::PoisonError::into_inner);
         *g = false;
     }
+    /// Like `wait`, but gives up after `timeout`, returning whether the event
+    /// was set.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let g = self
+            .mtx
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let (mut g, _) = self
+            .cv
+            .wait_timeout_while(g, timeout, |stopped| !*stopped)
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        core::mem::replace(&mut *g, false)
+    }
     pub fn notify(&self) {
         let mut g = self
             .mtx
@@ -477,39 +3217,58 @@ impl Event {
         self.cv.notify_one();
     }
 }
-fn schedule_point(r: u8) {
-    use std::time::Duration;
-    match r {
-        0..=10 => thread::sleep(Duration::from_nanos(0)),
-        // 6..=10 => thread::sleep(Duration::from_micros(1)),
-        11..=15 => thread::sleep(Duration::from_millis(1)),
-        16..=75 => thread::yield_now(),
-        76..=125 => {
-            for _ in 0..50usize {
-                core::hint::spin_loop();
-            }
-        }
-        225..=255 => {
-            for _ in 0..=5 {
-                thread::yield_now()
-            }
-        }
-        // #[cfg(target_vendor = "apple")]
-        // n @ 225..=255 => {
-        //     extern "C" {
-        //         // fn pthread_mach_thread_np(pthread: *core::ffi::c_void) -> u32;
-        //         fn thread_switch(p: u32, o: i32, t: u32) -> i32;
-        //     }
-        //     unsafe {
-        //         thread_switch(0, 1, (n > 240) as u32);
-        //     }
-        // }
-        n => unsafe {
-            for i in 0..(n as usize) {
-                let mut g = 0;
-                core::ptr::write_volatile(&mut g, i);
-                let _ = core::ptr::read_volatile(&g);
-            }
-        },
+/// Like `std::sync::Barrier`, but the number of threads it waits for can change
+/// between iterations (since not every thread participates in every one).
+#[derive(Default)]
+struct Barrier {
+    state: std::sync::Mutex<BarrierState>,
+    cv: std::sync::Condvar,
+}
+#[derive(Default)]
+struct BarrierState {
+    count: usize,
+    waiting: usize,
+    generation: usize,
+}
+impl Barrier {
+    /// Only call while no thread is waiting on the barrier.
+    fn reset(&self, count: usize) {
+        let mut g = self
+            .state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        debug_assert_eq!(g.waiting, 0);
+        g.count = count;
+    }
+    /// For a thread that won't be calling `wait` again this iteration.
+    fn leave(&self) {
+        let mut g = self
+            .state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        g.count = g.count.saturating_sub(1);
+        if g.waiting != 0 && g.waiting >= g.count {
+            g.waiting = 0;
+            g.generation = g.generation.wrapping_add(1);
+            self.cv.notify_all();
+        }
+    }
+    fn wait(&self) {
+        let mut g = self
+            .state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        g.waiting += 1;
+        if g.waiting >= g.count {
+            g.waiting = 0;
+            g.generation = g.generation.wrapping_add(1);
+            self.cv.notify_all();
+        } else {
+            let gen = g.generation;
+            let _g = self
+                .cv
+                .wait_while(g, |s| s.generation == gen)
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+        }
     }
 }
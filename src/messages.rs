@@ -0,0 +1,164 @@
+//! The messages left by `trace!`, which each thread keeps the latest few of,
+//! and the output written with `TestCtx::stdout` and `stderr`, to be printed
+//! when an iteration fails. See `TestCfg::trace_messages` and
+//! `TestCfg::capture_output`.
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::panic::Location;
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
+
+/// Records a message for the current thread's iteration, if it's one of
+/// cobb's runner threads. Use `cobb::trace!` rather than calling this.
+#[doc(hidden)]
+#[track_caller]
+pub fn trace(args: core::fmt::Arguments<'_>) {
+    let location = Location::caller();
+    crate::sync::with_current(|ctx| {
+        ctx.messages
+            .borrow_mut()
+            .push(Source::Trace(location), args)
+    });
+}
+
+/// Leaves a message (formatted like `format!`) that only gets printed if the
+/// iteration fails, along with the messages the other threads left, in the
+/// order they were left in. Unlike printing as you go, this doesn't take a
+/// lock or make a syscall, so it doesn't change the timing enough to make the
+/// race go away.
+///
+/// Each thread only keeps its latest `TestCfg::trace_messages`, and this
+/// does nothing outside of the runner threads (in `after_each`, say).
+///
+/// ```
+/// # use std::sync::atomic::{AtomicUsize, Ordering};
+/// cobb::run_test(cobb::TestCfg::<AtomicUsize> {
+///     setup: || AtomicUsize::new(0),
+///     test: |n, _| {
+///         let old = n.fetch_add(1, Ordering::Relaxed);
+///         cobb::trace!("incremented from {}", old);
+///     },
+///     ..Default::default()
+/// });
+/// ```
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        $crate::__private::trace(format_args!($($arg)+))
+    };
+}
+
+/// Where a message came from.
+#[derive(Clone, Copy)]
+pub(crate) enum Source {
+    Trace(&'static Location<'static>),
+    Stdout,
+    Stderr,
+}
+
+pub(crate) struct Message {
+    at: Instant,
+    source: Source,
+    text: String,
+}
+
+/// A thread's messages for the current iteration. Only the latest
+/// `capacity` are kept.
+pub(crate) struct Ring {
+    messages: VecDeque<Message>,
+    capacity: usize,
+    /// Strings from earlier iterations, so leaving a message doesn't usually
+    /// allocate.
+    spare: Vec<String>,
+    /// Whether the thread's slot has messages in it from an earlier iteration.
+    published: bool,
+}
+
+impl Ring {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            messages: VecDeque::with_capacity(capacity.min(64)),
+            capacity,
+            spare: vec![],
+            published: false,
+        }
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.capacity != 0
+    }
+
+    pub(crate) fn push(&mut self, source: Source, args: core::fmt::Arguments<'_>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut text = match self.messages.len() >= self.capacity {
+            true => self.messages.pop_front().map(|m| m.text),
+            false => self.spare.pop(),
+        }
+        .unwrap_or_default();
+        text.clear();
+        let _ = text.write_fmt(args);
+        self.messages.push_back(Message {
+            at: Instant::now(),
+            source,
+            text,
+        });
+    }
+}
+
+/// Where a group's threads leave their messages at the end of each iteration.
+pub(crate) struct Slots(Vec<Mutex<VecDeque<Message>>>);
+
+impl Slots {
+    pub(crate) fn new(threads: usize) -> Self {
+        Self((0..threads).map(|_| Mutex::default()).collect())
+    }
+
+    /// Moves thread `i`'s messages into its slot, replacing the last
+    /// iteration's. Doesn't touch the slot if there's nothing to do.
+    pub(crate) fn put(&self, i: usize, ring: &mut Ring) {
+        if ring.messages.is_empty() && !ring.published {
+            return;
+        }
+        let mut slot = self.0[i].lock().unwrap_or_else(PoisonError::into_inner);
+        core::mem::swap(&mut *slot, &mut ring.messages);
+        ring.published = !slot.is_empty();
+        ring.spare.extend(ring.messages.drain(..).map(|m| m.text));
+    }
+
+    /// Prints the messages from the last iteration (as `what`), all the
+    /// threads' together in the order they were left in.
+    pub(crate) fn dump(&self, name: &str, what: &str, group: usize, iteration: usize) {
+        let mut all = vec![];
+        for (thread, slot) in self.0.iter().enumerate() {
+            let slot = slot.lock().unwrap_or_else(PoisonError::into_inner);
+            all.extend(
+                slot.iter()
+                    .map(|m| (m.at, thread, m.source, m.text.clone())),
+            );
+        }
+        if all.is_empty() {
+            return;
+        }
+        all.sort_by_key(|m| (m.0, m.1));
+        let start = all[0].0;
+        let mut s = format!(
+            "{}: group {} iteration {} {}:",
+            name, group, iteration, what
+        );
+        for (at, thread, source, text) in all {
+            let at = format!("+{:.1?}", at - start);
+            let _ = match source {
+                Source::Trace(location) => write!(
+                    s,
+                    "\n  {:>10} thread {}: {} ({})",
+                    at, thread, text, location
+                ),
+                Source::Stdout => write!(s, "\n  {:>10} thread {} stdout: {}", at, thread, text),
+                Source::Stderr => write!(s, "\n  {:>10} thread {} stderr: {}", at, thread, text),
+            };
+        }
+        eprintln!("{}", s);
+    }
+}
@@ -0,0 +1,290 @@
+//! Looking for a smaller version of a failing test that still fails. See
+//! `TestCfg::minimize`.
+use crate::error::dedup_keys;
+use crate::{CobbError, Hooks, Replay, Rng, SpAction, TestCfg, Trace, TraceEvent};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How hard `TestCfg::minimize` looks for a smaller failing config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Minimize {
+    /// How many iterations each candidate gets to fail in.
+    pub iterations: usize,
+    /// Stop looking after this long, and report the smallest failing config
+    /// found so far.
+    pub max_time: Duration,
+}
+
+impl Default for Minimize {
+    fn default() -> Self {
+        Self {
+            iterations: 1000,
+            max_time: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The knobs being shrunk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Size {
+    threads: usize,
+    sub_iterations: usize,
+    sp_probability: f64,
+}
+
+impl Size {
+    /// Smaller versions of this to try, most promising first: the smallest
+    /// possible value of each knob, then half way there, then just a little
+    /// smaller.
+    fn candidates(&self) -> Vec<Size> {
+        let mut out = vec![];
+        for threads in [2, self.threads / 2, self.threads - 1] {
+            if threads >= 2 && threads < self.threads {
+                out.push(Size { threads, ..*self });
+            }
+        }
+        for sub_iterations in [1, self.sub_iterations / 2, self.sub_iterations - 1] {
+            if sub_iterations >= 1 && sub_iterations < self.sub_iterations {
+                out.push(Size {
+                    sub_iterations,
+                    ..*self
+                });
+            }
+        }
+        if self.sp_probability > 0.0 {
+            for sp_probability in [0.0, self.sp_probability / 4.0, self.sp_probability / 2.0] {
+                out.push(Size {
+                    sp_probability,
+                    ..*self
+                });
+            }
+        }
+        out.dedup();
+        out
+    }
+
+    fn apply<T>(&self, cfg: &mut TestCfg<T>) {
+        cfg.threads = self.threads;
+        cfg.sub_iterations = self.sub_iterations;
+        cfg.sp_probability = self.sp_probability;
+        cfg.priorities.truncate(self.threads);
+    }
+}
+
+/// Shrinks `test` (which just failed with `failure`) one knob at a time,
+/// keeping each smaller version that still fails the same way (see
+/// `error::dedup_key`) within `m.iterations`, and prints the smallest one.
+/// Then, with `TestCfg::trace_dir`, shrinks the trace of a failing iteration
+/// of that.
+pub(crate) fn run<T: Send + Sync + 'static>(
+    m: Minimize,
+    mut test: TestCfg<T>,
+    hooks: Hooks<T>,
+    failure: &CobbError,
+) {
+    let name = test.name.unwrap_or("cobb");
+    let deadline = Instant::now() + m.max_time;
+    // Pin down the ranges to their biggest values, so there's one thing to
+    // shrink.
+    let mut best = Size {
+        threads: test
+            .thread_range
+            .as_ref()
+            .map_or(test.threads, |r| *r.end()),
+        sub_iterations: test
+            .sub_iteration_range
+            .as_ref()
+            .map_or(test.sub_iterations, |r| *r.end())
+            .max(1),
+        sp_probability: test.sp_probability,
+    };
+    test.thread_range = None;
+    test.sub_iteration_range = None;
+    test.oversubscribe = None;
+    test.minimize = None;
+    test.confidence = None;
+    test.iterations = m.iterations;
+    test.groups = 1;
+    eprintln!("{}: looking for a smaller config that still fails", name);
+    let keys = dedup_keys(failure);
+    let same = |e: &CobbError| dedup_keys(e).iter().any(|k| keys.contains(k));
+    let mut best_seed = None;
+    let mut best_failure = None;
+    // The seed is always set by now, so a minimization can be repeated too.
+    let mut rng = Rng::with_seed(crate::split_seed(test.seed.unwrap_or(0), 0));
+    'shrink: while Instant::now() < deadline {
+        for candidate in best.candidates() {
+            let now = Instant::now();
+            if now >= deadline {
+                break 'shrink;
+            }
+            let mut cfg = test.clone();
+            candidate.apply(&mut cfg);
+            // Say, fewer threads than the inversion scenario needs.
+            if crate::validate(&cfg).is_err() {
+                continue;
+            }
+            let seed = rng.gen();
+            cfg.seed = Some(seed);
+            cfg.max_total_time = Some(deadline - now);
+            eprintln!(
+                "{}: trying threads {}, sub_iterations {}, sp_probability {}",
+                name, candidate.threads, candidate.sub_iterations, candidate.sp_probability
+            );
+            match crate::run_validated(cfg, hooks.clone(), None) {
+                Err(e) if same(&e) => {
+                    best = candidate;
+                    best_seed = Some(seed);
+                    best_failure = Some(e);
+                    continue 'shrink;
+                }
+                Err(_) => eprintln!("{}: that failed differently, so it doesn't count", name),
+                Ok(_) => {}
+            }
+        }
+        break;
+    }
+    match best_seed {
+        Some(seed) => eprintln!(
+            "{}: the smallest config that still failed has threads {}, sub_iterations {}, sp_probability {} (seed {:#x})",
+            name, best.threads, best.sub_iterations, best.sp_probability, seed
+        ),
+        None => eprintln!("{}: couldn't find a smaller config that still fails", name),
+    }
+    if let Some(dir) = test.trace_dir.take() {
+        best.apply(&mut test);
+        test.corpus_dir = None;
+        test.artifact_dir = None;
+        test.junit_dir = None;
+        test.html_dir = None;
+        let failure = best_failure.as_ref().unwrap_or(failure);
+        if let Some(seed) = best_seed {
+            test.seed = Some(seed);
+        }
+        let Some(trace) = failing_trace(&test, &hooks, failure, &same) else {
+            eprintln!("{}: couldn't get a failing trace to shrink", name);
+            return;
+        };
+        let (trace, kept, total) = shrink_trace(&test, &hooks, trace, &same, deadline);
+        eprintln!(
+            "{}: the failing trace still failed with {} of its {} schedule point perturbations",
+            name, kept, total
+        );
+        match trace.save_in(&dir) {
+            Ok(path) => eprintln!("{}: wrote the shrunk trace to {}", name, path.display()),
+            Err(e) => eprintln!("{}: failed to write the shrunk trace: {}", name, e),
+        }
+    }
+}
+
+/// How many times to replay a trace before deciding it doesn't fail, since
+/// the OS still gets a say in how it goes.
+const ATTEMPTS: usize = 3;
+
+/// The trace of an iteration of `test` that fails the way `failure` did,
+/// found by rerunning the iteration it happened in.
+fn failing_trace<T: Send + Sync + 'static>(
+    test: &TestCfg<T>,
+    hooks: &Hooks<T>,
+    failure: &CobbError,
+    same: &dyn Fn(&CobbError) -> bool,
+) -> Option<Trace> {
+    // Hooks' failures don't say the iteration's seed, but it can be worked
+    // out again.
+    let seed = match failure.location() {
+        (_, _, Some(seed)) => seed,
+        (Some(group), Some(iteration), None) => {
+            crate::derive_seed(test.seed?, crate::Stream::Iteration, group, iteration)
+        }
+        _ => return None,
+    };
+    let mut cfg = test.clone();
+    cfg.iterations = 1;
+    cfg.groups = 1;
+    (0..ATTEMPTS).find_map(|_| {
+        let capture = Arc::new(Mutex::new(vec![]));
+        let replay = Replay {
+            seed,
+            quiet: true,
+            capture: Some(Arc::clone(&capture)),
+            ..Default::default()
+        };
+        match crate::run_validated(cfg.clone(), hooks.clone(), Some(replay)) {
+            Err(e) if same(&e) => capture.lock().unwrap_or_else(PoisonError::into_inner).pop(),
+            _ => None,
+        }
+    })
+}
+
+/// Turns as many of the schedule points' perturbations in `trace` into
+/// `SpAction::Nothing` as it can while replaying it still fails, a chunk at a
+/// time, with the chunks getting smaller. Returns the shrunk trace, and how
+/// many perturbations it kept out of how many there were.
+fn shrink_trace<T: Send + Sync + 'static>(
+    test: &TestCfg<T>,
+    hooks: &Hooks<T>,
+    mut trace: Trace,
+    same: &dyn Fn(&CobbError) -> bool,
+    deadline: Instant,
+) -> (Trace, usize, usize) {
+    trace.test = format!("{}-shrunk", trace.test);
+    // Handoffs happen whatever the trace says, and there's no redoing
+    // `Other`.
+    let mut kept = vec![];
+    for (t, events) in trace.threads.iter().enumerate() {
+        for (i, e) in events.iter().enumerate() {
+            if let TraceEvent::Sp { action, .. } = e {
+                if !matches!(
+                    action,
+                    SpAction::Nothing | SpAction::Other | SpAction::Handoff(_)
+                ) {
+                    kept.push((t, i));
+                }
+            }
+        }
+    }
+    let total = kept.len();
+    let without = |drop: &[(usize, usize)]| {
+        let mut t = trace.clone();
+        for &(thread, i) in drop {
+            if let TraceEvent::Sp { action, .. } = &mut t.threads[thread][i] {
+                *action = SpAction::Nothing;
+            }
+        }
+        t
+    };
+    let fails = |t: &Trace| {
+        (0..ATTEMPTS).any(|_| {
+            let mut cfg = test.clone();
+            crate::for_replay(&mut cfg, t);
+            let replay = Replay {
+                seed: t.seed,
+                trace: Some(t.clone()),
+                quiet: true,
+                ..Default::default()
+            };
+            matches!(crate::run_validated(cfg, hooks.clone(), Some(replay)), Err(e) if same(&e))
+        })
+    };
+    let mut dropped = vec![];
+    let mut chunk = (kept.len() / 2).max(1);
+    while !kept.is_empty() && Instant::now() < deadline {
+        let mut i = 0;
+        while i < kept.len() && Instant::now() < deadline {
+            let end = (i + chunk).min(kept.len());
+            let mut drop = dropped.clone();
+            drop.extend_from_slice(&kept[i..end]);
+            if fails(&without(&drop)) {
+                dropped = drop;
+                kept.drain(i..end);
+            } else {
+                i = end;
+            }
+        }
+        if chunk == 1 {
+            break;
+        }
+        chunk /= 2;
+    }
+    (without(&dropped), kept.len(), total)
+}
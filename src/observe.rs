@@ -0,0 +1,25 @@
+//! Letting the code running a test follow along. See `TestCfg::observer`.
+use crate::{CobbError, Priority};
+
+/// Gets told what a run is up to as it goes, say to draw a progress bar or
+/// record metrics. All the methods do nothing by default. They're called from
+/// the groups' driver threads (and `on_failure` from wherever the failure
+/// happened), so they need to be quick, and happy being called from several
+/// threads at once.
+pub trait Observer: Send + Sync {
+    /// Group `group` is about to start its iterations, with `threads` runner
+    /// threads.
+    fn on_group_start(&self, _group: usize, _threads: usize) {}
+    /// Group `group` finished iteration `iteration`. Returning false stops the
+    /// whole run early (the same as running out of `TestCfg::max_total_time`),
+    /// for bailing out when something outside the test says to.
+    fn on_iteration_complete(&self, _group: usize, _iteration: usize) -> bool {
+        true
+    }
+    /// Group `group` changed its threads' priorities to `priorities` before
+    /// iteration `iteration`.
+    fn on_reprioritize(&self, _group: usize, _iteration: usize, _priorities: &[Priority]) {}
+    /// Something went wrong. The run carries on until all the groups notice,
+    /// so there might be more than one of these.
+    fn on_failure(&self, _error: &CobbError) {}
+}
@@ -0,0 +1,89 @@
+//! Counting how often each outcome of an iteration happened, as recorded with
+//! `TestCtx::outcome` and `IterCtx::outcome`.
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, PoisonError};
+
+pub(crate) type Counts = HashMap<String, u64>;
+
+/// Adds one to `key`'s count, without allocating if it's been seen before.
+pub(crate) fn bump(counts: &mut Counts, key: &str) {
+    match counts.get_mut(key) {
+        Some(n) => *n += 1,
+        None => {
+            counts.insert(key.to_string(), 1);
+        }
+    }
+}
+
+/// The counts for the whole run. Runner threads count locally, and add theirs
+/// in here when they exit.
+#[derive(Default)]
+pub(crate) struct Outcomes {
+    counts: Mutex<Counts>,
+}
+
+impl Outcomes {
+    pub(crate) fn record(&self, key: &str) {
+        bump(
+            &mut self.counts.lock().unwrap_or_else(PoisonError::into_inner),
+            key,
+        );
+    }
+
+    pub(crate) fn merge(&self, local: &Counts) {
+        if local.is_empty() {
+            return;
+        }
+        let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        for (key, n) in local {
+            *counts.entry(key.clone()).or_default() += n;
+        }
+    }
+
+    /// The counts, with any of the `expected` outcomes that never happened
+    /// in there as 0.
+    pub(crate) fn get(&self, expected: &[&str]) -> BTreeMap<String, u64> {
+        let counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut all = counts
+            .iter()
+            .map(|(k, &n)| (k.clone(), n))
+            .collect::<BTreeMap<_, _>>();
+        for &e in expected {
+            all.entry(e.to_string()).or_default();
+        }
+        all
+    }
+}
+
+/// The report printed at the end of the run, or `None` if nothing was
+/// recorded (or expected).
+pub(crate) fn report(counts: &BTreeMap<String, u64>) -> Option<String> {
+    if counts.is_empty() {
+        return None;
+    }
+    let total = counts.values().sum::<u64>();
+    let width = counts.keys().map(|k| k.chars().count()).max().unwrap_or(0);
+    let mut s = "outcomes:".to_string();
+    let mut sorted = counts.iter().collect::<Vec<_>>();
+    sorted.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    for (key, &n) in &sorted {
+        s += &format!(
+            "\n  {:<width$} {:>10} ({:.1}%)",
+            key,
+            n,
+            n as f64 * 100.0 / total.max(1) as f64,
+            width = width
+        );
+    }
+    let never = sorted
+        .iter()
+        .filter(|(_, &n)| n == 0)
+        .map(|(k, _)| k.as_str())
+        .collect::<Vec<_>>();
+    if !never.is_empty() {
+        // A legal outcome that never turns up usually means the test (or the
+        // code) can't actually get there.
+        s += &format!("\n  never happened: {}", never.join(", "));
+    }
+    Some(s)
+}
@@ -0,0 +1,77 @@
+//! Per-thread output. See `TestCtx::stdout` and `TestCfg::capture_output`.
+use crate::messages::Source;
+use crate::TestCtx;
+use std::io::{self, Write};
+
+/// Where `TestCtx::stdout` and `TestCtx::stderr` write. With
+/// `TestCfg::capture_output`, each line is kept (with when it was written) to
+/// be printed if the iteration fails. Otherwise, it goes straight to the
+/// process's stdout or stderr a whole line at a time, marked with the thread
+/// that wrote it. A partial line is finished when this is dropped.
+pub struct ThreadOutput<'a> {
+    ctx: &'a TestCtx,
+    stderr: bool,
+    line: Vec<u8>,
+}
+
+impl<'a> ThreadOutput<'a> {
+    pub(crate) fn new(ctx: &'a TestCtx, stderr: bool) -> Self {
+        Self {
+            ctx,
+            stderr,
+            line: vec![],
+        }
+    }
+
+    fn emit(&mut self) -> io::Result<()> {
+        let text = String::from_utf8_lossy(&self.line);
+        let text = text.strip_suffix('\n').unwrap_or(&text);
+        let mut output = self.ctx.output.borrow_mut();
+        let res = if output.enabled() {
+            let source = if self.stderr {
+                Source::Stderr
+            } else {
+                Source::Stdout
+            };
+            output.push(source, format_args!("{}", text));
+            Ok(())
+        } else {
+            let prefix = format!(
+                "[group {} thread {}] ",
+                self.ctx.group, self.ctx.thread_index
+            );
+            let line = format!("{}{}\n", prefix, text);
+            match self.stderr {
+                true => io::stderr().write_all(line.as_bytes()),
+                false => io::stdout().write_all(line.as_bytes()),
+            }
+        };
+        self.line.clear();
+        res
+    }
+}
+
+impl Write for ThreadOutput<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while let Some(i) = rest.iter().position(|&b| b == b'\n') {
+            self.line.extend_from_slice(&rest[..=i]);
+            self.emit()?;
+            rest = &rest[i + 1..];
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for ThreadOutput<'_> {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            let _ = self.emit();
+        }
+    }
+}
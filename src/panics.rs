@@ -0,0 +1,123 @@
+//! Catching panics along with where they happened, on which thread, and a
+//! backtrace, which are all gone by the time `catch_unwind` returns.
+use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::sync::{Arc, Mutex, Once, PoisonError};
+
+/// What cobb kept about a panic, besides its message. See
+/// `CobbError::panic_details`.
+pub struct PanicDetails {
+    /// Where it panicked, as `file:line:column`.
+    pub location: Option<String>,
+    /// The name of the thread that panicked.
+    pub thread: Option<String>,
+    /// If one could be taken.
+    pub backtrace: Option<Backtrace>,
+    payload: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+impl PanicDetails {
+    /// The value the code panicked with, as passed to `panic_any` (or the
+    /// message, for `panic!`). Only the first call gets it, since it can't be
+    /// cloned.
+    pub fn take_payload(&self) -> Option<Box<dyn Any + Send>> {
+        self.payload
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    /// For a panic that wasn't caught by `catch`, so all there is is the
+    /// payload.
+    pub(crate) fn from_payload(payload: Box<dyn Any + Send>) -> Arc<Self> {
+        Arc::new(Self {
+            location: None,
+            thread: None,
+            backtrace: None,
+            payload: Mutex::new(Some(payload)),
+        })
+    }
+}
+
+impl fmt::Debug for PanicDetails {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PanicDetails")
+            .field("location", &self.location)
+            .field("thread", &self.thread)
+            .field("backtrace", &self.backtrace.as_ref().map(|_| ".."))
+            .finish_non_exhaustive()
+    }
+}
+
+/// What the hook saw, for `catch` to pick up.
+struct Seen {
+    /// To tell whether it's the panic `catch` caught.
+    message: String,
+    location: Option<String>,
+    thread: Option<String>,
+    backtrace: Backtrace,
+}
+
+thread_local! {
+    /// Whether this thread is inside `catch`.
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+    static SEEN: RefCell<Option<Seen>> = const { RefCell::new(None) };
+}
+
+/// Adds a panic hook that takes note of the panic for `catch`, and then calls
+/// whatever hook was there before.
+fn install() {
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| {
+        let prev = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(Cell::get) {
+                let seen = Seen {
+                    message: crate::extract_msg(info.payload()),
+                    location: info.location().map(ToString::to_string),
+                    thread: std::thread::current().name().map(Into::into),
+                    backtrace: Backtrace::force_capture(),
+                };
+                SEEN.with(|s| *s.borrow_mut() = Some(seen));
+            }
+            prev(info);
+        }));
+    });
+}
+
+/// A caught panic's message and details.
+pub(crate) type Caught = (String, Arc<PanicDetails>);
+
+/// Like `catch_unwind`, but if `f` panics, returns its message and details.
+pub(crate) fn catch<R>(f: impl FnOnce() -> R) -> Result<R, Caught> {
+    install();
+    // A panic that was caught some other way (say, by the test's own
+    // `catch_unwind`) has nothing to do with this one, which might not go
+    // through the hook at all if it's a `resume_unwind`.
+    SEEN.with(|s| s.borrow_mut().take());
+    let was = CATCHING.with(|c| c.replace(true));
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+    CATCHING.with(|c| c.set(was));
+    res.map_err(|payload| {
+        let message = crate::extract_msg(&*payload);
+        // The last panic the hook saw might have been caught inside `f`,
+        // with this being a different one passed to `resume_unwind`.
+        let seen = SEEN
+            .with(|s| s.borrow_mut().take())
+            .filter(|seen| seen.message == message);
+        let details = match seen {
+            Some(seen) => Arc::new(PanicDetails {
+                location: seen.location,
+                thread: seen.thread,
+                backtrace: Some(seen.backtrace)
+                    .filter(|bt| bt.status() == BacktraceStatus::Captured),
+                payload: Mutex::new(Some(payload)),
+            }),
+            // `resume_unwind` doesn't call the hook.
+            None => PanicDetails::from_payload(payload),
+        };
+        (message, details)
+    })
+}
@@ -0,0 +1,107 @@
+//! Probabilistic concurrency testing, from "A Randomized Scheduler with
+//! Probabilistic Guarantees of Finding Bugs" (Burckhardt et al). Each
+//! iteration, the threads get random distinct priorities, and at `depth - 1`
+//! randomly chosen schedule points the thread that hits it drops to a priority
+//! lower than everyone's. At each schedule point, a thread waits for any
+//! higher priority threads that are still running.
+//!
+//! We can't tell whether a thread is blocked (say, on a lock held by a lower
+//! priority thread), so the waiting gives up after a little while, rather
+//! than deadlocking.
+use crate::{Rng, Scheduler, SpInfo};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for higher priority threads before assuming they're
+/// blocked.
+const MAX_WAIT: Duration = Duration::from_millis(2);
+
+pub(crate) struct PctShared {
+    depth: usize,
+    priorities: Vec<AtomicUsize>,
+    running: Vec<AtomicBool>,
+    /// Schedule points hit so far this iteration, by all threads.
+    steps: AtomicUsize,
+    /// Which steps lower the priority of the thread that hits them.
+    change_points: Vec<AtomicUsize>,
+}
+
+impl PctShared {
+    pub(crate) fn new(threads: usize, depth: usize) -> Self {
+        let depth = depth.max(1);
+        Self {
+            depth,
+            priorities: (0..threads).map(|_| AtomicUsize::new(0)).collect(),
+            running: (0..threads).map(|_| AtomicBool::new(false)).collect(),
+            steps: AtomicUsize::new(0),
+            change_points: (1..depth).map(|_| AtomicUsize::new(usize::MAX)).collect(),
+        }
+    }
+
+    /// Picks new priorities and change points. Only call between iterations.
+    pub(crate) fn new_iteration(&self, rng: &mut Rng, participating: impl Fn(usize) -> bool) {
+        // The number of steps there'll be is a guess, based on the last
+        // iteration.
+        let steps = self.steps.swap(0, Ordering::Relaxed).max(1);
+        let mut pris = (self.depth..self.depth + self.priorities.len()).collect::<Vec<_>>();
+        rng.shuffle(&mut pris);
+        for (p, v) in self.priorities.iter().zip(pris) {
+            p.store(v, Ordering::Relaxed);
+        }
+        for c in &self.change_points {
+            c.store(rng.upto(steps), Ordering::Relaxed);
+        }
+        // Threads count as running from the start of the iteration, rather
+        // than from when they get going, so that a low priority thread that
+        // happens to start first still waits for the others.
+        for (t, r) in self.running.iter().enumerate() {
+            r.store(participating(t), Ordering::Relaxed);
+        }
+    }
+
+    fn outranked(&self, me: usize) -> bool {
+        let mine = self.priorities[me].load(Ordering::Relaxed);
+        (0..self.priorities.len()).any(|t| {
+            t != me
+                && self.running[t].load(Ordering::Relaxed)
+                && self.priorities[t].load(Ordering::Relaxed) > mine
+        })
+    }
+}
+
+pub(crate) struct PctScheduler {
+    shared: Arc<PctShared>,
+    thread: usize,
+}
+
+impl PctScheduler {
+    pub(crate) fn new(shared: Arc<PctShared>, thread: usize) -> Self {
+        Self { shared, thread }
+    }
+}
+
+impl Scheduler for PctScheduler {
+    fn end_iteration(&mut self) {
+        self.shared.running[self.thread].store(false, Ordering::Relaxed);
+    }
+    fn perturb(&mut self, sp: &SpInfo<'_>) {
+        let s = &*self.shared;
+        let step = s.steps.fetch_add(1, Ordering::Relaxed);
+        if let Some(i) = s
+            .change_points
+            .iter()
+            .position(|c| c.load(Ordering::Relaxed) == step)
+        {
+            s.priorities[self.thread].store(i, Ordering::Relaxed);
+        }
+        if s.outranked(self.thread) {
+            sp.note_yield();
+        }
+        let start = Instant::now();
+        while s.outranked(self.thread) && start.elapsed() < MAX_WAIT {
+            thread::yield_now();
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{run_with_hooks, CachePad, Hooks, IterCtx, TestCfg, TestCtx};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, TryLockError};
+
+/// A test where each thread owns its own value, rather than all of them
+/// sharing one. This only needs `U: Send`, which is handy for testing things
+/// that are themselves the sharing mechanism, like channels (where each thread
+/// owns its `Sender` or `Receiver`).
+pub struct PerThreadCfg<U> {
+    /// Everything other than the callbacks. The callbacks in here are ignored
+    /// in favor of the ones below.
+    pub cfg: TestCfg<PerThread<U>>,
+    /// Gets passed the number of threads, and must return a value for each.
+    pub setup: fn(usize) -> Vec<U>,
+    pub teardown: fn(&mut PerThread<U>),
+    /// Gets passed the value owned by the thread it's running on.
+    pub test: fn(&mut U, &TestCtx),
+    pub before_each: fn(&PerThread<U>),
+    pub after_each: fn(&PerThread<U>, &IterCtx<'_>),
+}
+
+impl<U> Default for PerThreadCfg<U> {
+    fn default() -> Self {
+        Self {
+            cfg: TestCfg::default(),
+            setup: |_| panic!("please provide setup"),
+            teardown: |_| {},
+            test: |_, _| {},
+            before_each: |_| {},
+            after_each: |_, _| {},
+        }
+    }
+}
+
+/// A value for each thread. These are what each thread owns in a
+/// `PerThreadCfg` test, but they're also useful as part of the shared state of
+/// a normal test, for per-thread bookkeeping (like accumulators that
+/// `after_each` sums up), without the atomics or `UnsafeCell`s obscuring the
+/// code actually being tested.
+///
+/// ```
+/// # use std::sync::atomic::{AtomicUsize, Ordering};
+/// struct State {
+///     shared: AtomicUsize,
+///     seen: cobb::PerThread<usize>,
+/// }
+/// cobb::run_test(cobb::TestCfg::<State> {
+///     threads: 4,
+///     iterations: 10,
+///     setup: || State {
+///         shared: AtomicUsize::new(0),
+///         seen: cobb::PerThread::new(4, |_| 0),
+///     },
+///     before_each: |s| s.seen.reset(),
+///     test: |s, tctx| *s.seen.shard(tctx) += s.shared.fetch_add(1, Ordering::Relaxed),
+///     after_each: |s, _| {
+///         let n = s.shared.load(Ordering::Relaxed);
+///         assert_eq!(s.seen.iter().map(|v| *v).sum::<usize>(), (n - 4..n).sum::<usize>());
+///     },
+///     ..Default::default()
+/// });
+/// ```
+pub struct PerThread<U> {
+    values: Vec<CachePad<Mutex<U>>>,
+}
+
+impl<U> PerThread<U> {
+    /// Creates one value for each of `threads` threads, by calling `f` with
+    /// each thread index.
+    pub fn new(threads: usize, f: impl FnMut(usize) -> U) -> Self {
+        Self::from_vec((0..threads).map(f).collect())
+    }
+    /// Uses the `i`th value for the `i`th thread.
+    pub fn from_vec(values: Vec<U>) -> Self {
+        Self {
+            values: values
+                .into_iter()
+                .map(|v| CachePad::new(Mutex::new(v)))
+                .collect(),
+        }
+    }
+    /// The value belonging to the thread `ctx` is for. Only that thread can
+    /// get at it this way, and it's exclusive, so this panics rather than
+    /// blocking if it's already borrowed.
+    pub fn shard(&self, ctx: &TestCtx) -> MutexGuard<'_, U> {
+        let slot = self.values.get(ctx.thread_index()).unwrap_or_else(|| {
+            panic!(
+                "Cobb: no value for thread {} (only have {})",
+                ctx.thread_index(),
+                self.len()
+            )
+        });
+        match slot.try_lock() {
+            Ok(g) => g,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => panic!(
+                "Cobb: the value for thread {} is already borrowed",
+                ctx.thread_index()
+            ),
+        }
+    }
+    /// Resets all the values to their default.
+    pub fn reset(&self)
+    where
+        U: Default,
+    {
+        for mut v in self.iter() {
+            *v = U::default();
+        }
+    }
+    /// The number of values (which is the number of threads).
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+    /// The value owned by thread `thread_index`.
+    ///
+    /// Only use this from `before_each`/`after_each` and friends, since it
+    /// will block if the thread is running.
+    pub fn get(&self, thread_index: usize) -> MutexGuard<'_, U> {
+        self.values[thread_index]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+    pub fn get_mut(&mut self, thread_index: usize) -> &mut U {
+        self.values[thread_index]
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Iterates over the values, in thread order.
+    pub fn iter(&self) -> impl Iterator<Item = MutexGuard<'_, U>> + '_ {
+        (0..self.len()).map(move |i| self.get(i))
+    }
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut U> + '_ {
+        self.values
+            .iter_mut()
+            .map(|v| v.get_mut().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+/// Like `run_test`, for a `PerThreadCfg`.
+pub fn run_test_per_thread<U: Send + 'static>(test: PerThreadCfg<U>) {
+    let PerThreadCfg {
+        cfg,
+        setup,
+        teardown,
+        test,
+        before_each,
+        after_each,
+    } = test;
+    let hooks = Hooks {
+        setup: Arc::new(move |threads| {
+            let values = setup(threads);
+            assert_eq!(
+                values.len(),
+                threads,
+                "Cobb: setup must return one value per thread"
+            );
+            PerThread::from_vec(values)
+        }),
+        teardown: Arc::new(teardown),
+        phases: vec![Arc::new(move |state: &PerThread<U>, ctx: &TestCtx| {
+            test(&mut state.shard(ctx), ctx)
+        })],
+        owner: None,
+        before_each: Arc::new(before_each),
+        after_each: Arc::new(after_each),
+    };
+    run_with_hooks(cfg, hooks);
+}
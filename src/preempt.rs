@@ -0,0 +1,175 @@
+//! Interrupting the process with a timer signal every so often, so that the
+//! kernel gets lots of chances to preempt runner threads. See
+//! `TestCfg::preempt`.
+use crate::Rng;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// How often `TestCfg::preempt` interrupts the process. Each iteration picks
+/// a random interval between `min` and `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreemptCfg {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl Default for PreemptCfg {
+    fn default() -> Self {
+        Self {
+            min: Duration::from_micros(100),
+            max: Duration::from_millis(1),
+        }
+    }
+}
+
+/// Whether `TestCfg::preempt` does anything on this platform.
+pub(crate) const SUPPORTED: bool = imp::SUPPORTED;
+
+/// The timer is per process, so groups (and concurrent runs) share it. This
+/// counts the ones using it, so it's only disarmed (and the signal's old
+/// handler put back) once they're all done.
+static USERS: Mutex<usize> = Mutex::new(0);
+
+/// Keeps the timer armed until dropped.
+pub(crate) struct Preempter {
+    cfg: PreemptCfg,
+}
+
+impl Preempter {
+    pub(crate) fn start(cfg: PreemptCfg, rng: &mut Rng) -> Self {
+        let mut users = USERS.lock().unwrap_or_else(PoisonError::into_inner);
+        if *users == 0 {
+            imp::init();
+        }
+        *users += 1;
+        let p = Self { cfg };
+        p.rearm(rng);
+        p
+    }
+
+    /// Picks a new interval.
+    pub(crate) fn rearm(&self, rng: &mut Rng) {
+        let min = self.cfg.min.as_micros() as usize;
+        let max = (self.cfg.max.as_micros() as usize).max(min);
+        imp::arm(rng.between(min..max + 1).max(1) as u64);
+    }
+}
+
+impl Drop for Preempter {
+    fn drop(&mut self) {
+        let mut users = USERS.lock().unwrap_or_else(PoisonError::into_inner);
+        *users -= 1;
+        if *users == 0 {
+            imp::arm(0);
+            imp::finish();
+        }
+    }
+}
+
+/// `setitimer` with `ITIMER_REAL`, which is high resolution (unlike
+/// `ITIMER_PROF` on Linux, which only goes off on scheduler ticks). The
+/// handler does nothing: the point is the trip through the kernel.
+///
+/// Only on the unixes where `signal` installs the handler for good, with
+/// `SA_RESTART`. On System V ones like illumos, it goes back to the default
+/// after the first signal, which would kill the process on the next.
+#[cfg(all(
+    any(
+        target_os = "linux",
+        target_os = "android",
+        target_vendor = "apple",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd",
+    ),
+    not(miri)
+))]
+mod imp {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    pub(super) const SUPPORTED: bool = true;
+
+    const SIGALRM: i32 = 14;
+    const ITIMER_REAL: i32 = 0;
+
+    #[cfg(target_vendor = "apple")]
+    type Usec = i32;
+    #[cfg(not(target_vendor = "apple"))]
+    type Usec = core::ffi::c_long;
+
+    #[repr(C)]
+    struct Timeval {
+        tv_sec: core::ffi::c_long,
+        tv_usec: Usec,
+    }
+
+    #[repr(C)]
+    struct Itimerval {
+        it_interval: Timeval,
+        it_value: Timeval,
+    }
+
+    extern "C" {
+        fn setitimer(which: i32, new: *const Itimerval, old: *mut Itimerval) -> i32;
+        fn signal(sig: i32, handler: usize) -> usize;
+    }
+
+    const SIG_ERR: usize = !0;
+
+    /// The handler there was before `init`.
+    static PREVIOUS: AtomicUsize = AtomicUsize::new(SIG_ERR);
+
+    extern "C" fn on_signal(_sig: i32) {}
+
+    pub(super) fn init() {
+        // `signal` sets `SA_RESTART`, which restarts most interrupted
+        // syscalls, but not the ones with timeouts (see `TestCfg::preempt`).
+        let previous = unsafe { signal(SIGALRM, on_signal as extern "C" fn(i32) as usize) };
+        PREVIOUS.store(previous, Ordering::Relaxed);
+    }
+
+    /// Puts back the handler from before `init`. The timer has to be
+    /// disarmed first, since the old handler might well be the default one,
+    /// which kills the process.
+    pub(super) fn finish() {
+        let previous = PREVIOUS.swap(SIG_ERR, Ordering::Relaxed);
+        if previous != SIG_ERR {
+            unsafe { signal(SIGALRM, previous) };
+        }
+    }
+
+    /// Arms the timer to go off every `micros` microseconds, or disarms it if
+    /// that's 0.
+    pub(super) fn arm(micros: u64) {
+        let tv = || Timeval {
+            tv_sec: (micros / 1_000_000) as _,
+            tv_usec: (micros % 1_000_000) as _,
+        };
+        let it = Itimerval {
+            it_interval: tv(),
+            it_value: tv(),
+        };
+        unsafe { setitimer(ITIMER_REAL, &it, core::ptr::null_mut()) };
+    }
+}
+
+#[cfg(not(all(
+    any(
+        target_os = "linux",
+        target_os = "android",
+        target_vendor = "apple",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd",
+    ),
+    not(miri)
+)))]
+mod imp {
+    pub(super) const SUPPORTED: bool = false;
+
+    pub(super) fn init() {}
+    pub(super) fn finish() {}
+    pub(super) fn arm(_micros: u64) {}
+}
@@ -0,0 +1,250 @@
+//! Making runner threads high or low priority as far as the OS is concerned.
+//! See `TestCfg::priorities` and `TestCfg::reprioritize`.
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How low priority threads are made low priority. See
+/// `TestCfg::low_priority`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LowPriority {
+    /// The lowest ordinary priority: a nice value of 19 on Linux,
+    /// `THREAD_PRIORITY_LOWEST` on Windows, and the bottom of the
+    /// `SCHED_OTHER` range on Apple platforms.
+    #[default]
+    Lowest,
+    /// Only run when nothing else wants to: `SCHED_IDLE` on Linux, background
+    /// mode on Windows, and the background band (as with background QoS) on
+    /// Apple platforms. Besides the CPU, the last two lower the thread's I/O
+    /// priority. This starves the thread far more than `Lowest` does.
+    Background,
+}
+
+/// A realtime scheduling policy for high priority threads. See
+/// `TestCfg::realtime`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Realtime {
+    /// `SCHED_FIFO`: the thread runs until it blocks or yields.
+    Fifo,
+    /// `SCHED_RR`: like `Fifo`, but threads at the same priority take turns.
+    RoundRobin,
+}
+
+/// Makes the current thread high or low priority, warning (once) if that
+/// fails. If making it realtime fails, it's just made high priority.
+pub(crate) fn set_current(high: bool, low: LowPriority, realtime: Option<Realtime>) {
+    static WARNED: AtomicBool = AtomicBool::new(false);
+    static WARNED_REALTIME: AtomicBool = AtomicBool::new(false);
+    if let (true, Some(rt)) = (high, realtime) {
+        match imp::set_realtime(rt) {
+            Ok(()) => return,
+            Err(e) if !WARNED_REALTIME.swap(true, Ordering::Relaxed) => {
+                eprintln!(
+                    "cobb: failed to make a thread realtime, so it'll just be high priority: {}",
+                    e
+                );
+            }
+            Err(_) => {}
+        }
+    }
+    if let Err(e) = imp::set_current(high, low) {
+        if !WARNED.swap(true, Ordering::Relaxed) {
+            eprintln!("cobb: failed to set a thread's priority: {}", e);
+        }
+    }
+}
+
+/// On Linux, both of these are per thread, given its thread ID. Going back up
+/// from either needs `CAP_SYS_NICE`, or a high enough `RLIMIT_NICE`.
+#[cfg(all(target_os = "linux", not(miri)))]
+mod imp {
+    use super::{LowPriority, Realtime};
+    use std::io::{Error, Result};
+
+    const PRIO_PROCESS: i32 = 0;
+    const SCHED_OTHER: i32 = 0;
+    const SCHED_FIFO: i32 = 1;
+    const SCHED_RR: i32 = 2;
+    const SCHED_IDLE: i32 = 5;
+
+    #[repr(C)]
+    struct SchedParam {
+        priority: i32,
+    }
+
+    extern "C" {
+        fn gettid() -> i32;
+        fn setpriority(which: i32, who: u32, prio: i32) -> i32;
+        fn sched_setscheduler(pid: i32, policy: i32, param: *const SchedParam) -> i32;
+    }
+
+    fn check(ret: i32) -> Result<()> {
+        match ret {
+            0 => Ok(()),
+            _ => Err(Error::last_os_error()),
+        }
+    }
+
+    pub(super) fn set_current(high: bool, low: LowPriority) -> Result<()> {
+        let tid = unsafe { gettid() };
+        let policy =
+            |policy| check(unsafe { sched_setscheduler(tid, policy, &SchedParam { priority: 0 }) });
+        let nice = |nice| check(unsafe { setpriority(PRIO_PROCESS, tid as u32, nice) });
+        match (high, low) {
+            (true, _) => policy(SCHED_OTHER).and_then(|_| nice(0)),
+            (false, LowPriority::Lowest) => policy(SCHED_OTHER).and_then(|_| nice(19)),
+            (false, LowPriority::Background) => policy(SCHED_IDLE),
+        }
+    }
+
+    /// This uses the lowest realtime priority, which is still above every
+    /// ordinary thread. It needs `CAP_SYS_NICE`, or a nonzero
+    /// `RLIMIT_RTPRIO`.
+    pub(super) fn set_realtime(rt: Realtime) -> Result<()> {
+        let policy = match rt {
+            Realtime::Fifo => SCHED_FIFO,
+            Realtime::RoundRobin => SCHED_RR,
+        };
+        let param = SchedParam { priority: 1 };
+        check(unsafe { sched_setscheduler(gettid(), policy, &param) })
+    }
+}
+
+#[cfg(all(windows, not(miri)))]
+mod imp {
+    use super::{LowPriority, Realtime};
+    use std::io::{Error, ErrorKind, Result};
+
+    const THREAD_PRIORITY_NORMAL: i32 = 0;
+    const THREAD_PRIORITY_LOWEST: i32 = -2;
+    const THREAD_MODE_BACKGROUND_BEGIN: i32 = 0x0001_0000;
+    const THREAD_MODE_BACKGROUND_END: i32 = 0x0002_0000;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentThread() -> usize;
+        fn SetThreadPriority(thread: usize, priority: i32) -> i32;
+    }
+
+    fn set(priority: i32) -> Result<()> {
+        match unsafe { SetThreadPriority(GetCurrentThread(), priority) } {
+            0 => Err(Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    pub(super) fn set_current(high: bool, low: LowPriority) -> Result<()> {
+        match (high, low) {
+            (true, LowPriority::Lowest) => set(THREAD_PRIORITY_NORMAL),
+            // This fails if the thread isn't in background mode, which is
+            // fine: that's where we wanted it.
+            (true, LowPriority::Background) => {
+                let _ = set(THREAD_MODE_BACKGROUND_END);
+                Ok(())
+            }
+            (false, LowPriority::Lowest) => set(THREAD_PRIORITY_LOWEST),
+            (false, LowPriority::Background) => set(THREAD_MODE_BACKGROUND_BEGIN),
+        }
+    }
+
+    pub(super) fn set_realtime(_rt: Realtime) -> Result<()> {
+        Err(ErrorKind::Unsupported.into())
+    }
+}
+
+/// On Apple platforms, `Background` is the Darwin background band (the same
+/// thing background QoS uses), and `Lowest` is the bottom of the ordinary
+/// priority range.
+#[cfg(all(target_vendor = "apple", not(miri)))]
+mod imp {
+    use super::{LowPriority, Realtime};
+    use std::cell::Cell;
+    use std::io::{Error, ErrorKind, Result};
+
+    const PRIO_DARWIN_THREAD: i32 = 3;
+    const PRIO_DARWIN_BG: i32 = 0x1000;
+    const SCHED_OTHER: i32 = 1;
+
+    #[repr(C)]
+    struct SchedParam {
+        priority: i32,
+        _opaque: [u8; 4],
+    }
+
+    extern "C" {
+        fn setpriority(which: i32, who: u32, prio: i32) -> i32;
+        fn pthread_self() -> usize;
+        fn pthread_getschedparam(thread: usize, policy: *mut i32, param: *mut SchedParam) -> i32;
+        fn pthread_setschedparam(thread: usize, policy: i32, param: *const SchedParam) -> i32;
+        fn sched_get_priority_min(policy: i32) -> i32;
+    }
+
+    thread_local! {
+        /// The thread's policy and priority before we first lowered it.
+        static DEFAULT: Cell<Option<(i32, i32)>> = const { Cell::new(None) };
+    }
+
+    fn set_sched(policy: i32, priority: i32) -> Result<()> {
+        let param = SchedParam {
+            priority,
+            _opaque: [0; 4],
+        };
+        // This one returns the error rather than setting errno.
+        match unsafe { pthread_setschedparam(pthread_self(), policy, &param) } {
+            0 => Ok(()),
+            e => Err(Error::from_raw_os_error(e)),
+        }
+    }
+
+    fn default_sched() -> (i32, i32) {
+        DEFAULT.with(|d| {
+            if let Some(sched) = d.get() {
+                return sched;
+            }
+            let mut policy = SCHED_OTHER;
+            let mut param = SchedParam {
+                priority: 31,
+                _opaque: [0; 4],
+            };
+            unsafe { pthread_getschedparam(pthread_self(), &mut policy, &mut param) };
+            d.set(Some((policy, param.priority)));
+            (policy, param.priority)
+        })
+    }
+
+    fn set_band(prio: i32) -> Result<()> {
+        // `who` 0 is the calling thread.
+        match unsafe { setpriority(PRIO_DARWIN_THREAD, 0, prio) } {
+            0 => Ok(()),
+            _ => Err(Error::last_os_error()),
+        }
+    }
+
+    pub(super) fn set_current(high: bool, low: LowPriority) -> Result<()> {
+        let (policy, priority) = default_sched();
+        match (high, low) {
+            (true, LowPriority::Lowest) => set_sched(policy, priority),
+            (true, LowPriority::Background) => set_band(0),
+            (false, LowPriority::Lowest) => {
+                set_sched(SCHED_OTHER, unsafe { sched_get_priority_min(SCHED_OTHER) })
+            }
+            (false, LowPriority::Background) => set_band(PRIO_DARWIN_BG),
+        }
+    }
+
+    pub(super) fn set_realtime(_rt: Realtime) -> Result<()> {
+        Err(ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(not(all(any(target_os = "linux", windows, target_vendor = "apple"), not(miri))))]
+mod imp {
+    use super::{LowPriority, Realtime};
+    use std::io::{ErrorKind, Result};
+
+    pub(super) fn set_current(_high: bool, _low: LowPriority) -> Result<()> {
+        Ok(())
+    }
+
+    pub(super) fn set_realtime(_rt: Realtime) -> Result<()> {
+        Err(ErrorKind::Unsupported.into())
+    }
+}
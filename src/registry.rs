@@ -0,0 +1,58 @@
+use crate::TestSpec;
+
+#[doc(hidden)]
+pub struct Registered {
+    pub name: &'static str,
+    pub run: fn(),
+}
+
+inventory::collect!(Registered);
+
+/// Registers a cobb test, to be run by `run_registered`, from anywhere in the
+/// crate. Similar to `#[test]`, but for cobb tests.
+///
+/// The test is named after its module path and the identifier given, and this
+/// is also used as `TestCfg::name` if that isn't set.
+///
+/// ```ignore
+/// cobb::register_test!(push_pop, cobb::TestCfg::<Stack> {
+///     setup: Stack::new,
+///     test: |stk, _| { stk.push(1); stk.pop(); },
+///     ..Default::default()
+/// });
+/// ```
+#[macro_export]
+macro_rules! register_test {
+    ($name:ident, $cfg:expr $(,)?) => {
+        const _: () = {
+            fn run() {
+                let mut cfg = $cfg;
+                cfg.name
+                    .get_or_insert(concat!(module_path!(), "::", stringify!($name)));
+                $crate::run_test(cfg)
+            }
+            $crate::__private::inventory::submit! {
+                $crate::__private::Registered {
+                    name: concat!(module_path!(), "::", stringify!($name)),
+                    run,
+                }
+            }
+        };
+    };
+}
+
+/// All the tests registered with `register_test!`, sorted by name.
+pub fn registered_tests() -> Vec<TestSpec> {
+    let mut tests = inventory::iter::<Registered>
+        .into_iter()
+        .map(|r| TestSpec::from_fn(r.name, r.run))
+        .collect::<Vec<_>>();
+    tests.sort_by(|a, b| a.name().cmp(b.name()));
+    tests
+}
+
+/// Runs all the tests registered with `register_test!` using `run_tests`, so
+/// `COBB_FILTER` can be used to select them.
+pub fn run_registered() {
+    crate::run_tests(&registered_tests());
+}
@@ -0,0 +1,426 @@
+use crate::Rng;
+use std::cell::Cell;
+use std::panic::Location;
+use std::thread;
+use std::time::Duration;
+
+/// Decides what happens at a runner thread's schedule points (`TestCtx::sp`),
+/// for when cobb's usual mix of sleeps, yields and spins isn't what you want.
+/// Each runner thread gets its own, from `TestCfg::scheduler`.
+pub trait Scheduler {
+    /// Called at each schedule point, to delay the thread or give up its time
+    /// slice or whatever else might shake out a bug.
+    fn perturb(&mut self, sp: &SpInfo<'_>);
+    /// Called when the thread starts running the test for an iteration it's
+    /// participating in.
+    fn begin_iteration(&mut self, _iteration: usize) {}
+    /// Called when the thread's done with an iteration.
+    fn end_iteration(&mut self) {}
+    /// Called before the thread blocks in cobb (say, in `TestCtx::barrier`),
+    /// so that schedulers that only run one thread at a time can let another
+    /// one go.
+    fn before_block(&mut self) {}
+    /// Called once the thread is done blocking.
+    fn after_block(&mut self) {}
+    /// Called when the thread can't go on until another one does something,
+    /// say because a `cobb::sync::Mutex` it wants is held. Returning true
+    /// means the scheduler let another thread run for a while, and the thread
+    /// will check again rather than blocking. The default returns false, so it
+    /// just blocks.
+    fn contended(&mut self) -> bool {
+        false
+    }
+}
+
+/// The scheduling strategies built into cobb. See `TestCfg::schedule`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScheduleMode {
+    /// Randomly delay threads at schedule points, according to
+    /// `TestCfg::sp_weights`.
+    #[default]
+    Random,
+    /// Probabilistic concurrency testing: threads get random priorities, which
+    /// change at `depth - 1` random schedule points each iteration, and at each
+    /// schedule point a thread waits for higher priority ones to go first.
+    ///
+    /// With `n` threads hitting `k` schedule points in total, each iteration
+    /// has at least a `1 / (n * k^(depth - 1))` chance of finding a bug that
+    /// needs `depth` things to happen in a particular order, which the random
+    /// mode can't promise. Most bugs have a depth of 1 or 2.
+    Pct { depth: usize },
+    /// Only one thread runs at a time, and at each schedule point an RNG
+    /// seeded from `TestCfg::seed` (and the group and iteration) picks which
+    /// one runs next. This makes each iteration's interleaving fully
+    /// reproducible from the seed, for hunting logic races (rather than data
+    /// races, since nothing actually runs concurrently).
+    ///
+    /// The test's threads must only ever wait on each other at schedule points,
+    /// `TestCtx::barrier`, or with the types in `cobb::sync`. Otherwise
+    /// they'll deadlock, since a thread that blocks some other way keeps its
+    /// turn.
+    Cooperative,
+    /// Like `Cooperative`, but rather than picking which thread goes next at
+    /// random, each iteration runs a different schedule, enumerating all of
+    /// them with no preemptions (switching away from a thread at a schedule
+    /// point even though it could keep going), then the ones with 1, and so on
+    /// up to `max_preemptions`. The run stops early once it's tried them all,
+    /// and reports how much it got through.
+    ///
+    /// The number of schedules grows very quickly, so this is only practical
+    /// for small tests with few threads and schedule points. The test should
+    /// behave the same each time it's given the same schedule, so don't use
+    /// `random_participation` or `thread_range` with it.
+    Systematic { max_preemptions: usize },
+    /// Like `Cooperative`, but guided by coverage: each iteration's sequence of
+    /// schedule points (which thread hit which `sp` call) is hashed, and future
+    /// iterations mostly replay the start of the choices that led to
+    /// interleavings that hadn't been seen before, and pick randomly from
+    /// there. This finds rare interleavings much faster than choosing at random
+    /// from the start every time. The run reports how many distinct ones it
+    /// saw at the end.
+    CoverageGuided,
+}
+
+/// Makes the scheduler for a runner thread, given its index and the number of
+/// threads in its group. See `TestCfg::scheduler`.
+pub type MakeScheduler = fn(usize, usize) -> Box<dyn Scheduler>;
+
+/// Where a thread is when it hits a schedule point.
+#[derive(Debug)]
+pub struct SpInfo<'a> {
+    pub(crate) group: usize,
+    pub(crate) thread_index: usize,
+    pub(crate) thread_count: usize,
+    pub(crate) iteration: usize,
+    pub(crate) sub_iteration: usize,
+    pub(crate) location: &'static Location<'static>,
+    pub(crate) rng: &'a Cell<Rng>,
+    pub(crate) probability: f64,
+    pub(crate) intensity: f64,
+    pub(crate) body_time: Option<Duration>,
+    pub(crate) hint: SpHint,
+    pub(crate) yielded: Cell<bool>,
+    /// What the default scheduler did, for `TestCfg::trace_dir`.
+    pub(crate) action: Cell<SpAction>,
+}
+
+impl SpInfo<'_> {
+    pub fn group(&self) -> usize {
+        self.group
+    }
+    pub fn thread_index(&self) -> usize {
+        self.thread_index
+    }
+    pub fn thread_count(&self) -> usize {
+        self.thread_count
+    }
+    pub fn iteration(&self) -> usize {
+        self.iteration
+    }
+    pub fn sub_iteration(&self) -> usize {
+        self.sub_iteration
+    }
+    /// The chance that this schedule point should do anything, from
+    /// `TestCtx::sp_with`. 1.0 for plain `sp`.
+    pub fn probability(&self) -> f64 {
+        self.probability
+    }
+    /// How hard to perturb the thread here, relative to a plain `sp` (which
+    /// is 1.0), including `TestCfg::intensity`. See `TestCtx::sp_with`.
+    pub fn intensity(&self) -> f64 {
+        self.intensity
+    }
+    /// Where `sp` was called from.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+    /// What the test said is going on here, from `TestCtx::sp_hint`.
+    pub fn hint(&self) -> SpHint {
+        self.hint
+    }
+    /// How long a call to the test fn typically takes, once
+    /// `TestCfg::calibrate` has measured it.
+    pub fn body_time(&self) -> Option<Duration> {
+        self.body_time
+    }
+    /// A random number from the RNG cobb uses for schedule points (which is
+    /// separate from `TestCtx::rng`).
+    pub fn random(&self) -> u64 {
+        let mut rng = self.rng.get();
+        let v = rng.gen();
+        self.rng.set(rng);
+        v
+    }
+    /// Returns true with probability `p`, using the same RNG as `random`.
+    pub fn chance(&self, p: f64) -> bool {
+        let mut rng = self.rng.get();
+        let v = rng.chance(p);
+        self.rng.set(rng);
+        v
+    }
+    /// Records that the thread yielded, slept or waited for another one here,
+    /// for `TestCfg::sp_report`.
+    pub fn note_yield(&self) {
+        self.yielded.set(true);
+    }
+}
+
+/// What's going on at a schedule point, for `TestCtx::sp_hint`. The default
+/// scheduler perturbs the thread differently for each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SpHint {
+    /// Nothing in particular, as with plain `sp`.
+    #[default]
+    None,
+    /// Between reading a value and a compare-exchange on it. The thread gets
+    /// held up briefly but always, so others get a chance to change the value
+    /// in between.
+    BeforeCas,
+    /// Right after making something visible to other threads. The thread
+    /// stalls for a long time, so the others can see it and act on it before
+    /// it goes on.
+    AfterPublish,
+    /// Inside a retry loop. The thread just yields, since stalling would only
+    /// slow down each retry without making for new interleavings.
+    InRetryLoop,
+    /// Around a syscall (or something else that goes through the kernel). The
+    /// thread sleeps for no time or yields, much like a real syscall might.
+    Syscall,
+}
+
+/// How often the default scheduler does each of the things it does at a
+/// schedule point. Each is a weight, so the chance of doing something is its
+/// weight over the sum of them all.
+///
+/// The defaults are a fairly gentle mix. Turn up `sleep` (and
+/// `sleep_duration`) for harsher delays, or set it to 0 if the millisecond
+/// sleeps make your test too slow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpWeights {
+    /// Sleep for zero time, which is a syscall but usually not a context switch.
+    pub sleep_zero: u32,
+    /// Sleep for `sleep_duration`.
+    pub sleep: u32,
+    pub sleep_duration: Duration,
+    /// Yield the time slice once.
+    pub yield_now: u32,
+    /// Yield a few times in a row.
+    pub yield_many: u32,
+    /// Spin for a bit with `spin_loop` hints.
+    pub spin: u32,
+    /// Run a short busy loop of a random length.
+    pub busy: u32,
+    /// Do nothing at all.
+    pub nothing: u32,
+}
+
+impl Default for SpWeights {
+    fn default() -> Self {
+        Self {
+            sleep_zero: 11,
+            sleep: 5,
+            sleep_duration: Duration::from_millis(1),
+            yield_now: 60,
+            yield_many: 31,
+            spin: 50,
+            busy: 99,
+            nothing: 0,
+        }
+    }
+}
+
+impl SpWeights {
+    /// Picks out the things that suit `hint`.
+    fn hinted(&self, hint: SpHint) -> Self {
+        let none = Self {
+            sleep_zero: 0,
+            sleep: 0,
+            sleep_duration: self.sleep_duration,
+            yield_now: 0,
+            yield_many: 0,
+            spin: 0,
+            busy: 0,
+            nothing: 0,
+        };
+        match hint {
+            SpHint::None => *self,
+            SpHint::BeforeCas => Self {
+                sleep: 0,
+                yield_many: 0,
+                nothing: 0,
+                ..*self
+            },
+            SpHint::AfterPublish => Self {
+                sleep: self.sleep.max(1) * 4,
+                sleep_duration: self.sleep_duration * 2,
+                spin: 0,
+                busy: 0,
+                nothing: 0,
+                ..*self
+            },
+            SpHint::InRetryLoop => Self {
+                yield_now: 1,
+                ..none
+            },
+            SpHint::Syscall => Self {
+                sleep_zero: 1,
+                yield_now: 1,
+                ..none
+            },
+        }
+    }
+
+    /// Makes the disruptive things (sleeping, and yielding a lot) `intensity`
+    /// times as likely, and the mild ones that much less likely, for
+    /// `TestCtx::sp_with`.
+    fn scaled(&self, intensity: f64) -> Self {
+        let intensity = intensity.max(0.001);
+        let more = |w: u32| (f64::from(w) * intensity).round() as u32;
+        let less = |w: u32| (f64::from(w) / intensity).round() as u32;
+        Self {
+            sleep_zero: more(self.sleep_zero),
+            sleep: more(self.sleep),
+            sleep_duration: self.sleep_duration.mul_f64(intensity),
+            yield_now: self.yield_now,
+            yield_many: more(self.yield_many),
+            spin: less(self.spin),
+            busy: less(self.busy),
+            nothing: less(self.nothing),
+        }
+    }
+}
+
+/// The scheduler used by default, which randomly sleeps, yields, spins, or
+/// does nothing much, according to its `SpWeights`.
+#[derive(Debug, Clone, Default)]
+pub struct RandomScheduler {
+    weights: SpWeights,
+}
+
+impl RandomScheduler {
+    pub fn new(weights: SpWeights) -> Self {
+        Self { weights }
+    }
+}
+
+impl Scheduler for RandomScheduler {
+    fn perturb(&mut self, sp: &SpInfo<'_>) {
+        if sp.probability < 1.0 && !sp.chance(sp.probability) {
+            sp.action.set(SpAction::Nothing);
+            return;
+        }
+        let mut weights = self.weights.hinted(sp.hint);
+        if sp.intensity != 1.0 {
+            weights = weights.scaled(sp.intensity);
+        }
+        if let Some(t) = sp.body_time {
+            weights.sleep_duration =
+                crate::calibrate::relative_delay(t, sp.random()).mul_f64(sp.intensity);
+        }
+        let action = pick_action(&weights, sp.random(), sp.intensity);
+        sp.action.set(action);
+        if action.perform() {
+            sp.note_yield();
+        }
+    }
+}
+
+/// Picks what to do, from the random `r`. Spins and busy loops are
+/// `intensity` times as long as usual.
+fn pick_action(w: &SpWeights, r: u64, intensity: f64) -> SpAction {
+    let total = [
+        w.sleep_zero,
+        w.sleep,
+        w.yield_now,
+        w.yield_many,
+        w.spin,
+        w.busy,
+        w.nothing,
+    ]
+    .iter()
+    .map(|&n| u64::from(n))
+    .sum::<u64>();
+    if total == 0 {
+        return SpAction::Nothing;
+    }
+    let mut pick = (r >> 32) % total;
+    let mut take = |weight: u32| {
+        if pick < u64::from(weight) {
+            true
+        } else {
+            pick -= u64::from(weight);
+            false
+        }
+    };
+    if take(w.sleep_zero) {
+        SpAction::SleepZero
+    } else if take(w.sleep) {
+        SpAction::Sleep(w.sleep_duration)
+    } else if take(w.yield_now) {
+        SpAction::Yield
+    } else if take(w.spin) {
+        SpAction::Spin((50.0 * intensity) as usize)
+    } else if take(w.yield_many) {
+        SpAction::YieldMany
+    } else if take(w.busy) {
+        SpAction::Busy(((126 + r % 99) as f64 * intensity) as usize)
+    } else {
+        SpAction::Nothing
+    }
+}
+
+/// What happened at a schedule point, as recorded in a `Trace`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpAction {
+    Nothing,
+    /// Slept for zero time.
+    SleepZero,
+    Sleep(Duration),
+    /// Yielded the time slice once.
+    Yield,
+    /// Yielded a few times in a row.
+    YieldMany,
+    /// Spun with this many `spin_loop` hints.
+    Spin(usize),
+    /// Ran a busy loop of this many steps.
+    Busy(usize),
+    /// Handed off to this thread, with `TestCtx::sp_handoff`.
+    Handoff(usize),
+    /// Whatever a scheduler other than the default one did.
+    Other,
+}
+
+impl SpAction {
+    /// Does it, returning whether the thread yielded or slept. `Handoff` and
+    /// `Other` do nothing, since there's no telling how to redo them.
+    pub(crate) fn perform(self) -> bool {
+        match self {
+            SpAction::SleepZero => thread::sleep(Duration::from_nanos(0)),
+            SpAction::Sleep(d) => thread::sleep(d),
+            SpAction::Yield => thread::yield_now(),
+            SpAction::YieldMany => {
+                for _ in 0..=5 {
+                    thread::yield_now()
+                }
+            }
+            SpAction::Spin(n) => {
+                for _ in 0..n {
+                    core::hint::spin_loop();
+                }
+                return false;
+            }
+            SpAction::Busy(n) => {
+                for i in 0..n {
+                    let mut g = 0;
+                    unsafe {
+                        core::ptr::write_volatile(&mut g, i);
+                        let _ = core::ptr::read_volatile(&g);
+                    }
+                }
+                return false;
+            }
+            SpAction::Nothing | SpAction::Handoff(_) | SpAction::Other => return false,
+        }
+        true
+    }
+}
@@ -0,0 +1,184 @@
+//! How the driver gets a group's threads going each iteration.
+use crate::Rng;
+use core::ops::RangeInclusive;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How the driver starts threads. See `TestCfg::start_mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StartMode {
+    /// Wake them one at a time, in a random order.
+    #[default]
+    Sequential,
+    /// Wake them all, but have each spin on a shared flag until the last one
+    /// is ready, so they're all released within a few nanoseconds of each
+    /// other. Waking threads one at a time can serialize short test bodies so
+    /// much that they never actually overlap.
+    ///
+    /// Spinning is only worth it with a core for each thread. Otherwise they
+    /// mostly just yield to each other until the last one shows up.
+    SpinGate,
+}
+
+/// The shared flag for `StartMode::SpinGate`.
+#[derive(Default)]
+pub(crate) struct SpinGate {
+    arrived: AtomicUsize,
+    generation: AtomicUsize,
+}
+
+impl SpinGate {
+    /// Waits at the gate until the driver opens it. `seen` is the last
+    /// generation this thread went through.
+    pub(crate) fn pass(&self, seen: &mut usize) {
+        self.arrived.fetch_add(1, Ordering::AcqRel);
+        let mut spins = 0u32;
+        loop {
+            let gen = self.generation.load(Ordering::Acquire);
+            if gen != *seen {
+                *seen = gen;
+                return;
+            }
+            spin(&mut spins);
+        }
+    }
+
+    /// Waits for `n` threads to get to the gate, then lets them all through.
+    pub(crate) fn open(&self, n: usize) {
+        let mut spins = 0u32;
+        while self.arrived.load(Ordering::Acquire) < n {
+            spin(&mut spins);
+        }
+        self.arrived.store(0, Ordering::Relaxed);
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Spins, yielding every so often in case whoever we're waiting for needs
+/// our core.
+fn spin(spins: &mut u32) {
+    *spins = spins.wrapping_add(1);
+    if spins.is_multiple_of(64) {
+        thread::yield_now();
+    } else {
+        core::hint::spin_loop();
+    }
+}
+
+/// The order the driver starts threads in each iteration. See
+/// `TestCfg::start_order`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StartOrder {
+    /// A random order each time.
+    #[default]
+    Shuffle,
+    /// Always by thread index.
+    Fixed,
+    /// Always by thread index, backwards.
+    Reversed,
+    /// By thread index, but starting from a different thread each iteration.
+    Rotate,
+    /// Mostly replay orders that were used in iterations that failed (with
+    /// violations), and shuffle otherwise.
+    Adversarial,
+}
+
+/// Only this many failing orders are kept for `StartOrder::Adversarial`.
+const MAX_FAILING: usize = 64;
+
+pub(crate) struct OrderPicker {
+    mode: StartOrder,
+    failing: Vec<Vec<usize>>,
+}
+
+impl OrderPicker {
+    pub(crate) fn new(mode: StartOrder) -> Self {
+        Self {
+            mode,
+            failing: vec![],
+        }
+    }
+
+    pub(crate) fn pick(&mut self, order: &mut [usize], iteration: usize, rng: &mut Rng) {
+        let n = order.len();
+        match self.mode {
+            StartOrder::Shuffle => rng.shuffle(order),
+            StartOrder::Fixed => order.iter_mut().enumerate().for_each(|(i, o)| *o = i),
+            StartOrder::Reversed => order
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i, o)| *o = n - 1 - i),
+            StartOrder::Rotate => order
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i, o)| *o = (i + iteration) % n),
+            StartOrder::Adversarial => match rng.choose(&self.failing) {
+                Some(f) if rng.chance(0.75) => order.copy_from_slice(f),
+                _ => rng.shuffle(order),
+            },
+        }
+    }
+
+    /// Records how an iteration that used `order` went.
+    pub(crate) fn finished(&mut self, order: &[usize], failed: bool, rng: &mut Rng) {
+        if !failed || self.mode != StartOrder::Adversarial {
+            return;
+        }
+        if self.failing.len() < MAX_FAILING {
+            self.failing.push(order.to_vec());
+        } else {
+            let i = rng.upto(MAX_FAILING);
+            self.failing[i].copy_from_slice(order);
+        }
+    }
+}
+
+/// How spread out thread starts are in each iteration. See
+/// `TestCfg::start_stagger`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Stagger {
+    /// Start them back to back.
+    #[default]
+    None,
+    /// Wait a random time in this range before starting each thread after the
+    /// first.
+    Delay(RangeInclusive<Duration>),
+    /// Start them in groups of `size`, back to back, waiting a random time in
+    /// `gap` between groups.
+    Bursts {
+        size: usize,
+        gap: RangeInclusive<Duration>,
+    },
+}
+
+impl Stagger {
+    /// Waits for however long we should before starting the `n`th thread.
+    pub(crate) fn wait_before(&self, n: usize, rng: &mut Rng) {
+        let range = match self {
+            Stagger::None => return,
+            Stagger::Delay(range) if n != 0 => range,
+            Stagger::Bursts { size, gap } if n != 0 && n.is_multiple_of((*size).max(1)) => gap,
+            _ => return,
+        };
+        let (lo, hi) = (range.start().as_nanos(), range.end().as_nanos());
+        if hi <= lo {
+            delay(*range.start());
+        } else {
+            let nanos = lo + rng.upto((hi - lo + 1).min(usize::MAX as u128) as usize) as u128;
+            delay(Duration::from_nanos(nanos as u64));
+        }
+    }
+}
+
+/// Sleeping is far too coarse for the short delays, so spin for those.
+fn delay(d: Duration) {
+    if d >= Duration::from_millis(1) {
+        thread::sleep(d);
+    } else if d != Duration::ZERO {
+        let until = Instant::now() + d;
+        while Instant::now() < until {
+            core::hint::spin_loop();
+        }
+    }
+}
@@ -0,0 +1,463 @@
+//! Timing statistics for a run, returned as a `RunReport` and optionally
+//! printed (see `TestCfg::timing_report`).
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// What a successful run did, and how long it took. Returned by
+/// `try_run_test`.
+#[derive(Debug, Clone, Default)]
+pub struct RunReport {
+    pub wall_time: Duration,
+    /// The number of iterations run, across all the groups.
+    pub iterations: usize,
+    /// How long iterations took, across all the groups.
+    pub latency: Latency,
+    /// Like `GroupReport::overlap`, across all the groups.
+    pub overlap: f64,
+    pub groups: Vec<GroupReport>,
+    /// How many times each outcome was recorded with `TestCtx::outcome` or
+    /// `IterCtx::outcome`, including any of `TestCfg::expected_outcomes` that
+    /// never were.
+    pub outcomes: BTreeMap<String, u64>,
+}
+
+/// The part of a `RunReport` for one group.
+#[derive(Debug, Clone, Default)]
+pub struct GroupReport {
+    pub group: usize,
+    pub threads: usize,
+    pub iterations: usize,
+    pub wall_time: Duration,
+    /// Time spent in `setup`.
+    pub setup_time: Duration,
+    /// Time spent in `teardown`.
+    pub teardown_time: Duration,
+    /// The total time the group's runner threads spent waiting for the driver
+    /// to start their next iteration.
+    pub event_wait: Duration,
+    /// The total time the group's runner threads spent running the test.
+    pub running: Duration,
+    /// How many threads were running the test at once, on average, while
+    /// iterations were going: `running` over the total time iterations took.
+    /// Close to `threads` means they really did overlap, close to 1 means the
+    /// test was mostly serialized, by the harness or by the test itself.
+    pub overlap: f64,
+    /// How long iterations took, from the driver starting the threads to all
+    /// of them being done.
+    pub latency: Latency,
+    /// How much of the CPU the group's threads got while running the test at
+    /// high priority, and at low priority (see `ThreadReport::cpu_share`).
+    /// `None` where CPU time can't be measured, or if no thread ran at that
+    /// priority.
+    pub high_priority_cpu_share: Option<f64>,
+    pub low_priority_cpu_share: Option<f64>,
+    /// Each runner thread's share, in thread order.
+    pub per_thread: Vec<ThreadReport>,
+}
+
+/// The part of a `GroupReport` for one of its runner threads.
+#[derive(Debug, Clone, Default)]
+pub struct ThreadReport {
+    pub thread: usize,
+    /// The iterations it ran the test in. Benched threads sit some out.
+    pub iterations: usize,
+    /// Time spent running the test, including any time it spent blocked in
+    /// it (at a barrier or a schedule point, say).
+    pub running: Duration,
+    /// Time spent waiting for the driver to start its next iteration.
+    pub waiting: Duration,
+    /// The CPU time it used running the test, where that can be measured
+    /// (with `clock_gettime` on Linux and Apple platforms, and
+    /// `GetThreadTimes`, which is only as precise as the clock tick, on
+    /// Windows).
+    pub cpu_time: Option<Duration>,
+    /// The iterations it ran the test in at low priority (see
+    /// `TestCfg::priorities`), and how much of `running` and `cpu_time` were
+    /// from those.
+    pub low_priority_iterations: usize,
+    pub low_priority_running: Duration,
+    pub low_priority_cpu_time: Option<Duration>,
+    /// Whether it got under half as much of the CPU while low priority as the
+    /// group's threads did while high priority (see `cpu_share`), which is
+    /// what low priority is meant to do to a thread when there's competition
+    /// for the CPU.
+    pub starved: bool,
+}
+
+impl ThreadReport {
+    /// The fraction of its time that it spent running the test rather than
+    /// waiting to be started.
+    pub fn busy(&self) -> f64 {
+        fraction(self.running, self.running + self.waiting)
+    }
+
+    /// How much of the CPU it got while running the test at low priority, or
+    /// otherwise at high priority: its CPU time over its time running the
+    /// test. Below 1 means it was blocked or preempted some of that time.
+    pub fn cpu_share(&self, low_priority: bool) -> Option<f64> {
+        let (cpu, running) = self.at(low_priority)?;
+        Some(fraction(cpu, running))
+    }
+
+    /// The CPU time and running time at one priority, if it ran at it.
+    fn at(&self, low_priority: bool) -> Option<(Duration, Duration)> {
+        let cpu = self.cpu_time?;
+        let low_cpu = self.low_priority_cpu_time?;
+        match low_priority {
+            true if self.low_priority_iterations != 0 => Some((low_cpu, self.low_priority_running)),
+            false if self.iterations != self.low_priority_iterations => {
+                Some((cpu - low_cpu, self.running - self.low_priority_running))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The share of the CPU that `threads` got together at one priority.
+fn cpu_share(threads: &[ThreadReport], low_priority: bool) -> Option<f64> {
+    let (cpu, running) = threads
+        .iter()
+        .filter_map(|t| t.at(low_priority))
+        .reduce(|a, b| (a.0 + b.0, a.1 + b.1))?;
+    Some(fraction(cpu, running))
+}
+
+fn fraction(a: Duration, b: Duration) -> f64 {
+    match b.is_zero() {
+        true => 0.0,
+        false => a.as_secs_f64() / b.as_secs_f64(),
+    }
+}
+
+/// A summary of how long iterations took. The median and 99th percentile
+/// are approximate, to within about 6%.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Latency {
+    pub mean: Duration,
+    pub median: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+/// Durations bucketed by their top 5 significant bits, which keeps the
+/// percentiles close enough without keeping every sample around.
+#[derive(Clone)]
+pub(crate) struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    total: u128,
+    max: u64,
+}
+
+const SUB: u32 = 16;
+
+impl Histogram {
+    pub(crate) fn new() -> Self {
+        Self {
+            buckets: vec![0; (64 * SUB) as usize],
+            count: 0,
+            total: 0,
+            max: 0,
+        }
+    }
+
+    fn bucket(nanos: u64) -> usize {
+        if nanos < u64::from(SUB) {
+            return nanos as usize;
+        }
+        let log = 63 - nanos.leading_zeros();
+        let frac = (nanos >> (log - 4)) & u64::from(SUB - 1);
+        ((log - 3) * SUB) as usize + frac as usize
+    }
+
+    /// The smallest duration that falls in bucket `b`.
+    fn lower_bound(b: usize) -> u64 {
+        let b = b as u32;
+        if b < SUB {
+            return u64::from(b);
+        }
+        let log = b / SUB + 3;
+        (u64::from(SUB + b % SUB)) << (log - 4)
+    }
+
+    pub(crate) fn record(&mut self, d: Duration) {
+        let nanos = d.as_nanos().min(u128::from(u64::MAX)) as u64;
+        self.buckets[Self::bucket(nanos)] += 1;
+        self.count += 1;
+        self.total += u128::from(nanos);
+        self.max = self.max.max(nanos);
+    }
+
+    pub(crate) fn merge(&mut self, other: &Histogram) {
+        for (a, b) in self.buckets.iter_mut().zip(&other.buckets) {
+            *a += b;
+        }
+        self.count += other.count;
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+    }
+
+    fn quantile(&self, q: f64) -> Duration {
+        let want = ((self.count as f64 * q).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (b, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= want {
+                return Duration::from_nanos(Self::lower_bound(b));
+            }
+        }
+        Duration::from_nanos(self.max)
+    }
+
+    fn total_time(&self) -> Duration {
+        Duration::from_nanos(self.total.min(u128::from(u64::MAX)) as u64)
+    }
+
+    pub(crate) fn latency(&self) -> Latency {
+        if self.count == 0 {
+            return Latency::default();
+        }
+        let mean = self.total / u128::from(self.count);
+        Latency {
+            mean: Duration::from_nanos(mean as u64),
+            median: self.quantile(0.5),
+            p99: self.quantile(0.99),
+            max: Duration::from_nanos(self.max),
+        }
+    }
+}
+
+/// Where each group leaves its numbers for the end of the run.
+pub(crate) struct Slot {
+    /// Added to by the runner threads as they exit.
+    threads: Mutex<Vec<ThreadReport>>,
+    done: Mutex<Option<(GroupReport, Histogram)>>,
+}
+
+impl Slot {
+    pub(crate) fn new() -> Self {
+        Self {
+            threads: Mutex::new(vec![]),
+            done: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn thread_done(&self, report: ThreadReport) {
+        self.threads
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(report);
+    }
+}
+
+/// Keeps track of a group's times, and leaves them in its `Slot` when
+/// dropped, however the group ends.
+pub(crate) struct GroupTimes<'a> {
+    slot: &'a Slot,
+    start: Instant,
+    pub(crate) report: GroupReport,
+    pub(crate) latency: Histogram,
+}
+
+impl<'a> GroupTimes<'a> {
+    pub(crate) fn new(slot: &'a Slot, group: usize, threads: usize) -> Self {
+        Self {
+            slot,
+            start: Instant::now(),
+            report: GroupReport {
+                group,
+                threads,
+                ..Default::default()
+            },
+            latency: Histogram::new(),
+        }
+    }
+
+    pub(crate) fn iteration(&mut self, took: Duration) {
+        self.report.iterations += 1;
+        self.latency.record(took);
+    }
+}
+
+impl Drop for GroupTimes<'_> {
+    fn drop(&mut self) {
+        self.report.wall_time = self.start.elapsed();
+        self.report.latency = self.latency.latency();
+        let done = (core::mem::take(&mut self.report), self.latency.clone());
+        *self
+            .slot
+            .done
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(done);
+    }
+}
+
+/// Puts the groups' numbers together.
+pub(crate) fn report(slots: &[Slot], wall_time: Duration) -> RunReport {
+    let mut all = Histogram::new();
+    let mut groups = vec![];
+    for slot in slots {
+        let done = slot
+            .done
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some((mut g, h)) = done {
+            let mut threads =
+                core::mem::take(&mut *slot.threads.lock().unwrap_or_else(PoisonError::into_inner));
+            threads.sort_by_key(|t| t.thread);
+            g.event_wait = threads.iter().map(|t| t.waiting).sum();
+            g.running = threads.iter().map(|t| t.running).sum();
+            g.overlap = fraction(g.running, h.total_time());
+            g.high_priority_cpu_share = cpu_share(&threads, false);
+            g.low_priority_cpu_share = cpu_share(&threads, true);
+            if let Some(high) = g.high_priority_cpu_share {
+                for t in &mut threads {
+                    t.starved = t.cpu_share(true).is_some_and(|low| low < high / 2.0);
+                }
+            }
+            g.per_thread = threads;
+            all.merge(&h);
+            groups.push(g);
+        }
+    }
+    RunReport {
+        wall_time,
+        iterations: groups.iter().map(|g| g.iterations).sum(),
+        latency: all.latency(),
+        overlap: fraction(groups.iter().map(|g| g.running).sum(), all.total_time()),
+        groups,
+        outcomes: BTreeMap::new(),
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>6} {:>7} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>7} {:>10} {:>10} {:>10} {:>10}",
+            "group",
+            "threads",
+            "iters",
+            "wall",
+            "setup",
+            "teardown",
+            "waiting",
+            "running",
+            "overlap",
+            "mean",
+            "median",
+            "p99",
+            "max"
+        )?;
+        let row = |f: &mut fmt::Formatter<'_>, label: &str, threads: &str, g: &GroupReport| {
+            let d = |d: Duration| format!("{:.1?}", d);
+            writeln!(
+                f,
+                "{:>6} {:>7} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>7.2} {:>10} {:>10} {:>10} {:>10}",
+                label,
+                threads,
+                g.iterations,
+                d(g.wall_time),
+                d(g.setup_time),
+                d(g.teardown_time),
+                d(g.event_wait),
+                d(g.running),
+                g.overlap,
+                d(g.latency.mean),
+                d(g.latency.median),
+                d(g.latency.p99),
+                d(g.latency.max)
+            )
+        };
+        for g in &self.groups {
+            row(f, &g.group.to_string(), &g.threads.to_string(), g)?;
+        }
+        let total = GroupReport {
+            iterations: self.iterations,
+            wall_time: self.wall_time,
+            setup_time: self.groups.iter().map(|g| g.setup_time).sum(),
+            teardown_time: self.groups.iter().map(|g| g.teardown_time).sum(),
+            event_wait: self.groups.iter().map(|g| g.event_wait).sum(),
+            running: self.groups.iter().map(|g| g.running).sum(),
+            overlap: self.overlap,
+            latency: self.latency,
+            ..Default::default()
+        };
+        row(f, "all", "", &total)?;
+        writeln!(
+            f,
+            "{:>6} {:>7} {:>10} {:>10} {:>10} {:>6} {:>10} {:>8} {:>7} {:>7}",
+            "group",
+            "thread",
+            "iters",
+            "running",
+            "waiting",
+            "busy",
+            "cpu",
+            "lo iters",
+            "hi cpu",
+            "lo cpu"
+        )?;
+        let share = |s: Option<f64>| match s {
+            Some(s) => format!("{:.1}%", s * 100.0),
+            None => "-".to_string(),
+        };
+        for g in &self.groups {
+            for t in &g.per_thread {
+                writeln!(
+                    f,
+                    "{:>6} {:>7} {:>10} {:>10} {:>10} {:>5.1}% {:>10} {:>8} {:>7} {:>7}{}",
+                    g.group,
+                    t.thread,
+                    t.iterations,
+                    format!("{:.1?}", t.running),
+                    format!("{:.1?}", t.waiting),
+                    t.busy() * 100.0,
+                    match t.cpu_time {
+                        Some(d) => format!("{:.1?}", d),
+                        None => "-".to_string(),
+                    },
+                    t.low_priority_iterations,
+                    share(t.cpu_share(false)),
+                    share(t.cpu_share(true)),
+                    match t.starved {
+                        true => " starved",
+                        false => "",
+                    }
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decides when to print progress at `TestCfg::verbosity` 1: every tenth of
+/// the run, or every few seconds, whichever comes first.
+pub(crate) struct Progress {
+    every: usize,
+    last: usize,
+    last_time: Instant,
+}
+
+impl Progress {
+    pub(crate) fn new(iterations: usize) -> Self {
+        Self {
+            every: (iterations / 10).max(1),
+            last: 0,
+            last_time: Instant::now(),
+        }
+    }
+
+    /// Whether to print, now that `done` iterations are.
+    pub(crate) fn due(&mut self, done: usize) -> bool {
+        if done - self.last < self.every && self.last_time.elapsed() < Duration::from_secs(5) {
+            return false;
+        }
+        self.last = done;
+        self.last_time = Instant::now();
+        true
+    }
+}
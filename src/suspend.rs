@@ -0,0 +1,377 @@
+//! Suspending runner threads from outside, at arbitrary points, so that the
+//! OS creates preemption windows much longer than anything the schedule
+//! points can. See `TestCfg::suspend`.
+use crate::watchdog::ThreadStatus;
+use crate::Rng;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often runner threads get suspended, and for how long. See
+/// `TestCfg::suspend`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuspendCfg {
+    /// The average time between suspensions (of some thread in the group).
+    pub every: Duration,
+    /// The longest a thread stays suspended. Each suspension is for a random
+    /// time up to this.
+    pub max_pause: Duration,
+}
+
+impl Default for SuspendCfg {
+    fn default() -> Self {
+        Self {
+            every: Duration::from_millis(1),
+            max_pause: Duration::from_millis(2),
+        }
+    }
+}
+
+/// How often `TestCtx::freeze_candidate` freezes the thread that calls it,
+/// and for how long. See `TestCfg::freeze`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FreezeCfg {
+    /// The chance of freezing at each call, unless another thread in the
+    /// group is already frozen.
+    pub chance: f64,
+    pub duration: Duration,
+}
+
+impl Default for FreezeCfg {
+    fn default() -> Self {
+        Self {
+            chance: 0.05,
+            duration: Duration::from_millis(2),
+        }
+    }
+}
+
+/// A runner thread, as far as suspending it goes.
+#[derive(Default)]
+pub(crate) struct Target {
+    imp: imp::Target,
+}
+
+/// Makes the current thread suspendable through `target`, until dropped.
+pub(crate) fn register(target: &Target) -> imp::Registration<'_> {
+    imp::register(&target.imp)
+}
+
+/// Hands the rest of the current thread's timeslice straight to `target`,
+/// returning false if that can't be done here (or it isn't running the test).
+/// See `TestCtx::sp_handoff`.
+pub(crate) fn switch_to(target: &Target) -> bool {
+    imp::switch_to(&target.imp)
+}
+
+/// Whether `TestCfg::suspend` does anything on this platform.
+pub(crate) const SUPPORTED: bool = imp::SUPPORTED;
+
+/// Randomly suspends and resumes the threads that are running the test until
+/// dropped. Every suspended thread is resumed before it stops.
+pub(crate) struct Suspender {
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Suspender {
+    pub(crate) fn spawn(
+        label: String,
+        cfg: SuspendCfg,
+        threads: Vec<(Arc<ThreadStatus>, Arc<Target>)>,
+        mut rng: Rng,
+    ) -> Self {
+        imp::init();
+        let done = Arc::new(AtomicBool::new(false));
+        let done2 = Arc::clone(&done);
+        let thread = thread::Builder::new()
+            .name(format!("{} suspender", label))
+            .spawn(move || {
+                let every = cfg.every.as_nanos().max(1) as usize;
+                let max_pause = cfg.max_pause.as_nanos().max(1) as usize;
+                while !done2.load(Ordering::Acquire) {
+                    thread::sleep(Duration::from_nanos(rng.upto(2 * every) as u64));
+                    let (status, target) = &threads[rng.upto(threads.len())];
+                    // Threads only exit between iterations, so this one's
+                    // still around.
+                    if !status.in_test() || !imp::suspend(&target.imp) {
+                        continue;
+                    }
+                    thread::sleep(Duration::from_nanos(rng.upto(max_pause) as u64));
+                    imp::resume(&target.imp);
+                }
+            })
+            .unwrap_or_else(|e| panic!("Cobb: failed to launch suspender: {:?}", e));
+        Self {
+            done,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Suspender {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Release);
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+/// On Linux, suspending sends the thread a signal, whose handler sleeps until
+/// it's resumed. SIGSTOP would stop the whole process.
+#[cfg(all(target_os = "linux", not(miri)))]
+mod imp {
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    pub(super) const SUPPORTED: bool = true;
+
+    // SIGURG is ignored by default and almost never used, which is why Go
+    // uses it for preemption too. Its number depends on the architecture.
+    #[cfg(any(
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "mips32r6",
+        target_arch = "mips64r6",
+    ))]
+    const SIGURG: i32 = 21;
+    #[cfg(any(target_arch = "sparc", target_arch = "sparc64"))]
+    const SIGURG: i32 = 16;
+    #[cfg(not(any(
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "mips32r6",
+        target_arch = "mips64r6",
+        target_arch = "sparc",
+        target_arch = "sparc64",
+    )))]
+    const SIGURG: i32 = 23;
+
+    extern "C" {
+        fn pthread_self() -> usize;
+        fn pthread_kill(thread: usize, sig: i32) -> i32;
+        fn signal(sig: i32, handler: usize) -> usize;
+    }
+
+    #[derive(Default)]
+    pub(crate) struct Target {
+        /// The thread's `pthread_t`, or 0 if it's not registered.
+        thread: AtomicUsize,
+        suspended: AtomicBool,
+    }
+
+    thread_local! {
+        static CURRENT: Cell<*const Target> = const { Cell::new(core::ptr::null()) };
+    }
+
+    pub(crate) struct Registration<'a> {
+        target: &'a Target,
+    }
+
+    pub(super) fn register(target: &Target) -> Registration<'_> {
+        target
+            .thread
+            .store(unsafe { pthread_self() }, Ordering::Release);
+        CURRENT.with(|c| c.set(target));
+        Registration { target }
+    }
+
+    impl Drop for Registration<'_> {
+        fn drop(&mut self) {
+            CURRENT.with(|c| c.set(core::ptr::null()));
+            self.target.thread.store(0, Ordering::Release);
+        }
+    }
+
+    extern "C" fn on_signal(_sig: i32) {
+        let target = CURRENT.try_with(Cell::get).unwrap_or(core::ptr::null());
+        // Safety: it's only set while the `Registration` is alive, and that
+        // can't be dropped while we're in here.
+        if let Some(target) = unsafe { target.as_ref() } {
+            while target.suspended.load(Ordering::Acquire) {
+                // This is just `nanosleep`, which is async-signal-safe.
+                std::thread::sleep(Duration::from_micros(20));
+            }
+        }
+    }
+
+    pub(super) fn init() {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| unsafe {
+            signal(SIGURG, on_signal as extern "C" fn(i32) as usize);
+        });
+    }
+
+    pub(super) fn suspend(target: &Target) -> bool {
+        let thread = target.thread.load(Ordering::Acquire);
+        if thread == 0 {
+            return false;
+        }
+        target.suspended.store(true, Ordering::Release);
+        if unsafe { pthread_kill(thread, SIGURG) } != 0 {
+            target.suspended.store(false, Ordering::Release);
+            return false;
+        }
+        true
+    }
+
+    pub(super) fn resume(target: &Target) {
+        target.suspended.store(false, Ordering::Release);
+    }
+
+    /// Linux has no directed yield.
+    pub(super) fn switch_to(_target: &Target) -> bool {
+        false
+    }
+}
+
+/// On Windows, `SuspendThread` and `ResumeThread` do exactly what we want.
+#[cfg(all(windows, not(miri)))]
+mod imp {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    pub(super) const SUPPORTED: bool = true;
+
+    const THREAD_SUSPEND_RESUME: u32 = 0x0002;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentThreadId() -> u32;
+        fn OpenThread(access: u32, inherit: i32, id: u32) -> usize;
+        fn CloseHandle(handle: usize) -> i32;
+        fn SuspendThread(handle: usize) -> u32;
+        fn ResumeThread(handle: usize) -> u32;
+    }
+
+    #[derive(Default)]
+    pub(crate) struct Target {
+        /// A handle to the thread, or 0 if it's not registered.
+        handle: AtomicUsize,
+    }
+
+    pub(crate) struct Registration<'a> {
+        target: &'a Target,
+    }
+
+    pub(super) fn register(target: &Target) -> Registration<'_> {
+        let handle = unsafe { OpenThread(THREAD_SUSPEND_RESUME, 0, GetCurrentThreadId()) };
+        target.handle.store(handle, Ordering::Release);
+        Registration { target }
+    }
+
+    impl Drop for Registration<'_> {
+        fn drop(&mut self) {
+            let handle = self.target.handle.swap(0, Ordering::AcqRel);
+            if handle != 0 {
+                unsafe { CloseHandle(handle) };
+            }
+        }
+    }
+
+    pub(super) fn init() {}
+
+    // The suspended thread might be holding the heap lock (or any other), so
+    // the suspender mustn't allocate or take locks between these.
+    pub(super) fn suspend(target: &Target) -> bool {
+        let handle = target.handle.load(Ordering::Acquire);
+        handle != 0 && unsafe { SuspendThread(handle) } != u32::MAX
+    }
+
+    pub(super) fn resume(target: &Target) {
+        unsafe { ResumeThread(target.handle.load(Ordering::Acquire)) };
+    }
+
+    /// `SwitchToThread` doesn't take a thread, despite the name.
+    pub(super) fn switch_to(_target: &Target) -> bool {
+        false
+    }
+}
+
+/// On Apple platforms, `thread_suspend` and `thread_resume` on the thread's
+/// mach port, which stall it far more reliably than priorities do.
+#[cfg(all(target_vendor = "apple", not(miri)))]
+mod imp {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    pub(super) const SUPPORTED: bool = true;
+
+    extern "C" {
+        fn pthread_self() -> usize;
+        fn pthread_mach_thread_np(thread: usize) -> u32;
+        fn thread_suspend(thread: u32) -> i32;
+        fn thread_resume(thread: u32) -> i32;
+        fn thread_switch(thread: u32, option: i32, time: u32) -> i32;
+    }
+
+    /// Drops the caller's priority for a moment, so it doesn't get the CPU
+    /// straight back.
+    const SWITCH_OPTION_DEPRESS: i32 = 1;
+
+    #[derive(Default)]
+    pub(crate) struct Target {
+        /// The thread's mach port, or 0 if it's not registered.
+        port: AtomicU32,
+    }
+
+    pub(crate) struct Registration<'a> {
+        target: &'a Target,
+    }
+
+    pub(super) fn register(target: &Target) -> Registration<'_> {
+        // This doesn't add a reference to the port, so there's nothing to
+        // deallocate afterwards.
+        let port = unsafe { pthread_mach_thread_np(pthread_self()) };
+        target.port.store(port, Ordering::Release);
+        Registration { target }
+    }
+
+    impl Drop for Registration<'_> {
+        fn drop(&mut self) {
+            self.target.port.store(0, Ordering::Release);
+        }
+    }
+
+    pub(super) fn init() {}
+
+    // The suspended thread might be holding the malloc lock (or any other),
+    // so the suspender mustn't allocate or take locks between these.
+    pub(super) fn suspend(target: &Target) -> bool {
+        let port = target.port.load(Ordering::Acquire);
+        port != 0 && unsafe { thread_suspend(port) } == 0
+    }
+
+    pub(super) fn resume(target: &Target) {
+        unsafe { thread_resume(target.port.load(Ordering::Acquire)) };
+    }
+
+    pub(super) fn switch_to(target: &Target) -> bool {
+        let port = target.port.load(Ordering::Acquire);
+        // The time's in milliseconds.
+        port != 0 && unsafe { thread_switch(port, SWITCH_OPTION_DEPRESS, 1) } == 0
+    }
+}
+
+#[cfg(not(all(any(target_os = "linux", windows, target_vendor = "apple"), not(miri))))]
+mod imp {
+    pub(super) const SUPPORTED: bool = false;
+
+    #[derive(Default)]
+    pub(crate) struct Target;
+
+    pub(crate) struct Registration<'a>(core::marker::PhantomData<&'a Target>);
+
+    pub(super) fn register(_target: &Target) -> Registration<'_> {
+        Registration(core::marker::PhantomData)
+    }
+    pub(super) fn init() {}
+    pub(super) fn suspend(_target: &Target) -> bool {
+        false
+    }
+    pub(super) fn resume(_target: &Target) {}
+    pub(super) fn switch_to(_target: &Target) -> bool {
+        false
+    }
+}
@@ -0,0 +1,220 @@
+use super::sp_at;
+use core::fmt;
+use std::panic::Location;
+use std::sync::atomic::{self, Ordering};
+
+/// Runs `f` between two schedule points.
+#[inline]
+#[track_caller]
+fn around<R>(f: impl FnOnce() -> R) -> R {
+    let location = Location::caller();
+    sp_at(location);
+    let r = f();
+    sp_at(location);
+    r
+}
+
+macro_rules! common_methods {
+    ($name:ident, $std:ty, $t:ty) => {
+        impl $name {
+            pub const fn new(v: $t) -> Self {
+                Self(<$std>::new(v))
+            }
+            pub fn get_mut(&mut self) -> &mut $t {
+                self.0.get_mut()
+            }
+            pub fn into_inner(self) -> $t {
+                self.0.into_inner()
+            }
+            #[track_caller]
+            pub fn load(&self, order: Ordering) -> $t {
+                around(|| self.0.load(order))
+            }
+            #[track_caller]
+            pub fn store(&self, v: $t, order: Ordering) {
+                around(|| self.0.store(v, order))
+            }
+            #[track_caller]
+            pub fn swap(&self, v: $t, order: Ordering) -> $t {
+                around(|| self.0.swap(v, order))
+            }
+            #[track_caller]
+            pub fn compare_exchange(
+                &self,
+                current: $t,
+                new: $t,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$t, $t> {
+                around(|| self.0.compare_exchange(current, new, success, failure))
+            }
+            #[track_caller]
+            pub fn compare_exchange_weak(
+                &self,
+                current: $t,
+                new: $t,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$t, $t> {
+                around(|| self.0.compare_exchange_weak(current, new, success, failure))
+            }
+            #[track_caller]
+            pub fn fetch_update<F>(
+                &self,
+                set_order: Ordering,
+                fetch_order: Ordering,
+                f: F,
+            ) -> Result<$t, $t>
+            where
+                F: FnMut($t) -> Option<$t>,
+            {
+                around(|| self.0.fetch_update(set_order, fetch_order, f))
+            }
+        }
+
+        impl From<$t> for $name {
+            fn from(v: $t) -> Self {
+                Self::new(v)
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Debug::fmt(&self.0, f)
+            }
+        }
+    };
+}
+
+macro_rules! fetch_methods {
+    ($name:ident, $t:ty, [$($method:ident),*]) => {
+        impl $name {
+            $(
+                #[track_caller]
+                pub fn $method(&self, v: $t, order: Ordering) -> $t {
+                    around(|| self.0.$method(v, order))
+                }
+            )*
+        }
+    };
+}
+
+macro_rules! atomic_int {
+    ($($name:ident($t:ty)),* $(,)?) => {$(
+        /// Like the `std` type of the same name, but hits a schedule point
+        /// before and after each operation. See the module docs.
+        #[derive(Default)]
+        #[repr(transparent)]
+        pub struct $name(atomic::$name);
+        common_methods!($name, atomic::$name, $t);
+        fetch_methods!(
+            $name,
+            $t,
+            [fetch_add, fetch_sub, fetch_and, fetch_nand, fetch_or, fetch_xor, fetch_max, fetch_min]
+        );
+    )*};
+}
+
+atomic_int!(
+    AtomicUsize(usize),
+    AtomicIsize(isize),
+    AtomicU8(u8),
+    AtomicI8(i8),
+    AtomicU16(u16),
+    AtomicI16(i16),
+    AtomicU32(u32),
+    AtomicI32(i32),
+    AtomicU64(u64),
+    AtomicI64(i64),
+);
+
+/// Like `std::sync::atomic::AtomicBool`, but hits a schedule point before and
+/// after each operation. See the module docs.
+#[derive(Default)]
+#[repr(transparent)]
+pub struct AtomicBool(atomic::AtomicBool);
+common_methods!(AtomicBool, atomic::AtomicBool, bool);
+fetch_methods!(
+    AtomicBool,
+    bool,
+    [fetch_and, fetch_nand, fetch_or, fetch_xor]
+);
+
+/// Like `std::sync::atomic::AtomicPtr`, but hits a schedule point before and
+/// after each operation. See the module docs.
+#[repr(transparent)]
+pub struct AtomicPtr<T>(atomic::AtomicPtr<T>);
+
+impl<T> AtomicPtr<T> {
+    pub const fn new(p: *mut T) -> Self {
+        Self(atomic::AtomicPtr::new(p))
+    }
+    pub fn get_mut(&mut self) -> &mut *mut T {
+        self.0.get_mut()
+    }
+    pub fn into_inner(self) -> *mut T {
+        self.0.into_inner()
+    }
+    #[track_caller]
+    pub fn load(&self, order: Ordering) -> *mut T {
+        around(|| self.0.load(order))
+    }
+    #[track_caller]
+    pub fn store(&self, p: *mut T, order: Ordering) {
+        around(|| self.0.store(p, order))
+    }
+    #[track_caller]
+    pub fn swap(&self, p: *mut T, order: Ordering) -> *mut T {
+        around(|| self.0.swap(p, order))
+    }
+    #[track_caller]
+    pub fn compare_exchange(
+        &self,
+        current: *mut T,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<*mut T, *mut T> {
+        around(|| self.0.compare_exchange(current, new, success, failure))
+    }
+    #[track_caller]
+    pub fn compare_exchange_weak(
+        &self,
+        current: *mut T,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<*mut T, *mut T> {
+        around(|| self.0.compare_exchange_weak(current, new, success, failure))
+    }
+    #[track_caller]
+    pub fn fetch_update<F>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        f: F,
+    ) -> Result<*mut T, *mut T>
+    where
+        F: FnMut(*mut T) -> Option<*mut T>,
+    {
+        around(|| self.0.fetch_update(set_order, fetch_order, f))
+    }
+}
+
+impl<T> Default for AtomicPtr<T> {
+    fn default() -> Self {
+        Self::new(core::ptr::null_mut())
+    }
+}
+
+impl<T> From<*mut T> for AtomicPtr<T> {
+    fn from(p: *mut T) -> Self {
+        Self::new(p)
+    }
+}
+
+impl<T> fmt::Debug for AtomicPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
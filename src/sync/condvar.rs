@@ -0,0 +1,136 @@
+use super::{contended, map_lock, sp_at, spurious_wakeup, Mutex, MutexGuard};
+use core::fmt;
+use std::panic::Location;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{self, LockResult};
+use std::time::{Duration, Instant};
+
+/// Like `std::sync::Condvar`, but for `cobb::sync::Mutex`, and hits schedule
+/// points before and after waiting, and before notifying. With
+/// `TestCfg::spurious_wakeups`, waits sometimes return without waiting at
+/// all, which they're allowed to do, but rarely do in practice.
+///
+/// Under the cooperative schedulers, `notify_one` wakes every waiter, which
+/// is also allowed.
+#[derive(Default)]
+pub struct Condvar {
+    cv: sync::Condvar,
+    /// Bumped on every notification, for waiting cooperatively.
+    epoch: AtomicUsize,
+}
+
+/// Whether `Condvar::wait_timeout` timed out. (The `std` one can't be made
+/// outside of `std`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            cv: sync::Condvar::new(),
+            epoch: AtomicUsize::new(0),
+        }
+    }
+    #[track_caller]
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> LockResult<MutexGuard<'a, T>> {
+        map_lock(self.wait_for(guard, None), |(g, _)| g)
+    }
+    #[track_caller]
+    pub fn wait_while<'a, T, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> LockResult<MutexGuard<'a, T>>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard)?;
+        }
+        Ok(guard)
+    }
+    #[track_caller]
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        dur: Duration,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)> {
+        self.wait_for(guard, Some(dur))
+    }
+    #[track_caller]
+    pub fn notify_one(&self) {
+        sp_at(Location::caller());
+        self.epoch.fetch_add(1, Ordering::Relaxed);
+        self.cv.notify_one();
+    }
+    #[track_caller]
+    pub fn notify_all(&self) {
+        sp_at(Location::caller());
+        self.epoch.fetch_add(1, Ordering::Relaxed);
+        self.cv.notify_all();
+    }
+
+    #[track_caller]
+    fn wait_for<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        timeout: Option<Duration>,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)> {
+        let location = Location::caller();
+        sp_at(location);
+        let (mutex, guard_location) = (guard.mutex, guard.location);
+        let inner = guard.inner.take().unwrap();
+        let res = self.wait_inner(mutex, inner, timeout);
+        sp_at(location);
+        map_lock(res, |(g, timed_out)| {
+            (
+                MutexGuard::new(mutex, g, guard_location),
+                WaitTimeoutResult(timed_out),
+            )
+        })
+    }
+
+    fn wait_inner<'a, T>(
+        &self,
+        mutex: &'a Mutex<T>,
+        inner: sync::MutexGuard<'a, T>,
+        timeout: Option<Duration>,
+    ) -> LockResult<(sync::MutexGuard<'a, T>, bool)> {
+        if spurious_wakeup() {
+            return Ok((inner, false));
+        }
+        let epoch = self.epoch.load(Ordering::Relaxed);
+        if contended() {
+            // The scheduler takes turns, so rather than block (and keep ours
+            // forever), give it up until we're notified.
+            drop(inner);
+            let start = Instant::now();
+            let timed_out = loop {
+                if self.epoch.load(Ordering::Relaxed) != epoch {
+                    break false;
+                }
+                if timeout.is_some_and(|t| start.elapsed() >= t) {
+                    break true;
+                }
+                contended();
+            };
+            return map_lock(mutex.relock(), |g| (g, timed_out));
+        }
+        match timeout {
+            None => map_lock(self.cv.wait(inner), |g| (g, false)),
+            Some(t) => map_lock(self.cv.wait_timeout(inner, t), |(g, r)| (g, r.timed_out())),
+        }
+    }
+}
+
+impl fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.cv, f)
+    }
+}
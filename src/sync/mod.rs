@@ -0,0 +1,143 @@
+//! Drop-in replacements for `std::sync` types that hit a schedule point
+//! around everything they do when called from a cobb runner thread, so that
+//! code under test gets perturbed without hand-placed `TestCtx::sp` calls.
+//! Anywhere else (including helper threads, and outside tests entirely)
+//! they behave exactly like the `std` versions, at the cost of a thread local
+//! check.
+//!
+//! To leave the `std` types in non-test builds, import them conditionally:
+//!
+//! ```
+//! #[cfg(test)]
+//! use cobb::sync::AtomicUsize;
+//! #[cfg(not(test))]
+//! use std::sync::atomic::AtomicUsize;
+//! ```
+use crate::TestCtx;
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::panic::Location;
+use std::sync::atomic::{compiler_fence, fence};
+
+mod atomic;
+mod condvar;
+mod mutex;
+mod rwlock;
+pub use atomic::*;
+pub use condvar::{Condvar, WaitTimeoutResult};
+pub use mutex::{Mutex, MutexGuard};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use std::sync::atomic::Ordering;
+pub use std::sync::{LockResult, PoisonError, TryLockError, TryLockResult};
+
+thread_local! {
+    static CURRENT: Cell<*const TestCtx> = const { Cell::new(core::ptr::null()) };
+}
+
+/// Makes `ctx` the current thread's context until dropped.
+pub(crate) struct Entered<'a> {
+    prev: *const TestCtx,
+    _ctx: PhantomData<&'a TestCtx>,
+}
+
+pub(crate) fn enter(ctx: &TestCtx) -> Entered<'_> {
+    Entered {
+        prev: CURRENT.with(|c| c.replace(ctx)),
+        _ctx: PhantomData,
+    }
+}
+
+impl Drop for Entered<'_> {
+    fn drop(&mut self) {
+        CURRENT.with(|c| c.set(self.prev));
+    }
+}
+
+/// Calls `f` with the context of the test running on this thread, if any.
+pub(crate) fn with_current<R>(f: impl FnOnce(&TestCtx) -> R) -> Option<R> {
+    let ctx = CURRENT.try_with(Cell::get).unwrap_or(core::ptr::null());
+    // Safety: it's only non-null while an `Entered` borrowing it is alive,
+    // on this thread.
+    unsafe { ctx.as_ref() }.map(f)
+}
+
+/// Tells the scheduler (if we're on a runner thread) that we can't go on
+/// until another thread does something. If it returns true, it let another
+/// thread run, and we should check again rather than block.
+pub(crate) fn contended() -> bool {
+    with_current(|ctx| ctx.scheduler.borrow_mut().contended()).unwrap_or(false)
+}
+
+/// Takes a lock with `try_lock`, only blocking on it with `lock` if the
+/// scheduler doesn't want to take turns instead.
+fn acquire<G>(
+    mut try_lock: impl FnMut() -> TryLockResult<G>,
+    lock: impl FnOnce() -> LockResult<G>,
+) -> LockResult<G> {
+    loop {
+        match try_lock() {
+            Ok(g) => return Ok(g),
+            Err(TryLockError::Poisoned(e)) => return Err(e),
+            Err(TryLockError::WouldBlock) if !contended() => return lock(),
+            Err(TryLockError::WouldBlock) => {}
+        }
+    }
+}
+
+/// Whether a condvar wait should return right away, per
+/// `TestCfg::spurious_wakeups`.
+pub(crate) fn spurious_wakeup() -> bool {
+    with_current(|ctx| {
+        let p = ctx.run.spurious_wakeups;
+        p > 0.0 && {
+            let mut rng = ctx.rng.get();
+            let wake = rng.chance(p);
+            ctx.rng.set(rng);
+            wake
+        }
+    })
+    .unwrap_or(false)
+}
+
+/// Randomly emits nothing, a compiler fence, or a `SeqCst` fence, per
+/// `TestCfg::fence_chance`, when called from a cobb runner thread (and does
+/// nothing otherwise). Sprinkle it around code under test to see whether a
+/// failure is down to memory ordering: if it goes away when fences are
+/// likely, it probably is, and a fix should keep it away with them
+/// turned off.
+pub fn maybe_fence() {
+    with_current(|ctx| {
+        let mut rng = ctx.rng.get();
+        if rng.chance(ctx.run.fence_chance) {
+            if rng.chance(0.5) {
+                compiler_fence(Ordering::SeqCst);
+            } else {
+                fence(Ordering::SeqCst);
+            }
+        }
+        ctx.rng.set(rng);
+    });
+}
+
+fn map_lock<G, H>(res: LockResult<G>, f: impl FnOnce(G) -> H) -> LockResult<H> {
+    match res {
+        Ok(g) => Ok(f(g)),
+        Err(e) => Err(PoisonError::new(f(e.into_inner()))),
+    }
+}
+
+fn map_try_lock<G, H>(res: TryLockResult<G>, f: impl FnOnce(G) -> H) -> TryLockResult<H> {
+    match res {
+        Ok(g) => Ok(f(g)),
+        Err(TryLockError::Poisoned(e)) => {
+            Err(TryLockError::Poisoned(PoisonError::new(f(e.into_inner()))))
+        }
+        Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+    }
+}
+
+/// A schedule point at `location`, if we're on a runner thread.
+#[inline]
+pub(crate) fn sp_at(location: &'static Location<'static>) {
+    with_current(|ctx| ctx.sp_at(location, 1.0, 1.0, crate::SpHint::None));
+}
@@ -0,0 +1,106 @@
+use super::{acquire, map_lock, map_try_lock, sp_at};
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use std::panic::Location;
+use std::sync::{self, LockResult, TryLockResult};
+
+/// Like `std::sync::Mutex`, but hits a schedule point before locking, and
+/// before unlocking. See the module docs.
+#[derive(Default)]
+pub struct Mutex<T: ?Sized>(sync::Mutex<T>);
+
+impl<T> Mutex<T> {
+    pub const fn new(v: T) -> Self {
+        Self(sync::Mutex::new(v))
+    }
+    pub fn into_inner(self) -> LockResult<T> {
+        self.0.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// For `Condvar`, to lock it again after waiting.
+    pub(super) fn relock(&self) -> LockResult<sync::MutexGuard<'_, T>> {
+        acquire(|| self.0.try_lock(), || self.0.lock())
+    }
+    #[track_caller]
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        let location = Location::caller();
+        sp_at(location);
+        let res = acquire(|| self.0.try_lock(), || self.0.lock());
+        map_lock(res, |g| MutexGuard::new(self, g, location))
+    }
+    #[track_caller]
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        let location = Location::caller();
+        sp_at(location);
+        map_try_lock(self.0.try_lock(), |g| MutexGuard::new(self, g, location))
+    }
+    pub fn is_poisoned(&self) -> bool {
+        self.0.is_poisoned()
+    }
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.0.get_mut()
+    }
+}
+
+impl<T> From<T> for Mutex<T> {
+    fn from(v: T) -> Self {
+        Self::new(v)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+/// The guard for `Mutex`, which hits a schedule point before unlocking it.
+pub struct MutexGuard<'a, T: ?Sized> {
+    pub(super) mutex: &'a Mutex<T>,
+    // Only `None` while `Condvar` has it.
+    pub(super) inner: Option<sync::MutexGuard<'a, T>>,
+    pub(super) location: &'static Location<'static>,
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    pub(super) fn new(
+        mutex: &'a Mutex<T>,
+        inner: sync::MutexGuard<'a, T>,
+        location: &'static Location<'static>,
+    ) -> Self {
+        Self {
+            mutex,
+            inner: Some(inner),
+            location,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.inner.as_deref().unwrap()
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.inner.as_deref_mut().unwrap()
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.inner.is_some() && !std::thread::panicking() {
+            sp_at(self.location);
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
@@ -0,0 +1,133 @@
+use super::{acquire, map_lock, map_try_lock, sp_at};
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use std::panic::Location;
+use std::sync::{self, LockResult, TryLockResult};
+
+/// Like `std::sync::RwLock`, but hits a schedule point before locking, and
+/// before unlocking. See the module docs.
+#[derive(Default)]
+pub struct RwLock<T: ?Sized>(sync::RwLock<T>);
+
+impl<T> RwLock<T> {
+    pub const fn new(v: T) -> Self {
+        Self(sync::RwLock::new(v))
+    }
+    pub fn into_inner(self) -> LockResult<T> {
+        self.0.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    #[track_caller]
+    pub fn read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+        let location = Location::caller();
+        sp_at(location);
+        let res = acquire(|| self.0.try_read(), || self.0.read());
+        map_lock(res, |inner| RwLockReadGuard { inner, location })
+    }
+    #[track_caller]
+    pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+        let location = Location::caller();
+        sp_at(location);
+        let res = acquire(|| self.0.try_write(), || self.0.write());
+        map_lock(res, |inner| RwLockWriteGuard { inner, location })
+    }
+    #[track_caller]
+    pub fn try_read(&self) -> TryLockResult<RwLockReadGuard<'_, T>> {
+        let location = Location::caller();
+        sp_at(location);
+        map_try_lock(self.0.try_read(), |inner| RwLockReadGuard {
+            inner,
+            location,
+        })
+    }
+    #[track_caller]
+    pub fn try_write(&self) -> TryLockResult<RwLockWriteGuard<'_, T>> {
+        let location = Location::caller();
+        sp_at(location);
+        map_try_lock(self.0.try_write(), |inner| RwLockWriteGuard {
+            inner,
+            location,
+        })
+    }
+    pub fn is_poisoned(&self) -> bool {
+        self.0.is_poisoned()
+    }
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.0.get_mut()
+    }
+}
+
+impl<T> From<T> for RwLock<T> {
+    fn from(v: T) -> Self {
+        Self::new(v)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+/// The read guard for `RwLock`, which hits a schedule point before unlocking.
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    inner: sync::RwLockReadGuard<'a, T>,
+    location: &'static Location<'static>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            sp_at(self.location);
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// The write guard for `RwLock`, which hits a schedule point before
+/// unlocking.
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    inner: sync::RwLockWriteGuard<'a, T>,
+    location: &'static Location<'static>,
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            sp_at(self.location);
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
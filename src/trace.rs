@@ -0,0 +1,372 @@
+//! Recording what cobb did in each iteration, so a failing one can be looked
+//! at (and replayed) afterwards. See `TestCfg::trace_dir`.
+//!
+//! Traces are written as text, one thing per line:
+//!
+//! ```text
+//! cobb trace 1
+//! test stack
+//! group 0
+//! iteration 13
+//! seed 0xc200d4a2695c5326
+//! order 2 0 1
+//! priorities hi lo hi
+//! benched
+//! thread 0
+//! sp src/lib.rs:12:5 none yield
+//! enter publish
+//! sp src/lib.rs:14:5 after-publish sleep 2000000
+//! leave publish
+//! thread 1
+//! ...
+//! ```
+use crate::{Priority, SpAction, SpHint};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+/// Something a thread did during an iteration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// It hit a schedule point, at `location` (as in `file:line:column`).
+    Sp {
+        location: String,
+        hint: SpHint,
+        action: SpAction,
+    },
+    /// It entered the `TestCtx::region` with this name.
+    Enter(String),
+    /// It left the `TestCtx::region` with this name.
+    Leave(String),
+}
+
+/// Everything cobb decided about one iteration of a group.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    pub test: String,
+    pub group: usize,
+    pub iteration: usize,
+    /// The iteration's seed, as in `TestCtx::iteration_seed`.
+    pub seed: u64,
+    /// The order the threads were started in.
+    pub order: Vec<usize>,
+    /// Each thread's priority.
+    pub priorities: Vec<Priority>,
+    /// The threads that sat the iteration out, with
+    /// `TestCfg::random_participation`.
+    pub benched: Vec<usize>,
+    /// What each thread did, in order.
+    pub threads: Vec<Vec<TraceEvent>>,
+}
+
+impl Trace {
+    /// Writes the trace to a file named after the test, group and iteration
+    /// in `dir`, creating it if need be, and returns the file's path.
+    pub fn save_in(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!(
+            "{}-group{}-iteration{}.trace",
+            file_name(&self.test),
+            self.group,
+            self.iteration
+        ));
+        std::fs::write(&path, self.to_string())?;
+        Ok(path)
+    }
+
+    /// Reads a trace written by `save_in` (or anything else that formats one
+    /// with `Display`).
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Trace> {
+        use std::io::{Error, ErrorKind};
+        let text = std::fs::read_to_string(path)?;
+        text.parse()
+            .map_err(|e: String| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    /// Checks that the trace hangs together well enough to replay.
+    pub(crate) fn check(&self) -> Result<(), String> {
+        let n = self.threads.len();
+        let mut order = self.order.clone();
+        order.sort_unstable();
+        if n < 2 || order != (0..n).collect::<Vec<_>>() {
+            return Err(format!(
+                "the trace's start order {:?} doesn't fit its {} threads",
+                self.order, n
+            ));
+        }
+        if self.priorities.len() != n {
+            return Err(format!(
+                "the trace has {} priorities for {} threads",
+                self.priorities.len(),
+                n
+            ));
+        }
+        if self.benched.iter().any(|&i| i >= n) || self.benched.len() >= n {
+            return Err(format!(
+                "the trace's benched threads {:?} don't fit",
+                self.benched
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// `test` with anything that might not be allowed in a file name replaced.
+pub(crate) fn file_name(test: &str) -> String {
+    test.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn hint_name(hint: SpHint) -> &'static str {
+    match hint {
+        SpHint::None => "none",
+        SpHint::BeforeCas => "before-cas",
+        SpHint::AfterPublish => "after-publish",
+        SpHint::InRetryLoop => "in-retry-loop",
+        SpHint::Syscall => "syscall",
+    }
+}
+
+impl fmt::Display for SpAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpAction::Nothing => f.write_str("nothing"),
+            SpAction::SleepZero => f.write_str("sleep-zero"),
+            SpAction::Sleep(d) => write!(f, "sleep {}", d.as_nanos()),
+            SpAction::Yield => f.write_str("yield"),
+            SpAction::YieldMany => f.write_str("yield-many"),
+            SpAction::Spin(n) => write!(f, "spin {}", n),
+            SpAction::Busy(n) => write!(f, "busy {}", n),
+            SpAction::Handoff(t) => write!(f, "handoff {}", t),
+            SpAction::Other => f.write_str("other"),
+        }
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |v: &[usize]| v.iter().map(|n| format!(" {}", n)).collect::<String>();
+        writeln!(f, "cobb trace 1")?;
+        writeln!(f, "test {}", self.test)?;
+        writeln!(f, "group {}", self.group)?;
+        writeln!(f, "iteration {}", self.iteration)?;
+        writeln!(f, "seed {:#x}", self.seed)?;
+        writeln!(f, "order{}", list(&self.order))?;
+        write!(f, "priorities")?;
+        for p in &self.priorities {
+            write!(f, " {}", if *p == Priority::Hi { "hi" } else { "lo" })?;
+        }
+        writeln!(f)?;
+        writeln!(f, "benched{}", list(&self.benched))?;
+        for (i, events) in self.threads.iter().enumerate() {
+            writeln!(f, "thread {}", i)?;
+            for e in events {
+                match e {
+                    TraceEvent::Sp {
+                        location,
+                        hint,
+                        action,
+                    } => writeln!(f, "sp {} {} {}", location, hint_name(*hint), action)?,
+                    TraceEvent::Enter(name) => writeln!(f, "enter {}", name)?,
+                    TraceEvent::Leave(name) => writeln!(f, "leave {}", name)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where a group's threads leave their events at the end of each iteration.
+pub(crate) struct Slots(Vec<Mutex<Vec<TraceEvent>>>);
+
+impl Slots {
+    pub(crate) fn new(threads: usize) -> Self {
+        Self((0..threads).map(|_| Mutex::default()).collect())
+    }
+
+    /// Swaps `events` into thread `i`'s slot, leaving `events` empty.
+    pub(crate) fn put(&self, i: usize, events: &mut Vec<TraceEvent>) {
+        let mut slot = self.0[i].lock().unwrap_or_else(PoisonError::into_inner);
+        core::mem::swap(&mut *slot, events);
+        events.clear();
+    }
+
+    /// The events of every thread from the last iteration.
+    pub(crate) fn get(&self) -> Vec<Vec<TraceEvent>> {
+        self.0
+            .iter()
+            .map(|s| s.lock().unwrap_or_else(PoisonError::into_inner).clone())
+            .collect()
+    }
+}
+
+/// Formats a location the way traces have them.
+pub(crate) fn location(l: &std::panic::Location<'_>) -> String {
+    format!("{}:{}:{}", l.file(), l.line(), l.column())
+}
+
+fn parse_hint(s: &str) -> Option<SpHint> {
+    Some(match s {
+        "none" => SpHint::None,
+        "before-cas" => SpHint::BeforeCas,
+        "after-publish" => SpHint::AfterPublish,
+        "in-retry-loop" => SpHint::InRetryLoop,
+        "syscall" => SpHint::Syscall,
+        _ => return None,
+    })
+}
+
+fn parse_action(words: &[&str]) -> Option<SpAction> {
+    let n = || words.get(1)?.parse::<usize>().ok();
+    Some(match words[0] {
+        "nothing" => SpAction::Nothing,
+        "sleep-zero" => SpAction::SleepZero,
+        "sleep" => SpAction::Sleep(std::time::Duration::from_nanos(words.get(1)?.parse().ok()?)),
+        "yield" => SpAction::Yield,
+        "yield-many" => SpAction::YieldMany,
+        "spin" => SpAction::Spin(n()?),
+        "busy" => SpAction::Busy(n()?),
+        "handoff" => SpAction::Handoff(n()?),
+        "other" => SpAction::Other,
+        _ => return None,
+    })
+}
+
+/// Parses the rest of an `sp` line: the location (which might have spaces in
+/// it), the hint, and the action (which might have a number after it).
+fn parse_sp(rest: &str) -> Option<TraceEvent> {
+    let words = rest.split(' ').collect::<Vec<_>>();
+    let has_arg = words.last()?.bytes().all(|b| b.is_ascii_digit());
+    let action_at = words.len().checked_sub(if has_arg { 2 } else { 1 })?;
+    let hint_at = action_at.checked_sub(1).filter(|&i| i > 0)?;
+    Some(TraceEvent::Sp {
+        location: words[..hint_at].join(" "),
+        hint: parse_hint(words[hint_at])?,
+        action: parse_action(&words[action_at..])?,
+    })
+}
+
+impl std::str::FromStr for Trace {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut lines = s.lines().enumerate();
+        match lines.next() {
+            Some((_, "cobb trace 1")) => {}
+            _ => return Err("not a cobb trace".into()),
+        }
+        let mut t = Trace::default();
+        for (i, line) in lines {
+            let err = || format!("line {}: can't parse {:?}", i + 1, line);
+            let (key, rest) = match line.find(' ') {
+                Some(at) => (&line[..at], &line[at + 1..]),
+                None => (line, ""),
+            };
+            let nums = || {
+                rest.split_whitespace()
+                    .map(|n| n.parse::<usize>())
+                    .collect::<Result<Vec<_>, _>>()
+            };
+            match key {
+                "test" => t.test = rest.to_string(),
+                "group" => t.group = rest.parse().map_err(|_| err())?,
+                "iteration" => t.iteration = rest.parse().map_err(|_| err())?,
+                "seed" => t.seed = crate::config::parse_seed(rest).map_err(|_| err())?,
+                "order" => t.order = nums().map_err(|_| err())?,
+                "benched" => t.benched = nums().map_err(|_| err())?,
+                "priorities" => {
+                    t.priorities = rest
+                        .split_whitespace()
+                        .map(|p| match p {
+                            "hi" => Ok(Priority::Hi),
+                            "lo" => Ok(Priority::Lo),
+                            _ => Err(err()),
+                        })
+                        .collect::<Result<_, _>>()?
+                }
+                "thread" => t.threads.push(vec![]),
+                "sp" | "enter" | "leave" => {
+                    let event = match key {
+                        "sp" => parse_sp(rest).ok_or_else(err)?,
+                        "enter" => TraceEvent::Enter(rest.to_string()),
+                        _ => TraceEvent::Leave(rest.to_string()),
+                    };
+                    t.threads.last_mut().ok_or_else(err)?.push(event);
+                }
+                "" => {}
+                _ => return Err(err()),
+            }
+        }
+        Ok(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn sp(location: &str, hint: SpHint, action: SpAction) -> TraceEvent {
+        TraceEvent::Sp {
+            location: location.to_string(),
+            hint,
+            action,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let trace = Trace {
+            test: "stack push pop".into(),
+            group: 3,
+            iteration: 17,
+            seed: 0xc200_d4a2_695c_5326,
+            order: vec![2, 0, 1],
+            priorities: vec![Priority::Hi, Priority::Lo, Priority::Hi],
+            benched: vec![],
+            threads: vec![
+                vec![
+                    sp("src/lib.rs:12:5", SpHint::None, SpAction::Nothing),
+                    sp("src/lib.rs:13:5", SpHint::BeforeCas, SpAction::SleepZero),
+                    TraceEvent::Enter("publish".into()),
+                    sp(
+                        "src/lib.rs:14:5",
+                        SpHint::AfterPublish,
+                        SpAction::Sleep(Duration::from_nanos(2_000_000)),
+                    ),
+                    TraceEvent::Leave("publish".into()),
+                ],
+                vec![
+                    sp("src/lib.rs:20:9", SpHint::InRetryLoop, SpAction::Yield),
+                    sp("src/lib.rs:21:9", SpHint::Syscall, SpAction::YieldMany),
+                    sp("src/lib.rs:22:9", SpHint::None, SpAction::Spin(40)),
+                    TraceEvent::Enter("retry loop".into()),
+                    sp("src/lib.rs:23:9", SpHint::None, SpAction::Busy(126)),
+                    TraceEvent::Leave("retry loop".into()),
+                ],
+                vec![
+                    sp(
+                        "/my code/src 2/lib.rs:30:1",
+                        SpHint::None,
+                        SpAction::Handoff(1),
+                    ),
+                    sp(
+                        "/my code/src 2/lib.rs:31:1",
+                        SpHint::Syscall,
+                        SpAction::Other,
+                    ),
+                ],
+            ],
+        };
+        assert_eq!(trace.to_string().parse::<Trace>().unwrap(), trace);
+
+        let benched = Trace {
+            benched: vec![0, 2],
+            ..trace
+        };
+        assert_eq!(benched.to_string().parse::<Trace>().unwrap(), benched);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::CobbError;
+use std::collections::HashMap;
+use std::panic::Location;
+use std::sync::Mutex;
+
+/// Checks an invariant without panicking. If `cond` is false, this records a
+/// violation with `report_violation` (on the `TestCtx` or `IterCtx` passed as
+/// the first argument) and carries on, and the test fails at the end with all
+/// of the violations that were recorded.
+///
+/// ```
+/// # use std::sync::atomic::{AtomicUsize, Ordering};
+/// cobb::run_test(cobb::TestCfg::<AtomicUsize> {
+///     setup: || AtomicUsize::new(0),
+///     test: |n, ctx| {
+///         let old = n.fetch_add(1, Ordering::Relaxed);
+///         cobb::soft_assert!(ctx, old < 1_000_000, "counter got too big: {}", old);
+///     },
+///     ..Default::default()
+/// });
+/// ```
+#[macro_export]
+macro_rules! soft_assert {
+    ($ctx:expr, $cond:expr $(,)?) => {
+        if !$cond {
+            $ctx.report_violation(concat!("assertion failed: ", stringify!($cond)));
+        }
+    };
+    ($ctx:expr, $cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            $ctx.report_violation(format!($($arg)+));
+        }
+    };
+}
+
+/// An invariant violation, recorded by `soft_assert!` or `report_violation`.
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub group: usize,
+    /// The thread that reported it, or `None` for `after_each`.
+    pub thread: Option<usize>,
+    pub iteration: usize,
+    pub message: String,
+    pub location: &'static Location<'static>,
+    /// The `TestCtx::region`s the group's threads were inside when it was
+    /// reported, as `(thread, region)` pairs, innermost last.
+    pub regions: Vec<(usize, &'static str)>,
+}
+
+impl core::fmt::Display for Violation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "group {} ", self.group)?;
+        match self.thread {
+            Some(t) => write!(f, "thread {}", t)?,
+            None => f.write_str("after_each")?,
+        }
+        write!(
+            f,
+            " iteration {} at {}: {}",
+            self.iteration, self.location, self.message
+        )?;
+        for (i, (t, r)) in self.regions.iter().enumerate() {
+            let sep = if i == 0 { " (" } else { ", " };
+            write!(f, "{}thread {} was inside '{}'", sep, t, r)?;
+        }
+        if !self.regions.is_empty() {
+            f.write_str(")")?;
+        }
+        Ok(())
+    }
+}
+
+/// Only this many violations are kept, so that a race that bites every
+/// iteration doesn't eat all the memory. The rest are just counted.
+const MAX_KEPT: usize = 1000;
+
+#[derive(Default)]
+pub(crate) struct Violations {
+    inner: Mutex<Recorded>,
+}
+
+#[derive(Default)]
+struct Recorded {
+    count: usize,
+    /// The first `MAX_KEPT`.
+    kept: Vec<Violation>,
+    /// The first of each different one (up to `MAX_KEPT` of them), and how
+    /// many there have been.
+    distinct: Vec<(usize, Violation)>,
+    /// Where each is in `distinct`, by where it was reported and its
+    /// normalized message.
+    index: HashMap<(&'static Location<'static>, String), usize>,
+}
+
+impl Violations {
+    pub(crate) fn record(&self, v: Violation) {
+        let mut g = self
+            .inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let g = &mut *g;
+        g.count += 1;
+        let key = (v.location, crate::error::normalize(&v.message));
+        match g.index.get(&key) {
+            Some(&i) => g.distinct[i].0 += 1,
+            None if g.distinct.len() < MAX_KEPT => {
+                g.index.insert(key, g.distinct.len());
+                g.distinct.push((1, v.clone()));
+            }
+            None => {}
+        }
+        if g.kept.len() < MAX_KEPT {
+            g.kept.push(v);
+        }
+    }
+
+    /// How many have been recorded.
+    pub(crate) fn count(&self) -> usize {
+        self.inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .count
+    }
+
+    /// The violations as an error, if there were any.
+    pub(crate) fn to_error(&self) -> Option<CobbError> {
+        let g = self
+            .inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        (g.count != 0).then(|| {
+            let mut distinct = g.distinct.clone();
+            // Stable, so ties stay in the order they were first seen.
+            distinct.sort_by_key(|d| std::cmp::Reverse(d.0));
+            CobbError::Violations {
+                count: g.count,
+                violations: g.kept.clone(),
+                distinct,
+            }
+        })
+    }
+}
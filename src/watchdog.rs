@@ -0,0 +1,121 @@
+use std::panic::Location;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// What a runner thread was last seen doing, for the watchdog's report.
+#[derive(Default)]
+pub(crate) struct ThreadStatus {
+    /// How many iterations the thread has finished.
+    done: AtomicUsize,
+    in_test: AtomicBool,
+    last_sp: AtomicPtr<Location<'static>>,
+    /// The `TestCtx::region`s the thread is inside, innermost last.
+    regions: Mutex<Vec<&'static str>>,
+}
+
+impl ThreadStatus {
+    pub(crate) fn start_iteration(&self) {
+        self.in_test.store(true, Ordering::Relaxed);
+        self.regions().clear();
+    }
+    pub(crate) fn in_test(&self) -> bool {
+        self.in_test.load(Ordering::Relaxed)
+    }
+    pub(crate) fn finish_iteration(&self, iteration: usize) {
+        self.in_test.store(false, Ordering::Relaxed);
+        self.done.store(iteration + 1, Ordering::Relaxed);
+    }
+    pub(crate) fn reached_sp(&self, loc: &'static Location<'static>) {
+        self.last_sp.store(
+            loc as *const Location<'_> as *mut Location<'_>,
+            Ordering::Relaxed,
+        );
+    }
+    pub(crate) fn regions(&self) -> std::sync::MutexGuard<'_, Vec<&'static str>> {
+        self.regions.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    fn describe(&self) -> String {
+        let last_sp = self.last_sp.load(Ordering::Relaxed);
+        let regions = self.regions();
+        format!(
+            "finished {} iterations, {}{}, last schedule point {}",
+            self.done.load(Ordering::Relaxed),
+            if self.in_test() {
+                "running the test"
+            } else {
+                "waiting for the next iteration"
+            },
+            if regions.is_empty() {
+                String::new()
+            } else {
+                format!(" inside {}", crate::describe_regions(&regions))
+            },
+            // Safety: it only ever holds null or a `&'static Location`.
+            match unsafe { last_sp.as_ref() } {
+                Some(loc) => loc.to_string(),
+                None => "(none yet)".to_string(),
+            }
+        )
+    }
+}
+
+/// Aborts the process with a report of what each runner thread is up to if
+/// `progress` doesn't change for `timeout`. Stops watching when dropped.
+pub(crate) struct Watchdog {
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    pub(crate) fn spawn(
+        label: String,
+        timeout: Duration,
+        progress: Arc<AtomicUsize>,
+        threads: Vec<(String, Arc<ThreadStatus>)>,
+    ) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let done2 = Arc::clone(&done);
+        let thread = thread::Builder::new()
+            .name(format!("{} watchdog", label))
+            .spawn(move || {
+                let mut last = progress.load(Ordering::Relaxed);
+                let mut since = Instant::now();
+                while !done2.load(Ordering::Acquire) {
+                    thread::park_timeout(timeout.min(Duration::from_millis(100)));
+                    let now = progress.load(Ordering::Relaxed);
+                    if now != last {
+                        last = now;
+                        since = Instant::now();
+                    } else if since.elapsed() >= timeout && !done2.load(Ordering::Acquire) {
+                        eprintln!(
+                            "{}: no iteration finished in {:?} (after {} iterations), aborting.",
+                            label, timeout, now
+                        );
+                        // There's no portable way to get another thread's
+                        // backtrace, so this is the best we can do.
+                        for (name, status) in &threads {
+                            eprintln!("  {}: {}", name, status.describe());
+                        }
+                        std::process::abort();
+                    }
+                }
+            })
+            .unwrap_or_else(|e| panic!("Cobb: failed to launch watchdog: {:?}", e));
+        Self {
+            done,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Release);
+        if let Some(t) = self.thread.take() {
+            t.thread().unpark();
+            let _ = t.join();
+        }
+    }
+}